@@ -1,6 +1,8 @@
 pub mod middlewares;
 mod utils;
 
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -19,6 +21,21 @@ pub struct User {
     pub password_hash: Option<String>,
 
     pub created_at: DateTime<Utc>,
+
+    // only set for api tokens, sessions are allowed to do everything
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<TokenScope>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum TokenScope {
+    #[serde(rename = "read:messages")]
+    ReadMessages,
+    #[serde(rename = "write:messages")]
+    WriteMessages,
+    #[serde(rename = "read:chats")]
+    ReadChats,
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
@@ -26,6 +43,7 @@ pub struct Workspace {
     pub id: i64,
     pub name: String,
     pub owner_id: i64,
+    pub require_2fa: bool,
     pub created_at: DateTime<Utc>,
 }
 
@@ -34,6 +52,7 @@ pub struct ChatUser {
     pub id: i64,
     pub fullname: String,
     pub email: String,
+    pub is_bot: bool,
     pub created_at: DateTime<Utc>,
 }
 
@@ -62,6 +81,7 @@ pub struct Chat {
     pub id: i64,
     pub workspace_id: i64,
     pub name: Option<String>,
+    pub topic: Option<String>,
     pub r#type: ChatType,
     pub members: Vec<i64>,
     pub created_at: DateTime<Utc>,
@@ -76,6 +96,34 @@ impl User {
             email: email.to_string(),
             password_hash: None,
             created_at: Utc::now(),
+            scopes: None,
+        }
+    }
+
+    pub fn has_scope(&self, scope: TokenScope) -> bool {
+        self.scopes.as_ref().is_none_or(|s| s.contains(&scope))
+    }
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::ReadMessages => "read:messages",
+            TokenScope::WriteMessages => "write:messages",
+            TokenScope::ReadChats => "read:chats",
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read:messages" => Ok(TokenScope::ReadMessages),
+            "write:messages" => Ok(TokenScope::WriteMessages),
+            "read:chats" => Ok(TokenScope::ReadChats),
+            _ => Err(format!("invalid token scope: {}", s)),
         }
     }
 }
@@ -95,7 +143,111 @@ pub struct Message {
     pub id: i64,
     pub chat_id: i64,
     pub sender_id: i64,
+    // overrides the sender's fullname, set by incoming webhooks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_name: Option<String>,
     pub content: String,
+    #[serde(default)]
+    pub format: MessageFormat,
+    /// content rendered server side, safe to insert into a page
+    #[serde(default)]
+    pub html: String,
+    /// urls of all files, kept for clients that predate attachments
     pub files: Vec<String>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// the key the sender sent it with, to reconcile optimistic updates
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_msg_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An uploaded file with its metadata, width and height are set for images
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Attachment {
+    pub id: i64,
+    pub workspace_id: i64,
+    pub uploader_id: i64,
+    pub name: String,
+    pub size: i64,
+    pub mime: String,
+    pub hash: String,
+    pub url: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// placeholder shown while an image loads, set once its previews are generated
+    pub blurhash: Option<String>,
+    #[serde(default)]
+    pub scan_status: ScanStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Result of the malware scan of an upload, infected files are quarantined
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "file_scan_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ScanStatus {
+    /// uploaded without a scanner configured
+    #[default]
+    Unscanned,
+    Clean,
+    Infected,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "message_format", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    #[default]
+    Plain,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "mention_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MentionKind {
+    /// @user
+    User,
+    /// @here, stored for every member but only members online when it's sent
+    /// are notified
+    Here,
+    /// @channel and @group, every member
+    Channel,
+    Group,
+}
+
+/// A user mentioned by a message, broadcast mentions are stored once per member
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Mention {
+    pub id: i64,
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub sender_id: i64,
+    pub kind: MentionKind,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A message pinned to its chat by a member
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Pin {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub pinned_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A link or a file shown in the header of a chat, exactly one of them is set
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Bookmark {
+    pub id: i64,
+    pub chat_id: i64,
+    pub title: String,
+    pub link: Option<String>,
+    pub file_url: Option<String>,
+    pub created_by: i64,
     pub created_at: DateTime<Utc>,
 }
@@ -1,18 +1,29 @@
 mod auth;
+mod rate_limit;
 mod request_id;
 mod server_time;
+mod trace;
 
 use core::fmt;
+use std::future::Future;
 
 pub use auth::verify_token;
+pub use rate_limit::{
+    rate_limit, LockoutConfig, LockoutStore, MemoryLockouts, RateLimitConfig, RateLimiter,
+};
 
-use axum::{middleware::from_fn, Router};
+use axum::{
+    http::{header::AUTHORIZATION, HeaderName},
+    middleware::from_fn,
+    Router,
+};
 use request_id::set_request_id;
 use server_time::ServerTimeLayer;
 use tower::ServiceBuilder;
 use tower_http::{
     compression::CompressionLayer,
-    trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
+    sensitive_headers::SetSensitiveRequestHeadersLayer,
+    trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer},
 };
 use tracing::Level;
 
@@ -20,15 +31,17 @@ use crate::User;
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> impl Future<Output = Result<User, Self::Error>> + Send;
 }
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
 const SERVER_TIME_HEADER: &str = "x-server-time";
+/// Authenticates posts to incoming webhooks
+pub const WEBHOOK_SECRET_HEADER: &str = "x-webhook-secret";
 
 pub fn set_layer(app: Router) -> Router {
     let trace_layer = TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().include_headers(true))
+        .make_span_with(trace::make_span)
         .on_request(DefaultOnRequest::new().level(Level::INFO))
         .on_response(
             DefaultOnResponse::new()
@@ -40,6 +53,10 @@ pub fn set_layer(app: Router) -> Router {
 
     app.layer(
         ServiceBuilder::new()
+            .layer(SetSensitiveRequestHeadersLayer::new([
+                AUTHORIZATION,
+                HeaderName::from_static(WEBHOOK_SECRET_HEADER),
+            ]))
             .layer(trace_layer)
             .layer(compression_layer)
             .layer(from_fn(set_request_id))
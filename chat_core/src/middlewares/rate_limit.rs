@@ -0,0 +1,601 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{DecodingKey, User};
+
+// bodies of rate limited routes are small json documents
+const MAX_BODY_SIZE: usize = 64 * 1024;
+// prune expired entries once the tables grow beyond this
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// requests allowed per window, for each client ip, email and user id
+    pub max_requests: u32,
+    pub window_secs: u64,
+    #[serde(default)]
+    pub lockout: Option<LockoutConfig>,
+}
+
+/// Lock an account after repeated failures (401 / 403) from the same client
+/// ip, doubling the duration each time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockoutConfig {
+    pub max_failures: u32,
+    pub base_secs: u64,
+    pub max_secs: u64,
+}
+
+impl LockoutConfig {
+    /// How long to lock after `level` earlier lockouts
+    pub fn lock_secs(&self, level: u32) -> u64 {
+        self.base_secs
+            .saturating_mul(1 << level.min(16))
+            .min(self.max_secs)
+    }
+}
+
+/// Where failures and lockouts are kept, shared by all servers in production
+#[async_trait]
+pub trait LockoutStore: Send + Sync + 'static {
+    /// How long the key is still locked, if it is
+    async fn locked_for(&self, key: &str) -> anyhow::Result<Option<Duration>>;
+    /// Count a failure, the key is locked once there are `max_failures` of them
+    async fn record_failure(&self, key: &str, config: &LockoutConfig) -> anyhow::Result<()>;
+    async fn record_success(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Lockouts of a single process, for tests and limiters without a database
+#[derive(Debug, Default)]
+pub struct MemoryLockouts(Mutex<HashMap<String, Lockout>>);
+
+#[derive(Clone)]
+pub struct RateLimiter(Arc<RateLimiterInner>);
+
+struct RateLimiterInner {
+    config: RateLimitConfig,
+    trust_forwarded_for: bool,
+    // verifies the challenge tokens of 2fa requests, to lock their account
+    challenge_key: Option<DecodingKey>,
+    windows: Mutex<HashMap<String, Window>>,
+    lockouts: Arc<dyn LockoutStore>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+struct Lockout {
+    failures: u32,
+    level: u32,
+    until: Option<Instant>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmailBody {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChallengeBody {
+    challenge_token: String,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, trust_forwarded_for: bool) -> Self {
+        Self::new_with(
+            config,
+            trust_forwarded_for,
+            None,
+            Arc::new(MemoryLockouts::default()),
+        )
+    }
+
+    /// Keep lockouts in the store, and also lock the accounts of 2fa requests,
+    /// found by their challenge token
+    pub fn with_challenge_key(
+        config: RateLimitConfig,
+        trust_forwarded_for: bool,
+        challenge_key: DecodingKey,
+        lockouts: Arc<dyn LockoutStore>,
+    ) -> Self {
+        Self::new_with(config, trust_forwarded_for, Some(challenge_key), lockouts)
+    }
+
+    fn new_with(
+        config: RateLimitConfig,
+        trust_forwarded_for: bool,
+        challenge_key: Option<DecodingKey>,
+        lockouts: Arc<dyn LockoutStore>,
+    ) -> Self {
+        Self(Arc::new(RateLimiterInner {
+            config,
+            trust_forwarded_for,
+            challenge_key,
+            windows: Mutex::new(HashMap::new()),
+            lockouts,
+        }))
+    }
+
+    /// Count a request for an arbitrary key, e.g. a webhook id
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.hit(key, Instant::now())
+    }
+
+    /// Count a request for the key, returns how long to wait if it's over the limit
+    fn hit(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let window = Duration::from_secs(self.0.config.window_secs);
+        let mut windows = self.0.windows.lock().unwrap();
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, w| now.duration_since(w.start) < window);
+        }
+
+        let entry = windows.entry(key.to_string()).or_insert(Window {
+            start: now,
+            count: 0,
+        });
+        if now.duration_since(entry.start) >= window {
+            entry.start = now;
+            entry.count = 0;
+        }
+        if entry.count >= self.0.config.max_requests {
+            return Err(window - now.duration_since(entry.start));
+        }
+        entry.count += 1;
+        Ok(())
+    }
+
+    // the database being unavailable fails the signin anyway, so store errors
+    // don't lock anyone out
+    async fn check_lockout(&self, key: &str) -> Result<(), Duration> {
+        match self.0.lockouts.locked_for(key).await {
+            Ok(Some(retry_after)) => Err(retry_after),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                warn!("check lockout of {} error: {:?}", key, e);
+                Ok(())
+            }
+        }
+    }
+
+    async fn record_failure(&self, key: &str) {
+        let Some(config) = &self.0.config.lockout else {
+            return;
+        };
+        if let Err(e) = self.0.lockouts.record_failure(key, config).await {
+            warn!("record failure of {} error: {:?}", key, e);
+        }
+    }
+
+    async fn record_success(&self, key: &str) {
+        if let Err(e) = self.0.lockouts.record_success(key).await {
+            warn!("reset lockout of {} error: {:?}", key, e);
+        }
+    }
+
+    fn challenge_user(&self, body: &[u8]) -> Option<i64> {
+        let key = self.0.challenge_key.as_ref()?;
+        let body = serde_json::from_slice::<ChallengeBody>(body).ok()?;
+        key.verify_challenge(&body.challenge_token)
+            .ok()
+            .map(|user| user.id)
+    }
+
+    fn client_ip(&self, req: &Request) -> Option<String> {
+        if self.0.trust_forwarded_for {
+            let forwarded = req
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_string());
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    }
+}
+
+impl MemoryLockouts {
+    fn locked_for_at(&self, key: &str, now: Instant) -> Option<Duration> {
+        let lockouts = self.0.lock().unwrap();
+        match lockouts.get(key).and_then(|l| l.until) {
+            Some(until) if until > now => Some(until - now),
+            _ => None,
+        }
+    }
+
+    fn record_failure_at(&self, key: &str, config: &LockoutConfig, now: Instant) {
+        let mut lockouts = self.0.lock().unwrap();
+        if lockouts.len() > PRUNE_THRESHOLD {
+            let max = Duration::from_secs(config.max_secs);
+            lockouts.retain(|_, l| l.until.is_some_and(|until| until + max > now));
+        }
+
+        let lockout = lockouts.entry(key.to_string()).or_default();
+        lockout.failures += 1;
+        if lockout.failures >= config.max_failures {
+            let secs = config.lock_secs(lockout.level);
+            warn!(
+                "lock {} for {}s after {} failures",
+                key, secs, lockout.failures
+            );
+            lockout.until = Some(now + Duration::from_secs(secs));
+            lockout.failures = 0;
+            lockout.level += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl LockoutStore for MemoryLockouts {
+    async fn locked_for(&self, key: &str) -> anyhow::Result<Option<Duration>> {
+        Ok(self.locked_for_at(key, Instant::now()))
+    }
+
+    async fn record_failure(&self, key: &str, config: &LockoutConfig) -> anyhow::Result<()> {
+        self.record_failure_at(key, config, Instant::now());
+        Ok(())
+    }
+
+    async fn record_success(&self, key: &str) -> anyhow::Result<()> {
+        self.0.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Throttle requests by client ip, the `email` field of a json body and the
+/// authenticated user, and lock accounts out after repeated failures from the
+/// same client ip.
+pub async fn rate_limit(State(limiter): State<RateLimiter>, req: Request, next: Next) -> Response {
+    let now = Instant::now();
+    let mut keys = vec![];
+    let ip = limiter.client_ip(&req);
+    if let Some(ip) = &ip {
+        keys.push(format!("ip:{}", ip));
+    }
+    if let Some(user) = req.extensions().get::<User>() {
+        keys.push(format!("user:{}", user.id));
+    }
+
+    let (parts, body) = req.into_parts();
+    let bytes = match to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let msg = format!("read body error: {:?}", e);
+            warn!(msg);
+            return (StatusCode::PAYLOAD_TOO_LARGE, msg).into_response();
+        }
+    };
+    // account lockout follows the email, the user of a 2fa challenge, or the
+    // user id for authenticated routes
+    let account = match serde_json::from_slice::<EmailBody>(&bytes) {
+        Ok(v) => Some(format!("email:{}", v.email.trim().to_lowercase())),
+        Err(_) => limiter
+            .challenge_user(&bytes)
+            .map(|id| format!("user:{}", id))
+            .or_else(|| keys.iter().find(|k| k.starts_with("user:")).cloned()),
+    };
+    if let Some(account) = &account {
+        if !keys.contains(account) {
+            keys.push(account.clone());
+        }
+    }
+    // failures of other clients don't lock the account for this one
+    let lockout = account.map(|account| match &ip {
+        Some(ip) => format!("{}|ip:{}", account, ip),
+        None => account,
+    });
+    if let Some(lockout) = &lockout {
+        if let Err(retry_after) = limiter.check_lockout(lockout).await {
+            return too_many_requests(retry_after, "account is temporarily locked");
+        }
+    }
+
+    for key in &keys {
+        if let Err(retry_after) = limiter.hit(key, now) {
+            return too_many_requests(retry_after, "too many requests");
+        }
+    }
+
+    let res = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+
+    if let Some(lockout) = &lockout {
+        match res.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                limiter.record_failure(lockout).await
+            }
+            s if s.is_success() => limiter.record_success(lockout).await,
+            _ => {}
+        }
+    }
+    res
+}
+
+fn too_many_requests(retry_after: Duration, msg: &str) -> Response {
+    // round up, so clients never retry too early
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, secs.to_string())],
+        msg.to_string(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::EncodingKey;
+    use anyhow::Result;
+    use axum::{middleware::from_fn_with_state, routing::post, Json, Router};
+    use tower::ServiceExt;
+
+    fn config(lockout: Option<LockoutConfig>) -> RateLimitConfig {
+        RateLimitConfig {
+            max_requests: 3,
+            window_secs: 60,
+            lockout,
+        }
+    }
+
+    #[test]
+    fn rate_limiter_window_should_work() {
+        let limiter = RateLimiter::new(config(None), false);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.hit("ip:1", now).is_ok());
+        }
+        let later = now + Duration::from_secs(20);
+        assert_eq!(limiter.hit("ip:1", later), Err(Duration::from_secs(40)));
+        // other keys are not affected
+        assert!(limiter.hit("ip:2", later).is_ok());
+        // a new window starts
+        assert!(limiter.hit("ip:1", now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn rate_limiter_lockout_should_be_progressive() {
+        let lockout = LockoutConfig {
+            max_failures: 2,
+            base_secs: 60,
+            max_secs: 150,
+        };
+        let lockouts = MemoryLockouts::default();
+        let now = Instant::now();
+        let key = "email:wiki@acme.org|ip:10.0.0.1";
+
+        lockouts.record_failure_at(key, &lockout, now);
+        assert_eq!(lockouts.locked_for_at(key, now), None);
+        lockouts.record_failure_at(key, &lockout, now);
+        assert_eq!(
+            lockouts.locked_for_at(key, now),
+            Some(Duration::from_secs(60))
+        );
+
+        // second lockout doubles, third is capped by max_secs
+        let now = now + Duration::from_secs(60);
+        assert_eq!(lockouts.locked_for_at(key, now), None);
+        lockouts.record_failure_at(key, &lockout, now);
+        lockouts.record_failure_at(key, &lockout, now);
+        assert_eq!(
+            lockouts.locked_for_at(key, now),
+            Some(Duration::from_secs(120))
+        );
+
+        let now = now + Duration::from_secs(120);
+        lockouts.record_failure_at(key, &lockout, now);
+        lockouts.record_failure_at(key, &lockout, now);
+        assert_eq!(
+            lockouts.locked_for_at(key, now),
+            Some(Duration::from_secs(150))
+        );
+
+        // success resets everything
+        lockouts.0.lock().unwrap().remove(key);
+        assert_eq!(lockouts.locked_for_at(key, now), None);
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Signin {
+        password: String,
+    }
+
+    async fn handler(Json(input): Json<Signin>) -> impl IntoResponse {
+        if input.password == "123456" {
+            StatusCode::OK
+        } else {
+            StatusCode::FORBIDDEN
+        }
+    }
+
+    fn signin(email: &str, password: &str) -> Result<Request> {
+        signin_from("10.0.0.1", email, password)
+    }
+
+    fn signin_from(ip: &str, email: &str, password: &str) -> Result<Request> {
+        let body = format!(r#"{{"email": "{}", "password": "{}"}}"#, email, password);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "application/json")
+            .header("x-forwarded-for", ip)
+            .body(Body::from(body))?;
+        Ok(req)
+    }
+
+    #[tokio::test]
+    async fn rate_limit_middleware_should_work() -> Result<()> {
+        let lockout = LockoutConfig {
+            max_failures: 2,
+            base_secs: 60,
+            max_secs: 3600,
+        };
+        let limiter = RateLimiter::new(config(Some(lockout)), true);
+        let app = Router::new()
+            .route("/", post(handler))
+            .layer(from_fn_with_state(limiter, rate_limit));
+
+        let res = app
+            .clone()
+            .oneshot(signin("wiki@acme.org", "123456")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // two failures lock the account
+        for _ in 0..2 {
+            let res = app.clone().oneshot(signin("wiki@acme.org", "bad")?).await?;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+
+        // the ip limit is exhausted as well, but lockout is checked first
+        let res = app
+            .clone()
+            .oneshot(signin("Wiki@acme.org", "123456")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[RETRY_AFTER], "60");
+
+        // other accounts from the same ip hit the ip limit
+        let res = app
+            .clone()
+            .oneshot(signin("tyr@acme.org", "123456")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key(RETRY_AFTER));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limit_lockout_should_not_affect_other_ips() -> Result<()> {
+        let lockout = LockoutConfig {
+            max_failures: 2,
+            base_secs: 60,
+            max_secs: 3600,
+        };
+        let config = RateLimitConfig {
+            max_requests: 100,
+            ..config(Some(lockout))
+        };
+        let limiter = RateLimiter::new(config, true);
+        let app = Router::new()
+            .route("/", post(handler))
+            .layer(from_fn_with_state(limiter, rate_limit));
+
+        for _ in 0..2 {
+            let res = app
+                .clone()
+                .oneshot(signin_from("10.0.0.1", "wiki@acme.org", "bad")?)
+                .await?;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+        let res = app
+            .clone()
+            .oneshot(signin_from("10.0.0.1", "wiki@acme.org", "123456")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // the owner of the account can still sign in from their own ip
+        let res = app
+            .clone()
+            .oneshot(signin_from("10.0.0.2", "wiki@acme.org", "123456")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct TwoFactorSignin {
+        code: String,
+    }
+
+    async fn two_factor_handler(Json(input): Json<TwoFactorSignin>) -> impl IntoResponse {
+        if input.code == "123456" {
+            StatusCode::OK
+        } else {
+            StatusCode::FORBIDDEN
+        }
+    }
+
+    fn signin_2fa(challenge_token: &str, code: &str) -> Result<Request> {
+        let body = format!(
+            r#"{{"challenge_token": "{}", "code": "{}"}}"#,
+            challenge_token, code
+        );
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "application/json")
+            .body(Body::from(body))?;
+        Ok(req)
+    }
+
+    #[tokio::test]
+    async fn rate_limit_should_lock_accounts_on_bad_2fa_codes() -> Result<()> {
+        let ek = EncodingKey::load(include_str!("../../fixtures/encoding.pem"))?;
+        let dk = DecodingKey::load(include_str!("../../fixtures/decoding.pem"))?;
+        let lockout = LockoutConfig {
+            max_failures: 3,
+            base_secs: 60,
+            max_secs: 3600,
+        };
+        let config = RateLimitConfig {
+            max_requests: 100,
+            ..config(Some(lockout))
+        };
+        let lockouts = Arc::new(MemoryLockouts::default());
+        let limiter = RateLimiter::with_challenge_key(config, false, dk, lockouts);
+        let app = Router::new()
+            .route("/", post(two_factor_handler))
+            .layer(from_fn_with_state(limiter, rate_limit));
+
+        let user = User::new(1, 1, "wiki", "wiki@acme.org");
+        for _ in 0..3 {
+            // a new challenge for every attempt doesn't help
+            let challenge = ek.sign_challenge(user.clone())?;
+            let res = app
+                .clone()
+                .oneshot(signin_2fa(&challenge, "000000")?)
+                .await?;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+        let challenge = ek.sign_challenge(user)?;
+        let res = app
+            .clone()
+            .oneshot(signin_2fa(&challenge, "123456")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // other users are not affected
+        let other = ek.sign_challenge(User::new(2, 1, "tyr", "tyr@acme.org"))?;
+        let res = app.clone().oneshot(signin_2fa(&other, "123456")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::borrow::Cow;
+
+use axum::{extract::Request, http::Uri};
+use tracing::Span;
+
+// paths with a secret after the id, incoming webhooks used to be posted to
+// /api/hooks/:id/:secret
+const SECRET_PATHS: &[&str] = &["/api/hooks/"];
+const REDACTED: &str = "[redacted]";
+
+/// Same span as `DefaultMakeSpan`, without the secrets in the uri. Sensitive
+/// headers are hidden by `SetSensitiveRequestHeadersLayer`
+pub(crate) fn make_span(req: &Request) -> Span {
+    tracing::debug_span!(
+        "request",
+        method = %req.method(),
+        uri = %redact_uri(req.uri()),
+        version = ?req.version(),
+        headers = ?req.headers(),
+    )
+}
+
+fn redact_uri(uri: &Uri) -> Cow<'_, str> {
+    let path = uri.path();
+    for prefix in SECRET_PATHS {
+        let Some(rest) = path.strip_prefix(prefix) else {
+            continue;
+        };
+        if let Some((id, _)) = rest.split_once('/') {
+            return Cow::Owned(format!("{}{}/{}", prefix, id, REDACTED));
+        }
+    }
+    Cow::Borrowed(uri.path_and_query().map_or(path, |p| p.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_uri_should_hide_webhook_secrets() {
+        let uri: Uri = "/api/hooks/1/s3cr3t".parse().unwrap();
+        assert_eq!(redact_uri(&uri), "/api/hooks/1/[redacted]");
+        let uri: Uri = "/api/hooks/1".parse().unwrap();
+        assert_eq!(redact_uri(&uri), "/api/hooks/1");
+        let uri: Uri = "/api/chats/1?limit=5".parse().unwrap();
+        assert_eq!(redact_uri(&uri), "/api/chats/1?limit=5");
+    }
+}
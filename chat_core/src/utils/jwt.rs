@@ -11,6 +11,9 @@ use crate::User;
 const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUD: &str = "chat_web";
+// short-lived token proving the first factor, exchanged for a real token after 2FA
+const JWT_CHALLENGE_DURATION: u64 = 60 * 5;
+const JWT_CHALLENGE_AUD: &str = "chat_2fa";
 
 pub struct EncodingKey(Ed25519KeyPair);
 
@@ -23,8 +26,16 @@ impl EncodingKey {
     }
 
     pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
-        let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION));
-        let claims = claims.with_issuer(JWT_ISSUER).with_audience(JWT_AUD);
+        self.sign_with(user.into(), JWT_AUD, JWT_DURATION)
+    }
+
+    pub fn sign_challenge(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
+        self.sign_with(user.into(), JWT_CHALLENGE_AUD, JWT_CHALLENGE_DURATION)
+    }
+
+    fn sign_with(&self, user: User, aud: &str, secs: u64) -> Result<String, jwt_simple::Error> {
+        let claims = Claims::with_custom_claims(user, Duration::from_secs(secs));
+        let claims = claims.with_issuer(JWT_ISSUER).with_audience(aud);
         self.0.sign(claims)
     }
 }
@@ -36,9 +47,17 @@ impl DecodingKey {
 
     #[allow(unused)]
     pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        self.verify_with(token, JWT_AUD)
+    }
+
+    pub fn verify_challenge(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        self.verify_with(token, JWT_CHALLENGE_AUD)
+    }
+
+    fn verify_with(&self, token: &str, aud: &str) -> Result<User, jwt_simple::Error> {
         let opts = VerificationOptions {
             allowed_issuers: Some(HashSet::from([JWT_ISSUER.to_string()])),
-            allowed_audiences: Some(HashSet::from([JWT_AUD.to_string()])),
+            allowed_audiences: Some(HashSet::from([aud.to_string()])),
             ..Default::default()
         };
         // opts.allowed_issuers = Some(HashSet::from([JWT_ISSUER.to_string()]));
@@ -67,4 +86,20 @@ mod tests {
         assert_eq!(user, user2);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn jwt_challenge_should_not_be_accepted_as_token() -> Result<()> {
+        let ek = EncodingKey::load(include_str!("../../fixtures/encoding.pem"))?;
+        let dk = DecodingKey::load(include_str!("../../fixtures/decoding.pem"))?;
+
+        let user = User::new(1, 0, "wiki", "charmfocus@gmail.com");
+
+        let challenge = ek.sign_challenge(user.clone())?;
+        assert_eq!(dk.verify_challenge(&challenge)?, user);
+        assert!(dk.verify(&challenge).is_err());
+
+        let token = ek.sign(user)?;
+        assert!(dk.verify_challenge(&token).is_err());
+        Ok(())
+    }
 }
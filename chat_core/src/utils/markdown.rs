@@ -0,0 +1,150 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+
+use crate::MessageFormat;
+
+// links to other schemes are reduced to their text, e.g. javascript:
+const SAFE_SCHEMES: &[&str] = &["http://", "https://", "mailto:"];
+
+/// Render message content to html that is safe to insert into a page as is
+pub fn render_message(content: &str, format: MessageFormat) -> String {
+    match format {
+        MessageFormat::Plain => escape_html(content),
+        MessageFormat::Markdown => render_markdown(content),
+    }
+}
+
+/// Only code blocks, inline code, links, lists, quotes, bold, italics and
+/// strikethrough are kept. Raw html is escaped and shown as text, other
+/// elements are reduced to paragraphs or their text.
+fn render_markdown(content: &str) -> String {
+    let mut in_unsafe_link = false;
+    let events = Parser::new_ext(content, Options::ENABLE_STRIKETHROUGH).filter_map(|event| {
+        match event {
+            Event::Html(s) | Event::InlineHtml(s) => Some(Event::Text(s)),
+            // chat messages keep their line breaks
+            Event::SoftBreak => Some(Event::HardBreak),
+            Event::Start(tag) => match tag {
+                Tag::Paragraph
+                | Tag::CodeBlock(_)
+                | Tag::List(_)
+                | Tag::Item
+                | Tag::BlockQuote(_)
+                | Tag::Emphasis
+                | Tag::Strong
+                | Tag::Strikethrough => Some(Event::Start(tag)),
+                Tag::Heading { .. } => Some(Event::Start(Tag::Paragraph)),
+                Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                } => {
+                    in_unsafe_link = !is_safe_url(&dest_url);
+                    (!in_unsafe_link).then_some(Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    }))
+                }
+                // images and html blocks are reduced to their text
+                _ => None,
+            },
+            Event::End(tag) => match tag {
+                TagEnd::Paragraph
+                | TagEnd::CodeBlock
+                | TagEnd::List(_)
+                | TagEnd::Item
+                | TagEnd::BlockQuote(_)
+                | TagEnd::Emphasis
+                | TagEnd::Strong
+                | TagEnd::Strikethrough => Some(Event::End(tag)),
+                TagEnd::Heading(_) => Some(Event::End(TagEnd::Paragraph)),
+                TagEnd::Link => (!std::mem::take(&mut in_unsafe_link)).then_some(Event::End(tag)),
+                _ => None,
+            },
+            Event::Text(_) | Event::Code(_) | Event::HardBreak => Some(event),
+            Event::Rule => Some(Event::Text(CowStr::Borrowed("---"))),
+            _ => None,
+        }
+    });
+
+    let mut output = String::with_capacity(content.len() * 3 / 2);
+    html::push_html(&mut output, events);
+    output
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim().to_ascii_lowercase();
+    SAFE_SCHEMES.iter().any(|scheme| url.starts_with(scheme))
+}
+
+/// Same escaping as the migration that renders existing plain messages
+fn escape_html(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn md(s: &str) -> String {
+        render_message(s, MessageFormat::Markdown)
+    }
+
+    #[test]
+    fn render_plain_should_escape_html() {
+        assert_eq!(
+            render_message("<b>hi</b> & 'you'", MessageFormat::Plain),
+            "&lt;b&gt;hi&lt;/b&gt; &amp; &#39;you&#39;"
+        );
+    }
+
+    #[test]
+    fn render_markdown_should_work() {
+        assert_eq!(
+            md("**bold** _it_ `code`"),
+            "<p><strong>bold</strong> <em>it</em> <code>code</code></p>\n"
+        );
+        assert_eq!(
+            md("- one\n- two"),
+            "<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n"
+        );
+        assert_eq!(
+            md("```rust\nfn main() {}\n```"),
+            "<pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n"
+        );
+        assert_eq!(
+            md("[docs](https://acme.org/docs)"),
+            "<p><a href=\"https://acme.org/docs\">docs</a></p>\n"
+        );
+        assert_eq!(md("# title\nline"), "<p>title</p>\n<p>line</p>\n");
+    }
+
+    #[test]
+    fn render_markdown_should_strip_unsafe_content() {
+        assert_eq!(
+            md("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(
+            md("hi <img src=x onerror=alert(1)>"),
+            "<p>hi &lt;img src=x onerror=alert(1)&gt;</p>\n"
+        );
+        assert_eq!(
+            md("[click](javascript:alert(1)) ![x](https://acme.org/x.png)"),
+            "<p>click x</p>\n"
+        );
+    }
+}
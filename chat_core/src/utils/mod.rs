@@ -1,3 +1,11 @@
 mod jwt;
+mod markdown;
+mod net;
+mod signature;
 
 pub use jwt::{DecodingKey, EncodingKey};
+pub use markdown::render_message;
+pub use net::{is_public_ip, resolve_public_addrs, PublicResolver};
+pub use signature::{
+    sign_payload, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
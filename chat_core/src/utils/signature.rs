@@ -0,0 +1,30 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const EVENT_HEADER: &str = "x-chat-event";
+pub const DELIVERY_HEADER: &str = "x-chat-delivery";
+pub const TIMESTAMP_HEADER: &str = "x-chat-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-chat-signature";
+
+/// Signature of outgoing http requests, `sha256=` followed by the hex encoded
+/// HMAC-SHA256 of `{timestamp}.{payload}`. The timestamp lets receivers reject replays.
+pub fn sign_payload(secret: &str, timestamp: u64, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_payload_should_work() {
+        let signature = sign_payload("secret", 1733011200, "{}");
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), 7 + 64);
+        assert_ne!(signature, sign_payload("other", 1733011200, "{}"));
+        assert_ne!(signature, sign_payload("secret", 1733011201, "{}"));
+    }
+}
@@ -0,0 +1,29 @@
+use std::{env, fs::File};
+
+use anyhow::{bail, Result};
+use chat_server::{copy_files, storage_from_config, AppConfig, StorageConfig};
+use tracing::{info, level_filters::LevelFilter};
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+
+/// Copy the stored files from the configured storage to another one, e.g.
+/// `migrate_storage target.yml` with `type: s3` and the bucket settings in it.
+/// Files already in the target are skipped, so it can be run again before switching.
+#[tokio::main]
+async fn main() -> Result<()> {
+    let layer = Layer::new().with_filter(LevelFilter::INFO);
+    tracing_subscriber::registry().with(layer).init();
+
+    let Some(target) = env::args().nth(1) else {
+        bail!("usage: migrate_storage <target storage config>");
+    };
+    let target: StorageConfig = serde_yaml::from_reader(File::open(target)?)?;
+    let config = AppConfig::load()?;
+    let base_dir = &config.server.base_dir;
+
+    let from = storage_from_config(&config.storage, base_dir)?;
+    let to = storage_from_config(&target, base_dir)?;
+    let copied = copy_files(from.as_ref(), to.as_ref(), base_dir).await?;
+    info!("Copied {} files", copied);
+
+    Ok(())
+}
@@ -1,12 +1,25 @@
 use std::{env, fs::File, path::PathBuf};
 
 use anyhow::{bail, Result};
+use chat_core::middlewares::{LockoutConfig, RateLimitConfig};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub files: FileSettings,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub scanner: ScannerConfig,
+    #[serde(default)]
+    pub chats: ChatSettings,
+    #[serde(default)]
+    pub outbound: OutboundSettings,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +35,243 @@ pub struct ServerConfig {
     pub base_dir: PathBuf,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    // only enable this behind a reverse proxy that sets x-forwarded-for
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    #[serde(default = "default_signin_limit")]
+    pub signin: RateLimitConfig,
+    #[serde(default = "default_signup_limit")]
+    pub signup: RateLimitConfig,
+    // applies to each incoming webhook
+    #[serde(default = "default_webhook_limit")]
+    pub webhook: RateLimitConfig,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            trust_forwarded_for: false,
+            signin: default_signin_limit(),
+            signup: default_signup_limit(),
+            webhook: default_webhook_limit(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSettings {
+    // remove exif, e.g. the gps location, from uploaded images
+    #[serde(default = "default_strip_metadata")]
+    pub strip_metadata: bool,
+    // in bytes, for each uploaded file and for the whole upload request
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+    #[serde(default = "default_max_request_size")]
+    pub max_request_size: u64,
+    // resumable uploads are dropped when they aren't resumed for this long
+    #[serde(default = "default_upload_expires_secs")]
+    pub upload_expires_secs: u64,
+    // lifetime of the signed urls used without an authorization header
+    #[serde(default = "default_signed_url_expires_secs")]
+    pub signed_url_expires_secs: u64,
+    // uploads no message references are deleted after this long
+    #[serde(default = "default_gc_grace_secs")]
+    pub gc_grace_secs: u64,
+    #[serde(default = "default_gc_interval_secs")]
+    pub gc_interval_secs: u64,
+}
+
+impl Default for FileSettings {
+    fn default() -> Self {
+        Self {
+            strip_metadata: default_strip_metadata(),
+            max_file_size: default_max_file_size(),
+            max_request_size: default_max_request_size(),
+            upload_expires_secs: default_upload_expires_secs(),
+            signed_url_expires_secs: default_signed_url_expires_secs(),
+            gc_grace_secs: default_gc_grace_secs(),
+            gc_interval_secs: default_gc_interval_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatSettings {
+    // per chat, pinning more fails until a message is unpinned
+    #[serde(default = "default_max_pins")]
+    pub max_pins: u64,
+    #[serde(default = "default_max_bookmarks")]
+    pub max_bookmarks: u64,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            max_pins: default_max_pins(),
+            max_bookmarks: default_max_bookmarks(),
+        }
+    }
+}
+
+/// Requests to urls set by users, i.e. bot commands and identity providers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboundSettings {
+    #[serde(default = "default_allowed_schemes")]
+    pub allowed_schemes: Vec<String>,
+    // bot commands only reach public addresses unless this is set, e.g. for
+    // local development
+    #[serde(default)]
+    pub allow_private_addrs: bool,
+}
+
+impl Default for OutboundSettings {
+    fn default() -> Self {
+        Self {
+            allowed_schemes: default_allowed_schemes(),
+            allow_private_addrs: false,
+        }
+    }
+}
+
+impl OutboundSettings {
+    pub fn allows_scheme(&self, scheme: &str) -> bool {
+        self.allowed_schemes
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(scheme))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConfig {
+    // defaults to the base dir of the server
+    Local {
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+    S3(S3Config),
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::Local { path: None }
+    }
+}
+
+/// Malware scanning of uploads, disabled by default
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScannerConfig {
+    #[default]
+    None,
+    // `host:port` or `unix:/path/to/clamd.sock`
+    Clamd {
+        address: String,
+        #[serde(default = "default_clamd_timeout_secs")]
+        timeout_secs: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub endpoint: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    // `{endpoint}/{bucket}/{key}` instead of `{bucket}.{endpoint}/{key}`, needed by MinIO
+    #[serde(default)]
+    pub path_style: bool,
+    // redirect downloads to the bucket instead of streaming them through the server
+    #[serde(default)]
+    pub presigned_downloads: bool,
+    #[serde(default = "default_presigned_expires_secs")]
+    pub presigned_expires_secs: u64,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_presigned_expires_secs() -> u64 {
+    5 * 60
+}
+
+fn default_strip_metadata() -> bool {
+    true
+}
+
+fn default_max_file_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_max_request_size() -> u64 {
+    200 * 1024 * 1024
+}
+
+fn default_upload_expires_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_signed_url_expires_secs() -> u64 {
+    60 * 60
+}
+
+fn default_clamd_timeout_secs() -> u64 {
+    30
+}
+
+fn default_gc_grace_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_gc_interval_secs() -> u64 {
+    60 * 60
+}
+
+fn default_max_pins() -> u64 {
+    100
+}
+
+fn default_max_bookmarks() -> u64 {
+    50
+}
+
+fn default_allowed_schemes() -> Vec<String> {
+    vec!["https".to_string()]
+}
+
+fn default_signin_limit() -> RateLimitConfig {
+    RateLimitConfig {
+        max_requests: 10,
+        window_secs: 60,
+        lockout: Some(LockoutConfig {
+            max_failures: 5,
+            base_secs: 60,
+            max_secs: 60 * 60,
+        }),
+    }
+}
+
+fn default_signup_limit() -> RateLimitConfig {
+    RateLimitConfig {
+        max_requests: 5,
+        window_secs: 60 * 60,
+        lockout: None,
+    }
+}
+
+fn default_webhook_limit() -> RateLimitConfig {
+    RateLimitConfig {
+        max_requests: 30,
+        window_secs: 60,
+        lockout: None,
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from /etc/config/chat.yml, or ./chat.yml, or from env CHAT_CONFIG
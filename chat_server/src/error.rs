@@ -1,12 +1,18 @@
 use axum::{
     body::Body,
-    http::StatusCode,
+    extract::multipart::MultipartError,
+    http::{
+        header::{CONTENT_RANGE, RETRY_AFTER},
+        StatusCode,
+    },
     response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::models::{UploadRejection, TUS_VERSION, TUS_VERSION_HEADER};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorOutput {
     pub error: String,
@@ -20,6 +26,14 @@ impl ErrorOutput {
     }
 }
 
+/// An error with the details of a refused upload
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadRejectedOutput {
+    pub error: String,
+    #[serde(flatten)]
+    pub rejection: UploadRejection,
+}
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("sqlx error: {0}")]
@@ -51,6 +65,84 @@ pub enum AppError {
 
     #[error("http header parse error: {0}")]
     HttpHeaderParseError(#[from] axum::http::header::InvalidHeaderValue),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("two-factor error: {0}")]
+    TwoFactorError(String),
+
+    #[error("invalid two-factor code")]
+    InvalidTwoFactorCode,
+
+    #[error("openid connect error: {0}")]
+    OidcError(String),
+
+    #[error("http client error: {0}")]
+    HttpClientError(#[from] reqwest::Error),
+
+    #[error("api token error: {0}")]
+    ApiTokenError(String),
+
+    #[error("create bot error: {0}")]
+    CreateBotError(String),
+
+    #[error("command error: {0}")]
+    CommandError(String),
+
+    #[error("event subscription error: {0}")]
+    EventSubscriptionError(String),
+
+    #[error("webhook error: {0}")]
+    WebhookError(String),
+
+    #[error("too many requests, retry after {0}s")]
+    TooManyRequests(u64),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("multipart error: {0}")]
+    MultipartError(#[from] MultipartError),
+
+    #[error("range not satisfiable, file size is {0}")]
+    RangeNotSatisfiable(u64),
+
+    #[error("storage error: {0}")]
+    StorageError(String),
+
+    #[error("scan error: {0}")]
+    ScanError(String),
+
+    #[error("upload error: {0}")]
+    UploadError(String),
+
+    #[error("upload offset mismatch: {0}")]
+    UploadOffsetMismatch(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("unsupported tus version: {0}")]
+    TusVersionMismatch(String),
+
+    #[error("upload rejected: {0}")]
+    UploadRejected(#[from] UploadRejection),
+
+    #[error("list messages error: {0}")]
+    ListMessagesError(String),
+
+    #[error("pin error: {0}")]
+    PinError(String),
+
+    #[error("bookmark error: {0}")]
+    BookmarkError(String),
+
+    #[error("reminder error: {0}")]
+    ReminderError(String),
+
+    #[error("scheduled message error: {0}")]
+    ScheduledMessageError(String),
 }
 
 impl IntoResponse for AppError {
@@ -66,8 +158,60 @@ impl IntoResponse for AppError {
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::ChatFileError(_) => StatusCode::BAD_REQUEST,
+            AppError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            AppError::TwoFactorError(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidTwoFactorCode => StatusCode::FORBIDDEN,
+            AppError::OidcError(_) => StatusCode::UNAUTHORIZED,
+            AppError::HttpClientError(_) => StatusCode::BAD_GATEWAY,
+            AppError::ApiTokenError(_) => StatusCode::BAD_REQUEST,
+            AppError::CreateBotError(_) => StatusCode::BAD_REQUEST,
+            AppError::CommandError(_) => StatusCode::BAD_REQUEST,
+            AppError::EventSubscriptionError(_) => StatusCode::BAD_REQUEST,
+            AppError::WebhookError(_) => StatusCode::BAD_REQUEST,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::MultipartError(e) => e.status(),
+            AppError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            AppError::StorageError(_) => StatusCode::BAD_GATEWAY,
+            AppError::ScanError(_) => StatusCode::BAD_GATEWAY,
+            AppError::UploadError(_) => StatusCode::BAD_REQUEST,
+            AppError::UploadOffsetMismatch(_) => StatusCode::CONFLICT,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::TusVersionMismatch(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::ListMessagesError(_) => StatusCode::BAD_REQUEST,
+            AppError::PinError(_) => StatusCode::BAD_REQUEST,
+            AppError::BookmarkError(_) => StatusCode::BAD_REQUEST,
+            AppError::ReminderError(_) => StatusCode::BAD_REQUEST,
+            AppError::ScheduledMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::UploadRejected(rejection) => match rejection {
+                UploadRejection::QuotaExceeded { .. } | UploadRejection::FileTooLarge { .. } => {
+                    StatusCode::PAYLOAD_TOO_LARGE
+                }
+                UploadRejection::ExtensionNotAllowed { .. }
+                | UploadRejection::MimeTypeNotAllowed { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                UploadRejection::GuestUploadsDisabled => StatusCode::FORBIDDEN,
+            },
         };
 
-        (status, Json(ErrorOutput::new(self.to_string()))).into_response()
+        let body = Json(ErrorOutput::new(self.to_string()));
+        match self {
+            AppError::TooManyRequests(secs) => {
+                (status, [(RETRY_AFTER, secs.to_string())], body).into_response()
+            }
+            AppError::RangeNotSatisfiable(len) => {
+                (status, [(CONTENT_RANGE, format!("bytes */{}", len))], body).into_response()
+            }
+            AppError::TusVersionMismatch(_) => {
+                (status, [(TUS_VERSION_HEADER, TUS_VERSION)], body).into_response()
+            }
+            AppError::UploadRejected(rejection) => {
+                let body = UploadRejectedOutput {
+                    error: body.0.error,
+                    rejection,
+                };
+                (status, Json(body)).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
     }
 }
@@ -0,0 +1,72 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreateApiToken, CreateBot};
+use chat_core::User;
+
+pub(crate) async fn create_api_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateApiToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state.create_api_token(&input, &user, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+pub(crate) async fn list_api_tokens_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = state.list_api_tokens(user.id as _).await?;
+    Ok(Json(tokens))
+}
+
+pub(crate) async fn revoke_api_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_api_token(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn create_bot_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateBot>,
+) -> Result<impl IntoResponse, AppError> {
+    let bot = state.create_bot(&input.name, &user).await?;
+    Ok((StatusCode::CREATED, Json(bot)))
+}
+
+pub(crate) async fn list_bots_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let bots = state.list_bots(user.id as _).await?;
+    Ok(Json(bots))
+}
+
+pub(crate) async fn create_bot_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateApiToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state.create_api_token(&input, &user, id).await?;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+pub(crate) async fn list_bot_tokens_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_bot_owner(id, user.id as _).await?;
+    let tokens = state.list_api_tokens(id).await?;
+    Ok(Json(tokens))
+}
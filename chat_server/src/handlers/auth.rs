@@ -1,4 +1,11 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chat_core::User;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -9,6 +16,32 @@ use crate::{
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthOutput {
     pub token: String,
+    // only set when 2fa gets enabled as part of signin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorChallenge {
+    pub challenge_token: String,
+    // workspace requires 2fa but the user hasn't enrolled yet
+    pub enrollment_required: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorSignin {
+    pub challenge_token: String,
+    #[serde(default)]
+    pub code: String,
+}
+
+impl AuthOutput {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            recovery_codes: None,
+        }
+    }
 }
 
 pub(crate) async fn signup_handler(
@@ -21,7 +54,7 @@ pub(crate) async fn signup_handler(
     // let mut header = HeaderMap::new();
     // header.insert("X-Auth-Token", token.parse()?);
     // Ok((StatusCode::CREATED, header))
-    let body = Json(AuthOutput { token });
+    let body = Json(AuthOutput::new(token));
     Ok((StatusCode::CREATED, body))
 }
 
@@ -31,10 +64,7 @@ pub(crate) async fn signin_handler(
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.verify_user(&input).await?;
     match user {
-        Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
-        }
+        Some(user) => signin_response(&state, user).await,
         None => {
             let body = Json(ErrorOutput::new("Invalid email or password"));
             Ok((StatusCode::FORBIDDEN, body).into_response())
@@ -42,9 +72,64 @@ pub(crate) async fn signin_handler(
     }
 }
 
+/// A 2fa challenge if the user enrolled or the workspace requires it, a token otherwise
+pub(super) async fn signin_response(state: &AppState, user: User) -> Result<Response, AppError> {
+    let status = state.two_factor_status(user.id as _).await?;
+    if status.enabled || status.required {
+        let challenge_token = state.ek.sign_challenge(user)?;
+        let body = Json(TwoFactorChallenge {
+            challenge_token,
+            enrollment_required: !status.enabled,
+        });
+        return Ok((StatusCode::OK, body).into_response());
+    }
+
+    let token = state.ek.sign(user)?;
+    Ok((StatusCode::OK, Json(AuthOutput::new(token))).into_response())
+}
+
+/// Exchange a challenge token plus a totp / recovery code for a real token.
+/// If the workspace forced an enrollment, the code confirms the pending secret.
+pub(crate) async fn signin_2fa_handler(
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorSignin>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.dk.verify_challenge(&input.challenge_token)?;
+    let now = Utc::now().timestamp() as u64;
+
+    let status = state.two_factor_status(user.id as _).await?;
+    let recovery_codes = if status.enabled {
+        if !state.verify_two_factor(&user, &input.code, now).await? {
+            return Err(AppError::InvalidTwoFactorCode);
+        }
+        None
+    } else {
+        let codes = state.confirm_totp(&user, &input.code, now).await?;
+        Some(codes.recovery_codes)
+    };
+
+    let token = state.ek.sign(user)?;
+    let body = AuthOutput {
+        token,
+        recovery_codes,
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+/// Start a totp enrollment with a challenge token, for workspaces that require 2fa
+pub(crate) async fn signin_2fa_enroll_handler(
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorSignin>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.dk.verify_challenge(&input.challenge_token)?;
+    let enrollment = state.enroll_totp(&user).await?;
+    Ok((StatusCode::OK, Json(enrollment)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{models::totp_code, UpdateWorkspace};
     use anyhow::Result;
     use axum::body::to_bytes;
 
@@ -123,4 +208,94 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn signin_with_2fa_should_require_code() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let now = Utc::now().timestamp() as u64;
+        let enrollment = state.enroll_totp(&user).await?;
+        let code = totp_code(&enrollment.secret, &user.email, now - 30);
+        state.confirm_totp(&user, &code, now).await?;
+
+        let input = SigninUser::new(&user.email, "123456");
+        let ret = signin_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let challenge = serde_json::from_slice::<TwoFactorChallenge>(&body)?;
+        assert!(!challenge.enrollment_required);
+
+        // challenge token can't be used as a regular token
+        assert!(state.dk.verify(&challenge.challenge_token).is_err());
+
+        let input = TwoFactorSignin {
+            challenge_token: challenge.challenge_token.clone(),
+            code: "000000".to_string(),
+        };
+        let ret = signin_2fa_handler(State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let input = TwoFactorSignin {
+            challenge_token: challenge.challenge_token,
+            code: totp_code(&enrollment.secret, &user.email, now),
+        };
+        let ret = signin_2fa_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let ret = serde_json::from_slice::<AuthOutput>(&body)?;
+        assert_eq!(state.dk.verify(&ret.token)?.id, user.id);
+        assert!(ret.recovery_codes.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_in_2fa_required_workspace_should_enroll() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        state
+            .update_workspace(1, 1, &UpdateWorkspace::new(true))
+            .await?;
+
+        let input = SigninUser::new("wukun@gmail.com", "123456");
+        let ret = signin_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let challenge = serde_json::from_slice::<TwoFactorChallenge>(&body)?;
+        assert!(challenge.enrollment_required);
+
+        let input = TwoFactorSignin {
+            challenge_token: challenge.challenge_token.clone(),
+            code: String::new(),
+        };
+        let ret = signin_2fa_enroll_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let enrollment = serde_json::from_slice::<crate::TotpEnrollment>(&body)?;
+
+        let now = Utc::now().timestamp() as u64;
+        let input = TwoFactorSignin {
+            challenge_token: challenge.challenge_token,
+            code: totp_code(&enrollment.secret, "wukun@gmail.com", now),
+        };
+        let ret = signin_2fa_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let ret = serde_json::from_slice::<AuthOutput>(&body)?;
+        assert!(!ret.token.is_empty());
+        assert_eq!(ret.recovery_codes.map(|v| v.len()), Some(10));
+        assert!(state.two_factor_status(2).await?.enabled);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,44 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+use crate::{AppError, AppState, CreateBookmark, UpdateBookmark};
+
+pub(crate) async fn list_bookmarks_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let bookmarks = state.list_bookmarks(id).await?;
+    Ok(Json(bookmarks))
+}
+
+pub(crate) async fn create_bookmark_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateBookmark>,
+) -> Result<impl IntoResponse, AppError> {
+    let bookmark = state.create_bookmark(id, user.id as _, input).await?;
+    Ok((StatusCode::CREATED, Json(bookmark)))
+}
+
+pub(crate) async fn update_bookmark_handler(
+    State(state): State<AppState>,
+    Path((id, bookmark_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateBookmark>,
+) -> Result<impl IntoResponse, AppError> {
+    let bookmark = state.update_bookmark(bookmark_id, id, input).await?;
+    Ok(Json(bookmark))
+}
+
+pub(crate) async fn delete_bookmark_handler(
+    State(state): State<AppState>,
+    Path((id, bookmark_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_bookmark(bookmark_id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
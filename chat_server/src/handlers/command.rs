@@ -0,0 +1,46 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, ListCommands, RegisterCommand};
+use chat_core::User;
+
+pub(crate) async fn list_commands_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListCommands>,
+) -> Result<impl IntoResponse, AppError> {
+    let commands = state.list_commands(user.workspace_id as _, &input).await?;
+    Ok(Json(commands))
+}
+
+pub(crate) async fn register_command_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<RegisterCommand>,
+) -> Result<impl IntoResponse, AppError> {
+    let command = state.register_command(&input, id, &user).await?;
+    Ok((StatusCode::CREATED, Json(command)))
+}
+
+pub(crate) async fn list_bot_commands_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let commands = state.list_bot_commands(id, user.id as _).await?;
+    Ok(Json(commands))
+}
+
+pub(crate) async fn delete_command_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, command_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_command(command_id, id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,51 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreateEventSubscription, ListEventDeliveries};
+use chat_core::User;
+
+pub(crate) async fn create_event_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateEventSubscription>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscription = state
+        .create_event_subscription(&input, id, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+pub(crate) async fn list_event_subscriptions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscriptions = state.list_event_subscriptions(id, user.id as _).await?;
+    Ok(Json(subscriptions))
+}
+
+pub(crate) async fn delete_event_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, subscription_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_event_subscription(subscription_id, id, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_event_deliveries_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ListEventDeliveries>,
+) -> Result<impl IntoResponse, AppError> {
+    let deliveries = state.list_event_deliveries(input, id, user.id as _).await?;
+    Ok(Json(deliveries))
+}
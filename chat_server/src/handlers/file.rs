@@ -0,0 +1,355 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::{
+        header::{
+            ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            IF_NONE_MATCH, LOCATION, RANGE,
+        },
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chat_core::User;
+use mime_guess::{mime, Mime};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tracing::warn;
+
+use crate::{
+    models::{parse_range, thumbnail_key},
+    AppError, AppState, ChatFile, FileQuery, SignFileUrl, SignedFileQuery, THUMBNAIL_SIZES,
+};
+
+pub(crate) async fn file_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((workspace_id, path)): Path<(i64, String)>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let url = format!("/files/{}/{}", workspace_id, path);
+    // only the uploader and the members of the chats it was sent to
+    if user.workspace_id != workspace_id || !state.can_access_file(user.id as _, &url).await? {
+        return Err(AppError::NotFound(
+            "file doesn't exist or you don't have permission to access it.".to_string(),
+        ));
+    }
+    serve_file(&state, workspace_id, &url, query.size, &headers).await
+}
+
+/// Files embedded without an authorization header, e.g. in `<img>` tags
+pub(crate) async fn signed_file_handler(
+    State(state): State<AppState>,
+    Path((workspace_id, path)): Path<(i64, String)>,
+    Query(query): Query<SignedFileQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let url = format!("/files/{}/{}", workspace_id, path);
+    state.verify_file_signature(&url, query.expires, &query.signature)?;
+    serve_file(&state, workspace_id, &url, query.size, &headers).await
+}
+
+pub(crate) async fn sign_file_url_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<SignFileUrl>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspace_id = ChatFile::from_str(&input.url).map(|f| f.workspace_id as i64);
+    if workspace_id.ok() != Some(user.workspace_id)
+        || !state.can_access_file(user.id as _, &input.url).await?
+    {
+        return Err(AppError::NotFound(
+            "file doesn't exist or you don't have permission to access it.".to_string(),
+        ));
+    }
+    Ok(Json(state.sign_file_url(&input.url)))
+}
+
+async fn serve_file(
+    state: &AppState,
+    workspace_id: i64,
+    url: &str,
+    size: Option<u32>,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let Ok(file) = ChatFile::from_str(url) else {
+        return Err(AppError::NotFound("file doesn't exist".to_string()));
+    };
+    let mut key = file.hash_to_path();
+    let Some(mut len) = state.storage.size(&key).await? else {
+        return Err(AppError::NotFound("file doesn't exist".to_string()));
+    };
+    // the content is stored under its hash, so the hash never goes stale
+    let mut etag = file.hash.clone();
+    if let Some(size) = size {
+        if !THUMBNAIL_SIZES.contains(&size) {
+            return Err(AppError::ChatFileError(format!(
+                "Unsupported thumbnail size: {}",
+                size
+            )));
+        }
+        // not an image, smaller than the size or not generated yet
+        let thumbnail = thumbnail_key(&key, size);
+        if let Some(thumbnail_len) = state.storage.size(&thumbnail).await? {
+            key = thumbnail;
+            len = thumbnail_len;
+            etag = format!("{}-{}", etag, size);
+        }
+    }
+    let etag = HeaderValue::from_str(&format!("\"{}\"", etag))?;
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|v| etag_matches(v, &etag))
+    {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    let mime = mime_guess::from_path(&key).first_or_octet_stream();
+    let disposition = state
+        .find_file_name(workspace_id as _, url)
+        .await?
+        .map(|name| content_disposition(&name, &mime));
+    // the storage serves the download itself, the client follows the short lived url
+    if let Some(location) = state.storage.presigned_url(&key, disposition.as_deref()) {
+        return Ok((
+            StatusCode::TEMPORARY_REDIRECT,
+            [(LOCATION, HeaderValue::from_str(&location)?)],
+        )
+            .into_response());
+    }
+
+    let mut res_headers = HeaderMap::new();
+    res_headers.insert(CONTENT_TYPE, HeaderValue::from_str(mime.as_ref())?);
+    res_headers.insert(ETAG, etag);
+    res_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(disposition) = disposition {
+        res_headers.insert(CONTENT_DISPOSITION, HeaderValue::from_str(&disposition)?);
+    }
+
+    let range = match headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => parse_range(value, len)?,
+        None => None,
+    };
+    let Some(range) = range else {
+        res_headers.insert(CONTENT_LENGTH, len.into());
+        let body = state.storage.get(&key, None).await?;
+        return Ok((res_headers, body).into_response());
+    };
+
+    res_headers.insert(
+        CONTENT_RANGE,
+        HeaderValue::from_str(&format!("bytes {}-{}/{}", range.start, range.end - 1, len))?,
+    );
+    res_headers.insert(CONTENT_LENGTH, (range.end - range.start).into());
+    let body = state.storage.get(&key, Some(range)).await?;
+    Ok((StatusCode::PARTIAL_CONTENT, res_headers, body).into_response())
+}
+
+pub(crate) async fn upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let workspace_id = user.workspace_id as u64;
+    let mut policy = state.upload_policy(workspace_id, user.id as _).await?;
+    let mut files = vec![];
+
+    while let Some(mut field) = multipart.next_field().await? {
+        let Some(filename) = field.file_name().map(|s| s.to_string()) else {
+            warn!(
+                "Skipping multipart field without file name: {:?}",
+                field.name()
+            );
+            continue;
+        };
+        policy.check_file(&filename, field.content_type())?;
+
+        // written to disk as it arrives, the whole file is never in memory
+        let mut temp = state.temp_file().await?;
+        while let Some(chunk) = field.chunk().await? {
+            policy.check_size(temp.size() + chunk.len() as u64)?;
+            temp.write(&chunk).await?;
+        }
+        let file = state
+            .store_file(workspace_id, user.id as _, &filename, temp)
+            .await?;
+        policy.add_usage(file.size as _);
+        files.push(file);
+    }
+
+    Ok(Json(files))
+}
+
+fn etag_matches(value: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(value) = value.to_str() else {
+        return false;
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag.as_bytes())
+}
+
+/// Images, audio and video are shown inline, anything else is downloaded so
+/// uploaded html or svg never runs on our origin
+fn content_disposition(name: &str, mime: &Mime) -> String {
+    let inline = match mime.type_() {
+        mime::IMAGE => mime.subtype() != mime::SVG,
+        mime::AUDIO | mime::VIDEO => true,
+        _ => false,
+    };
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' ' => ' ',
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        if inline { "inline" } else { "attachment" },
+        fallback,
+        utf8_percent_encode(name, NON_ALPHANUMERIC)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        get_router, CreateMessage, FileRef, UploadRejectedOutput, UploadRejection,
+        WorkspaceFileSettings,
+    };
+
+    fn get(uri: &str, token: Option<&str>) -> Result<Request<Body>> {
+        let mut req = Request::builder().uri(uri);
+        if let Some(token) = token {
+            req = req.header("Authorization", format!("Bearer {}", token));
+        }
+        Ok(req.body(Body::empty())?)
+    }
+
+    #[tokio::test]
+    async fn files_should_only_be_served_to_chat_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = state.create_file(1, 1, "dm.txt", b"secret").await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            format: Default::default(),
+            files: vec![FileRef::Id(file.id)],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        // the direct chat of user 1 and 2
+        state.create_message(input, 3, 1).await?;
+        let mut tokens = vec![];
+        for id in [1, 2, 3] {
+            let user = state.find_user_by_id(id).await?.expect("user should exist");
+            tokens.push(state.ek.sign(user)?);
+        }
+        let signed = state.sign_file_url(&file.url);
+        let app = get_router(state).await?;
+
+        let uri = format!("/api{}", file.url);
+        for (token, status) in
+            tokens
+                .iter()
+                .zip([StatusCode::OK, StatusCode::OK, StatusCode::NOT_FOUND])
+        {
+            let res = app.clone().oneshot(get(&uri, Some(token))?).await?;
+            assert_eq!(res.status(), status);
+        }
+
+        let res = app.clone().oneshot(get(&signed.url, None)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body.as_ref(), b"secret");
+        let tampered = signed.url.replace("expires=", "expires=1");
+        let res = app.clone().oneshot(get(&tampered, None)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let expired = format!("/api/signed{}?expires=1&signature=00", file.url);
+        let res = app.oneshot(get(&expired, None)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_should_be_rejected_by_the_workspace_policy() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let settings = WorkspaceFileSettings {
+            quota_bytes: Some(8),
+            blocked_extensions: vec!["exe".to_string()],
+            ..Default::default()
+        };
+        state.update_file_settings(1, 1, &settings).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let app = get_router(state).await?;
+
+        let upload = |name: &str, data: &str| -> Result<Request<Body>> {
+            let body = format!(
+                "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n{}\r\n--X--\r\n",
+                name, data
+            );
+            Ok(Request::builder()
+                .method("POST")
+                .uri("/api/upload")
+                .header("Authorization", format!("Bearer {}", token))
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=X")
+                .body(Body::from(body))?)
+        };
+        let res = app.clone().oneshot(upload("setup.exe", "MZ")?).await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        let output: UploadRejectedOutput = serde_json::from_slice(&body)?;
+        assert_eq!(
+            output.rejection,
+            UploadRejection::ExtensionNotAllowed {
+                extension: "exe".to_string()
+            }
+        );
+
+        let res = app.clone().oneshot(upload("a.txt", "hello")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(upload("b.txt", "world")?).await?;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        let output: UploadRejectedOutput = serde_json::from_slice(&body)?;
+        assert_eq!(
+            output.rejection,
+            UploadRejection::QuotaExceeded { quota: 8, used: 5 }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_disposition_should_work() {
+        assert_eq!(
+            content_disposition("büro \"plan\".png", &mime::IMAGE_PNG),
+            "inline; filename=\"b_ro _plan_.png\"; filename*=UTF-8''b%C3%BCro%20%22plan%22%2Epng"
+        );
+        assert!(content_disposition("x.svg", &mime::IMAGE_SVG).starts_with("attachment;"));
+        assert!(content_disposition("x.html", &mime::TEXT_HTML).starts_with("attachment;"));
+    }
+
+    #[test]
+    fn etag_matches_should_work() {
+        let etag = HeaderValue::from_static("\"abc\"");
+        let matches = |v| etag_matches(&HeaderValue::from_static(v), &etag);
+        assert!(matches("\"abc\""));
+        assert!(matches("\"x\", W/\"abc\""));
+        assert!(matches("*"));
+        assert!(!matches("\"abcd\""));
+    }
+}
@@ -0,0 +1,131 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderName, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::warn;
+
+use crate::{AppError, AppState, CreateIncomingWebhook, WebhookPayload};
+use chat_core::{middlewares::WEBHOOK_SECRET_HEADER, User};
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+pub(crate) async fn create_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateIncomingWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let hook = state.create_incoming_webhook(&input, id, &user).await?;
+    Ok((StatusCode::CREATED, Json(hook)))
+}
+
+pub(crate) async fn list_incoming_webhooks_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let hooks = state.list_incoming_webhooks(id).await?;
+    Ok(Json(hooks))
+}
+
+pub(crate) async fn regenerate_webhook_secret_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, hook_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let hook = state
+        .regenerate_webhook_secret(hook_id, id, user.id as _)
+        .await?;
+    Ok(Json(hook))
+}
+
+pub(crate) async fn delete_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, hook_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_incoming_webhook(hook_id, id, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Public endpoint, the secret in the `X-Webhook-Secret` header authenticates the caller
+pub(crate) async fn post_incoming_webhook_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Json(payload): Json<WebhookPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let secret = headers
+        .get(WEBHOOK_SECRET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    state.post_incoming_webhook(id, secret, payload).await?;
+    // same response as slack, so existing integrations keep working
+    Ok("ok")
+}
+
+/// Deprecated, the secret in the url ends up in proxy and access logs
+pub(crate) async fn post_incoming_webhook_with_path_secret_handler(
+    State(state): State<AppState>,
+    Path((id, secret)): Path<(u64, String)>,
+    Json(payload): Json<WebhookPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    state.post_incoming_webhook(id, &secret, payload).await?;
+    warn!(
+        "Webhook {} was posted with the secret in the url, send it in the {} header",
+        id, WEBHOOK_SECRET_HEADER
+    );
+    Ok(([(DEPRECATION, "true")], "ok"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn incoming_webhook_secret_should_be_sent_in_a_header() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+        let input = CreateIncomingWebhook::new("ci", bot.id);
+        let created = state.create_incoming_webhook(&input, 1, &user).await?;
+        let id = created.info.id as u64;
+        let payload = || WebhookPayload {
+            text: "deployed".to_string(),
+            ..Default::default()
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(WEBHOOK_SECRET_HEADER, created.secret.parse()?);
+        let ret =
+            post_incoming_webhook_handler(State(state.clone()), Path(id), headers, Json(payload()))
+                .await?
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let ret = post_incoming_webhook_handler(
+            State(state.clone()),
+            Path(id),
+            HeaderMap::new(),
+            Json(payload()),
+        )
+        .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // the secret in the path still works, but is deprecated
+        let ret = post_incoming_webhook_with_path_secret_handler(
+            State(state.clone()),
+            Path((id, created.secret)),
+            Json(payload()),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        assert_eq!(ret.headers()[DEPRECATION], "true");
+        Ok(())
+    }
+}
@@ -1,12 +1,40 @@
+mod api_token;
 mod auth;
+mod bookmark;
 mod chat;
+mod command;
+mod event_subscription;
+mod file;
+mod incoming_webhook;
 mod messages;
+mod oidc;
+mod pin;
+mod reminder;
+mod retention;
+mod saved_message;
+mod scheduled_message;
+mod two_factor;
+mod upload;
 mod workspace;
 
+pub(crate) use api_token::*;
 pub(crate) use auth::*;
 use axum::response::IntoResponse;
+pub(crate) use bookmark::*;
 pub(crate) use chat::*;
+pub(crate) use command::*;
+pub(crate) use event_subscription::*;
+pub(crate) use file::*;
+pub(crate) use incoming_webhook::*;
 pub(crate) use messages::*;
+pub(crate) use oidc::*;
+pub(crate) use pin::*;
+pub(crate) use reminder::*;
+pub(crate) use retention::*;
+pub(crate) use saved_message::*;
+pub(crate) use scheduled_message::*;
+pub(crate) use two_factor::*;
+pub(crate) use upload::*;
 pub(crate) use workspace::*;
 
 pub(crate) async fn index_handler() -> impl IntoResponse {
@@ -0,0 +1,62 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect},
+    Extension, Json,
+};
+
+use super::{signin_response, AuthOutput};
+use crate::{AppError, AppState, OidcCallback, UpsertOidcProvider};
+use chat_core::User;
+
+pub(crate) async fn oidc_authorize_handler(
+    State(state): State<AppState>,
+    Path(workspace): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state.oidc_authorize_url(&workspace).await?;
+    Ok(Redirect::to(&url))
+}
+
+/// The IdP redirects here, 2fa applies as for a password signin unless the IdP enforces mfa
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    Query(input): Query<OidcCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(error) = input.error {
+        let desc = input.error_description.unwrap_or_default();
+        return Err(AppError::OidcError(format!("{} {}", error, desc)));
+    }
+    let Some(code) = input.code else {
+        return Err(AppError::OidcError(
+            "missing authorization code".to_string(),
+        ));
+    };
+
+    let signin = state.oidc_signin(&code, &input.state).await?;
+    if !signin.enforces_mfa {
+        return signin_response(&state, signin.user).await;
+    }
+    let token = state.ek.sign(signin.user)?;
+    Ok((StatusCode::OK, Json(AuthOutput::new(token))).into_response())
+}
+
+pub(crate) async fn upsert_oidc_provider_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpsertOidcProvider>,
+) -> Result<impl IntoResponse, AppError> {
+    let provider = state
+        .upsert_oidc_provider(user.workspace_id as _, user.id as _, &input)
+        .await?;
+    Ok(Json(provider))
+}
+
+pub(crate) async fn delete_oidc_provider_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_oidc_provider(user.workspace_id as _, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
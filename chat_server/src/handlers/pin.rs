@@ -0,0 +1,34 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+use crate::{AppError, AppState};
+
+pub(crate) async fn list_pins_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.list_pins(id).await?;
+    Ok(Json(pins))
+}
+
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let pin = state.pin_message(id, message_id, user.id as _).await?;
+    Ok(Json(pin))
+}
+
+pub(crate) async fn unpin_message_handler(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.unpin_message(id, message_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,35 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+use crate::{AppError, AppState, CreateReminder};
+
+pub(crate) async fn list_reminders_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let reminders = state.list_reminders(user.id as _).await?;
+    Ok(Json(reminders))
+}
+
+pub(crate) async fn create_reminder_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateReminder>,
+) -> Result<impl IntoResponse, AppError> {
+    let reminder = state.create_message_reminder(user.id as _, &input).await?;
+    Ok((StatusCode::CREATED, Json(reminder)))
+}
+
+pub(crate) async fn delete_reminder_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_reminder(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
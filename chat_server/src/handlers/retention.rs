@@ -0,0 +1,71 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+use crate::{AppError, AppState, CreateLegalHold, WorkspaceFileSettings};
+
+/// Storage used by the workspace, for its owner
+pub(crate) async fn storage_usage_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let usage = state
+        .storage_usage(user.workspace_id as _, user.id as _)
+        .await?;
+    Ok(Json(usage))
+}
+
+pub(crate) async fn get_file_settings_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let settings = state.get_file_settings(user.workspace_id as _).await?;
+    Ok(Json(settings))
+}
+
+pub(crate) async fn update_file_settings_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<WorkspaceFileSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let settings = state
+        .update_file_settings(user.workspace_id as _, user.id as _, &input)
+        .await?;
+    Ok(Json(settings))
+}
+
+pub(crate) async fn list_legal_holds_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let holds = state
+        .list_legal_holds(user.workspace_id as _, user.id as _)
+        .await?;
+    Ok(Json(holds))
+}
+
+pub(crate) async fn create_legal_hold_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateLegalHold>,
+) -> Result<impl IntoResponse, AppError> {
+    let hold = state
+        .create_legal_hold(user.workspace_id as _, user.id as _, &input)
+        .await?;
+    Ok((StatusCode::CREATED, Json(hold)))
+}
+
+pub(crate) async fn delete_legal_hold_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_legal_hold(id, user.workspace_id as _, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,36 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+use crate::{AppError, AppState, ListSavedMessages};
+
+pub(crate) async fn list_saved_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListSavedMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let saved = state.list_saved_messages(input, user.id as _).await?;
+    Ok(Json(saved))
+}
+
+pub(crate) async fn save_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(message_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let saved = state.save_message(message_id, user.id as _).await?;
+    Ok(Json(saved))
+}
+
+pub(crate) async fn unsave_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(message_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.unsave_message(message_id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,50 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+use crate::{AppError, AppState, CreateScheduledMessage, UpdateScheduledMessage};
+
+pub(crate) async fn create_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state
+        .create_scheduled_message(id, user.id as _, input)
+        .await?;
+    Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+pub(crate) async fn list_scheduled_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state.list_scheduled_messages(user.id as _).await?;
+    Ok(Json(scheduled))
+}
+
+pub(crate) async fn update_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state
+        .update_scheduled_message(id, user.id as _, input)
+        .await?;
+    Ok(Json(scheduled))
+}
+
+pub(crate) async fn cancel_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.cancel_scheduled_message(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
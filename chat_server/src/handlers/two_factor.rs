@@ -0,0 +1,33 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+
+use crate::{AppError, AppState, TwoFactorCode};
+use chat_core::User;
+
+pub(crate) async fn enroll_2fa_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let enrollment = state.enroll_totp(&user).await?;
+    Ok((StatusCode::OK, Json(enrollment)))
+}
+
+pub(crate) async fn confirm_2fa_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let now = Utc::now().timestamp() as u64;
+    let codes = state.confirm_totp(&user, &input.code, now).await?;
+    Ok((StatusCode::OK, Json(codes)))
+}
+
+pub(crate) async fn disable_2fa_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let now = Utc::now().timestamp() as u64;
+    state.disable_totp(&user, &input.code, now).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
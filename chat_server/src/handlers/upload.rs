@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::IntoResponse,
+    Extension,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::User;
+
+use crate::{models::TUS_VERSION_HEADER, AppError, AppState, Upload, TUS_VERSION};
+
+const TUS_EXTENSIONS: &str = "creation,expiration";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Tus discovery of the supported version, extensions and size
+pub(crate) async fn upload_options_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, TUS_VERSION.to_string()),
+            ("tus-extension", TUS_EXTENSIONS.to_string()),
+            ("tus-max-size", state.config.files.max_file_size.to_string()),
+        ],
+    )
+}
+
+/// Create an upload with `Upload-Length` and the file name in `Upload-Metadata`
+pub(crate) async fn create_upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    if headers.contains_key("upload-defer-length") {
+        return Err(AppError::UploadError(
+            "Upload-Defer-Length is not supported".to_string(),
+        ));
+    }
+    let Some(length) = header_u64(&headers, "upload-length") else {
+        return Err(AppError::UploadError(
+            "Upload-Length is required".to_string(),
+        ));
+    };
+    let metadata = headers
+        .get("upload-metadata")
+        .and_then(|v| v.to_str().ok())
+        .map(parse_upload_metadata)
+        .transpose()?
+        .unwrap_or_default();
+    let Some(name) = metadata.get("filename").or_else(|| metadata.get("name")) else {
+        return Err(AppError::UploadError(
+            "filename is required in Upload-Metadata".to_string(),
+        ));
+    };
+
+    let content_type = metadata.get("filetype").map(String::as_str);
+    let upload = state
+        .create_upload(
+            user.workspace_id as _,
+            user.id as _,
+            name,
+            content_type,
+            length,
+        )
+        .await?;
+    let mut res_headers = upload_headers(&upload)?;
+    res_headers.insert(
+        LOCATION,
+        HeaderValue::from_str(&format!("/api/uploads/{}", upload.id))?,
+    );
+    Ok((StatusCode::CREATED, res_headers))
+}
+
+/// Where to resume the upload
+pub(crate) async fn upload_offset_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let upload = find_upload(&state, &id, &user).await?;
+    let mut res_headers = upload_headers(&upload)?;
+    res_headers.insert("upload-length", upload.length.into());
+    res_headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok((StatusCode::OK, res_headers))
+}
+
+/// Append the body at `Upload-Offset`
+pub(crate) async fn upload_chunk_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let content_type = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    if content_type != Some(OFFSET_CONTENT_TYPE) {
+        return Err(AppError::UnsupportedMediaType(format!(
+            "Content-Type must be {}",
+            OFFSET_CONTENT_TYPE
+        )));
+    }
+    let Some(offset) = header_u64(&headers, "upload-offset") else {
+        return Err(AppError::UploadError(
+            "Upload-Offset is required".to_string(),
+        ));
+    };
+
+    let upload = find_upload(&state, &id, &user).await?;
+    let upload = state
+        .append_upload(&upload, offset, body.into_data_stream())
+        .await?;
+    Ok((StatusCode::NO_CONTENT, upload_headers(&upload)?))
+}
+
+async fn find_upload(state: &AppState, id: &str, user: &User) -> Result<Upload, AppError> {
+    match state.find_upload(id, user.id as _).await? {
+        Some(upload) => Ok(upload),
+        None => Err(AppError::NotFound(format!("upload {} not found", id))),
+    }
+}
+
+/// The stored file is announced once the upload is complete
+fn upload_headers(upload: &Upload) -> Result<HeaderMap, AppError> {
+    let mut headers = HeaderMap::new();
+    headers.insert("upload-offset", upload.received.into());
+    let expires = upload.expires_at.format("%a, %d %b %Y %H:%M:%S GMT");
+    headers.insert(
+        "upload-expires",
+        HeaderValue::from_str(&expires.to_string())?,
+    );
+    if let Some(file_id) = upload.file_id {
+        headers.insert("upload-file-id", file_id.into());
+    }
+    Ok(headers)
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `Upload-Metadata` is a list of `key base64(value)`, the value is optional
+fn parse_upload_metadata(value: &str) -> Result<HashMap<String, String>, AppError> {
+    let mut metadata = HashMap::new();
+    for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = STANDARD
+                    .decode(value.trim())
+                    .ok()
+                    .and_then(|v| String::from_utf8(v).ok())
+                    .ok_or_else(|| {
+                        AppError::UploadError(format!("invalid Upload-Metadata value of {}", key))
+                    })?;
+                (key, value)
+            }
+            None => (pair, String::new()),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::get_router;
+
+    fn request(method: Method, uri: &str, token: &str) -> axum::http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Tus-Resumable", TUS_VERSION)
+    }
+
+    fn header<'a>(res: &'a axum::response::Response, name: &str) -> &'a str {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn parse_upload_metadata_should_work() -> Result<()> {
+        let metadata =
+            parse_upload_metadata("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential")?;
+        assert_eq!(metadata["filename"], "world_domination_plan.pdf");
+        assert_eq!(metadata["is_confidential"], "");
+        assert!(parse_upload_metadata("filename !!!").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tus_upload_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let app = get_router(state).await?;
+
+        let req = request(Method::OPTIONS, "/api/uploads", &token).body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "tus-version"), TUS_VERSION);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/uploads")
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Upload-Length", "11")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(header(&res, "tus-resumable"), TUS_VERSION);
+
+        let name = STANDARD.encode("hello.txt");
+        let req = request(Method::POST, "/api/uploads", &token)
+            .header("Upload-Length", "11")
+            .header("Upload-Metadata", format!("filename {}", name))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = header(&res, "location").to_string();
+        assert!(location.starts_with("/api/uploads/"));
+
+        let patch = |offset: &str, data: &'static str| -> Result<Request<Body>> {
+            Ok(request(Method::PATCH, &location, &token)
+                .header("Content-Type", OFFSET_CONTENT_TYPE)
+                .header("Upload-Offset", offset)
+                .body(Body::from(data))?)
+        };
+        let res = app.clone().oneshot(patch("0", "hello ")?).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "upload-offset"), "6");
+
+        let req = request(Method::HEAD, &location, &token).body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "upload-offset"), "6");
+        assert_eq!(header(&res, "upload-length"), "11");
+        assert!(!header(&res, "upload-expires").is_empty());
+
+        let res = app.clone().oneshot(patch("0", "world")?).await?;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let req = request(Method::PATCH, &location, &token)
+            .header("Upload-Offset", "6")
+            .body(Body::from("world"))?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let res = app.clone().oneshot(patch("6", "world")?).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header(&res, "upload-offset"), "11");
+        assert!(!header(&res, "upload-file-id").is_empty());
+
+        let req = request(Method::HEAD, "/api/uploads/unknown", &token).body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{extract::State, response::IntoResponse, Extension, Json};
 
-use crate::{AppError, AppState};
+use crate::{AppError, AppState, UpdateWorkspace};
 use chat_core::User;
 
 pub async fn list_chat_users_handler(
@@ -10,3 +10,14 @@ pub async fn list_chat_users_handler(
     let users = state.fetch_chat_users(user.workspace_id as _).await?;
     Ok(Json(users))
 }
+
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .update_workspace(user.workspace_id as _, user.id as _, &input)
+        .await?;
+    Ok(Json(ws))
+}
@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use anyhow::Result;
 use chat_server::{get_router, AppConfig, AppState};
 use tokio::net::TcpListener;
@@ -13,12 +15,22 @@ async fn main() -> Result<()> {
     let addr = format!("0.0.0.0:{}", config.server.port);
 
     let state = AppState::try_new(config).await?;
+    state.spawn_reminder_worker();
+    state.spawn_scheduled_message_worker();
+    state.spawn_upload_cleaner();
+    state.spawn_file_gc();
+    state.spawn_file_rehash();
 
     let app = get_router(state).await?;
     let listener = TcpListener::bind(&addr).await?;
     info!("listening on {}", addr);
 
-    axum::serve(listener, app.into_make_service()).await?;
+    // client address is needed by the rate limiter
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
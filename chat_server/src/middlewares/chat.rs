@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use axum::{
     extract::{FromRequestParts, Path, Request, State},
     middleware::Next,
@@ -9,9 +11,14 @@ use chat_core::User;
 
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    // nested routes have more params than the chat id, e.g. /:id/webhooks/:hook_id
+    let params = Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state).await;
+    let Some(chat_id) = params
+        .ok()
+        .and_then(|Path(p)| p.get("id").and_then(|id| id.parse::<u64>().ok()))
+    else {
+        return AppError::NotFound("chat id is missing or invalid".to_string()).into_response();
+    };
 
     let user = parts.extensions.get::<User>().unwrap();
 
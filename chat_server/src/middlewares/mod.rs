@@ -1,3 +1,7 @@
 mod chat;
+mod scope;
+mod tus;
 
 pub use chat::verify_chat;
+pub use scope::verify_scope;
+pub use tus::verify_tus_version;
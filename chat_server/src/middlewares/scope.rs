@@ -0,0 +1,116 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppError;
+use chat_core::{TokenScope, User};
+
+/// Api tokens can only reach the endpoints listed in `required_scope`,
+/// and only with the matching scope. Session tokens are not restricted.
+pub async fn verify_scope(req: Request, next: Next) -> Response {
+    let scopes = req
+        .extensions()
+        .get::<User>()
+        .and_then(|u| u.scopes.as_ref());
+
+    if let Some(scopes) = scopes {
+        let path = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|p| p.as_str().trim_end_matches('/'))
+            .unwrap_or_default();
+
+        match required_scope(req.method(), path) {
+            Some(scope) if scopes.contains(&scope) => {}
+            Some(scope) => {
+                let msg = format!("api token is missing scope {}", scope.as_str());
+                return AppError::PermissionDenied(msg).into_response();
+            }
+            None => {
+                let msg = "api tokens can't access this endpoint".to_string();
+                return AppError::PermissionDenied(msg).into_response();
+            }
+        }
+    }
+
+    next.run(req).await
+}
+
+fn required_scope(method: &Method, path: &str) -> Option<TokenScope> {
+    match (method.as_str(), path) {
+        ("GET", "/api/users" | "/api/chats" | "/api/chats/:id") => Some(TokenScope::ReadChats),
+        (
+            "GET",
+            "/api/chats/:id/messages"
+            | "/api/chats/:id/pins"
+            | "/api/chats/:id/bookmarks"
+            | "/api/mentions"
+            | "/api/files/:workspace_id/*path",
+        )
+        | ("POST", "/api/files/sign") => Some(TokenScope::ReadMessages),
+        (
+            "POST",
+            "/api/chats/:id" | "/api/chats/:id/bookmarks" | "/api/upload" | "/api/uploads",
+        )
+        | ("PUT" | "DELETE", "/api/chats/:id/pins/:message_id")
+        | ("PATCH" | "DELETE", "/api/chats/:id/bookmarks/:bookmark_id")
+        | ("OPTIONS", "/api/uploads")
+        | ("HEAD" | "PATCH", "/api/uploads/:id") => Some(TokenScope::WriteMessages),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_router, AppState, CreateApiToken};
+
+    use anyhow::Result;
+    use axum::{body::Body, http::StatusCode};
+    use tower::ServiceExt;
+
+    fn get(uri: &str, token: &str) -> Result<Request> {
+        let req = Request::builder()
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        Ok(req)
+    }
+
+    #[tokio::test]
+    async fn verify_scope_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = CreateApiToken::new("ci", &[TokenScope::ReadChats]);
+        let token = state.create_api_token(&input, &user, 1).await?.token;
+        let session = state.ek.sign(user)?;
+        let app = get_router(state).await?;
+
+        let res = app.clone().oneshot(get("/api/chats", &token)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(get("/api/chats/1", &token)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // missing scope
+        let res = app
+            .clone()
+            .oneshot(get("/api/chats/1/messages?limit=1", &token)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // not reachable with api tokens at all
+        let res = app.clone().oneshot(get("/api/tokens", &token)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.clone().oneshot(get("/api/tokens", &session)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // unknown api token
+        let res = app.oneshot(get("/api/chats", "chat_pat_bad")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use axum::{
+    extract::Request,
+    http::{HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    models::{TUS_RESUMABLE_HEADER, TUS_VERSION},
+    AppError,
+};
+
+/// Tus requests must announce the version we speak, except the `OPTIONS`
+/// discovery, and every response announces it back
+pub async fn verify_tus_version(req: Request, next: Next) -> Response {
+    let version = req
+        .headers()
+        .get(TUS_RESUMABLE_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let mut res = match version {
+        _ if req.method() == Method::OPTIONS => next.run(req).await,
+        Some(TUS_VERSION) => next.run(req).await,
+        version => {
+            let msg = format!("Tus-Resumable is {}", version.unwrap_or("missing"));
+            AppError::TusVersionMismatch(msg).into_response()
+        }
+    };
+    res.headers_mut()
+        .insert(TUS_RESUMABLE_HEADER, HeaderValue::from_static(TUS_VERSION));
+    res
+}
@@ -0,0 +1,256 @@
+use std::str::FromStr;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chat_core::{middlewares::TokenVerify, TokenScope, User};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+
+use crate::{AppError, AppState};
+
+/// Personal access tokens are opaque, this tells them apart from jwt
+pub const API_TOKEN_PREFIX: &str = "chat_pat_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateApiToken {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returned once on creation, the plain token can't be retrieved afterwards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiToken,
+}
+
+#[derive(Debug, FromRow)]
+struct ApiTokenUser {
+    id: i64,
+    workspace_id: i64,
+    fullname: String,
+    email: String,
+    created_at: DateTime<Utc>,
+    scopes: Vec<String>,
+}
+
+/// Verify personal access tokens, used next to the jwt verification of `AppState`
+#[derive(Debug, Clone)]
+pub struct ApiTokenVerifier {
+    pool: PgPool,
+}
+
+impl ApiTokenVerifier {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl TokenVerify for ApiTokenVerifier {
+    type Error = AppError;
+
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        let user: Option<ApiTokenUser> = sqlx::query_as(
+            r#"
+            WITH t AS (
+                UPDATE api_tokens
+                SET last_used_at = CURRENT_TIMESTAMP
+                WHERE token_hash = $1
+                AND revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                RETURNING user_id, scopes
+            )
+            SELECT u.id, u.workspace_id, u.fullname, u.email, u.created_at, t.scopes
+            FROM t
+            JOIN users u ON u.id = t.user_id
+            "#,
+        )
+        .bind(hash_api_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(u) = user else {
+            return Err(AppError::PermissionDenied("invalid api token".to_string()));
+        };
+
+        let mut user = User::new(u.id, u.workspace_id, &u.fullname, &u.email);
+        user.created_at = u.created_at;
+        // scopes are validated on creation, unknown ones are simply dropped
+        let scopes = u.scopes.iter().filter_map(|s| TokenScope::from_str(s).ok());
+        user.scopes = Some(scopes.collect());
+        Ok(user)
+    }
+}
+
+impl AppState {
+    /// Create a token for the user itself or for a bot the user owns
+    pub async fn create_api_token(
+        &self,
+        input: &CreateApiToken,
+        owner: &User,
+        user_id: u64,
+    ) -> Result<CreatedApiToken, AppError> {
+        if input.name.is_empty() || input.scopes.is_empty() {
+            return Err(AppError::ApiTokenError(
+                "token must have a name and at least one scope".to_string(),
+            ));
+        }
+        if owner.id as u64 != user_id {
+            self.verify_bot_owner(user_id, owner.id as _).await?;
+        }
+
+        let mut buf = [0u8; 32];
+        OsRng.fill_bytes(&mut buf);
+        let token = format!("{}{}", API_TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(buf));
+        let scopes: Vec<&str> = input.scopes.iter().map(|s| s.as_str()).collect();
+        let expires_at = input
+            .expires_in_days
+            .map(|days| Utc::now() + Duration::days(days as _));
+
+        let info = sqlx::query_as(
+            r#"
+            INSERT INTO api_tokens (user_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, name, scopes, expires_at, last_used_at, created_at
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(&input.name)
+        .bind(hash_api_token(&token))
+        .bind(&scopes)
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedApiToken { token, info })
+    }
+
+    pub async fn list_api_tokens(&self, user_id: u64) -> Result<Vec<ApiToken>, AppError> {
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT id, user_id, name, scopes, expires_at, last_used_at, created_at
+            FROM api_tokens
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Revoke a token of the user or of one of the bots it owns
+    pub async fn revoke_api_token(&self, id: u64, owner_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE api_tokens t
+            SET revoked_at = CURRENT_TIMESTAMP
+            FROM users u
+            WHERE t.id = $1 AND u.id = t.user_id AND t.revoked_at IS NULL
+            AND (u.id = $2 OR u.bot_owner_id = $2)
+            "#,
+        )
+        .bind(id as i64)
+        .bind(owner_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("api token id {} not found", id)));
+        }
+        Ok(())
+    }
+}
+
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+impl CreateApiToken {
+    pub fn new(name: &str, scopes: &[TokenScope]) -> Self {
+        Self {
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            expires_in_days: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn api_token_create_verify_and_revoke_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        let input = CreateApiToken::new("ci", &[TokenScope::ReadChats]);
+        let created = state.create_api_token(&input, &user, 1).await?;
+        assert!(created.token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(created.info.scopes, vec!["read:chats"]);
+
+        let verifier = ApiTokenVerifier::new(state.pool.clone());
+        let verified = verifier.verify(&created.token).await?;
+        assert_eq!(verified.id, 1);
+        assert!(verified.has_scope(TokenScope::ReadChats));
+        assert!(!verified.has_scope(TokenScope::WriteMessages));
+
+        let tokens = state.list_api_tokens(1).await?;
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used_at.is_some());
+
+        // other users can't revoke it
+        assert!(state
+            .revoke_api_token(created.info.id as _, 2)
+            .await
+            .is_err());
+        state.revoke_api_token(created.info.id as _, 1).await?;
+        assert!(verifier.verify(&created.token).await.is_err());
+        assert!(state.list_api_tokens(1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_token_for_bot_should_require_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let other = state.find_user_by_id(2).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+
+        let input = CreateApiToken::new("ci", &[TokenScope::WriteMessages]);
+        let ret = state.create_api_token(&input, &other, bot.id as _).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let created = state.create_api_token(&input, &user, bot.id as _).await?;
+        let verifier = ApiTokenVerifier::new(state.pool.clone());
+        assert_eq!(verifier.verify(&created.token).await?.id, bot.id);
+
+        // bot owner can revoke it
+        state.revoke_api_token(created.info.id as _, 1).await?;
+        assert!(verifier.verify(&created.token).await.is_err());
+
+        Ok(())
+    }
+}
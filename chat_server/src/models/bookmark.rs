@@ -0,0 +1,331 @@
+use std::str::FromStr;
+
+use chat_core::{Bookmark, ScanStatus};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{AppError, AppState, ChatFile, FileRef};
+
+const MAX_BOOKMARK_TITLE_LEN: usize = 128;
+
+/// Either a link or a file, files become downloadable by every member
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateBookmark {
+    pub title: String,
+    #[serde(default)]
+    pub link: Option<String>,
+    #[serde(default)]
+    pub file: Option<FileRef>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateBookmark {
+    pub title: Option<String>,
+    /// only for link bookmarks, a file is replaced by deleting its bookmark
+    pub link: Option<String>,
+}
+
+impl AppState {
+    pub async fn create_bookmark(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        input: CreateBookmark,
+    ) -> Result<Bookmark, AppError> {
+        let title = validate_title(&input.title)?;
+        let (link, file_url) = match (&input.link, &input.file) {
+            (Some(link), None) => (Some(validate_link(link)?), None),
+            (None, Some(file)) => (
+                None,
+                Some(self.bookmark_file(chat_id, user_id, file).await?),
+            ),
+            _ => {
+                return Err(AppError::BookmarkError(
+                    "a bookmark needs either a link or a file".to_string(),
+                ))
+            }
+        };
+
+        let mut tx = self.pool.begin().await?;
+        // serializes bookmarks of the chat, so the cap can't be raced past
+        let exists: Option<i64> =
+            sqlx::query_scalar("SELECT id FROM chats WHERE id = $1 FOR UPDATE")
+                .bind(chat_id as i64)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if exists.is_none() {
+            return Err(AppError::NotFound(format!("chat id {} not found", chat_id)));
+        }
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM chat_bookmarks WHERE chat_id = $1")
+                .bind(chat_id as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+        let max_bookmarks = self.config.chats.max_bookmarks;
+        if count as u64 >= max_bookmarks {
+            return Err(AppError::BookmarkError(format!(
+                "a chat can have at most {} bookmarks",
+                max_bookmarks
+            )));
+        }
+
+        let bookmark: Bookmark = sqlx::query_as(
+            r#"
+            INSERT INTO chat_bookmarks (chat_id, title, link, file_url, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, chat_id, title, link, file_url, created_by, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(title)
+        .bind(&link)
+        .bind(&file_url)
+        .bind(user_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if let Some(url) = &file_url {
+            sqlx::query(
+                "INSERT INTO chat_files (chat_id, url) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            )
+            .bind(chat_id as i64)
+            .bind(url)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(bookmark)
+    }
+
+    pub async fn list_bookmarks(&self, chat_id: u64) -> Result<Vec<Bookmark>, AppError> {
+        let bookmarks = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, title, link, file_url, created_by, created_at
+            FROM chat_bookmarks
+            WHERE chat_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bookmarks)
+    }
+
+    pub async fn update_bookmark(
+        &self,
+        id: u64,
+        chat_id: u64,
+        input: UpdateBookmark,
+    ) -> Result<Bookmark, AppError> {
+        let title = input.title.as_deref().map(validate_title).transpose()?;
+        let link = input.link.as_deref().map(validate_link).transpose()?;
+
+        let bookmark: Option<Bookmark> = sqlx::query_as(
+            r#"
+            UPDATE chat_bookmarks
+            SET title = COALESCE($3, title), link = COALESCE($4, link)
+            WHERE id = $1 AND chat_id = $2 AND ($4::TEXT IS NULL OR file_url IS NULL)
+            RETURNING id, chat_id, title, link, file_url, created_by, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .bind(title)
+        .bind(&link)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(bookmark) = bookmark {
+            return Ok(bookmark);
+        }
+
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM chat_bookmarks WHERE id = $1 AND chat_id = $2)",
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        if exists {
+            Err(AppError::BookmarkError(format!(
+                "bookmark {} is a file, not a link",
+                id
+            )))
+        } else {
+            Err(AppError::NotFound(format!("bookmark {} not found", id)))
+        }
+    }
+
+    pub async fn delete_bookmark(&self, id: u64, chat_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM chat_bookmarks WHERE id = $1 AND chat_id = $2")
+            .bind(id as i64)
+            .bind(chat_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("bookmark {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// The url of a file the user may share in the chat, as for attachments
+    async fn bookmark_file(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        file: &FileRef,
+    ) -> Result<String, AppError> {
+        let url = match file {
+            FileRef::Id(id) => {
+                let row: Option<(String, ScanStatus)> = sqlx::query_as(
+                    r#"
+                    SELECT f.url, f.scan_status
+                    FROM files f
+                    JOIN chats c ON c.workspace_id = f.workspace_id
+                    WHERE c.id = $1 AND f.id = $2
+                    "#,
+                )
+                .bind(chat_id as i64)
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+                match row {
+                    Some((_, ScanStatus::Infected)) => {
+                        return Err(AppError::BookmarkError(format!(
+                            "File {} is quarantined",
+                            id
+                        )))
+                    }
+                    Some((url, _)) => url,
+                    None => {
+                        return Err(AppError::BookmarkError(format!(
+                            "File {} does not exist",
+                            id
+                        )))
+                    }
+                }
+            }
+            FileRef::Url(url) => {
+                ChatFile::from_str(url)?;
+                url.clone()
+            }
+        };
+        if !self.can_access_file(user_id, &url).await? {
+            return Err(AppError::BookmarkError(format!(
+                "File {} does not exist",
+                url
+            )));
+        }
+        Ok(url)
+    }
+}
+
+fn validate_title(title: &str) -> Result<&str, AppError> {
+    let title = title.trim();
+    if title.is_empty() || title.chars().count() > MAX_BOOKMARK_TITLE_LEN {
+        return Err(AppError::BookmarkError(format!(
+            "bookmark title must be 1 to {} characters",
+            MAX_BOOKMARK_TITLE_LEN
+        )));
+    }
+    Ok(title)
+}
+
+fn validate_link(link: &str) -> Result<String, AppError> {
+    match Url::parse(link) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(link.to_string()),
+        _ => Err(AppError::BookmarkError(format!("invalid link: {}", link))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn bookmark_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateBookmark {
+            title: "Runbook".to_string(),
+            link: Some("https://example.com/runbook".to_string()),
+            file: None,
+        };
+        let link = state.create_bookmark(1, 1, input).await?;
+        assert_eq!(link.file_url, None);
+
+        // user 1 uploaded it, user 2 can download it once bookmarked in chat 1
+        let file = state.create_file(1, 1, "plan.txt", b"the plan").await?;
+        assert!(!state.can_access_file(2, &file.url).await?);
+        let input = CreateBookmark {
+            title: "Plan".to_string(),
+            link: None,
+            file: Some(FileRef::Id(file.id)),
+        };
+        let bookmark = state.create_bookmark(1, 1, input).await?;
+        assert_eq!(bookmark.file_url.as_deref(), Some(file.url.as_str()));
+        assert!(state.can_access_file(2, &file.url).await?);
+
+        let input = UpdateBookmark {
+            title: Some(" On-call runbook ".to_string()),
+            link: None,
+        };
+        let updated = state.update_bookmark(link.id as _, 1, input).await?;
+        assert_eq!(updated.title, "On-call runbook");
+        assert_eq!(updated.link, link.link);
+        let input = UpdateBookmark {
+            title: None,
+            link: Some("https://example.com".to_string()),
+        };
+        let ret = state.update_bookmark(bookmark.id as _, 1, input).await;
+        assert!(matches!(ret, Err(AppError::BookmarkError(_))));
+
+        let bookmarks = state.list_bookmarks(1).await?;
+        assert_eq!(bookmarks, vec![updated, bookmark.clone()]);
+
+        // bookmarks belong to their chat
+        let ret = state.delete_bookmark(bookmark.id as _, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        state.delete_bookmark(bookmark.id as _, 1).await?;
+        assert_eq!(state.list_bookmarks(1).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_bookmark_should_validate_input() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        config.chats.max_bookmarks = 1;
+        let (_tdb, state) = AppState::new_for_test_with_config(config).await?;
+        let input = |link: Option<&str>, file| CreateBookmark {
+            title: "Docs".to_string(),
+            link: link.map(|s| s.to_string()),
+            file,
+        };
+
+        for (link, file) in [
+            (None, None),
+            (Some("javascript:alert(1)"), None),
+            (Some("https://example.com"), Some(FileRef::Id(1))),
+        ] {
+            let ret = state.create_bookmark(1, 1, input(link, file)).await;
+            assert!(matches!(ret, Err(AppError::BookmarkError(_))));
+        }
+        // only files the user can access can be bookmarked
+        let file = state.create_file(1, 2, "secret.txt", b"secret").await?;
+        let ret = state
+            .create_bookmark(1, 1, input(None, Some(FileRef::Url(file.url))))
+            .await;
+        assert!(matches!(ret, Err(AppError::BookmarkError(_))));
+
+        state
+            .create_bookmark(1, 1, input(Some("https://example.com"), None))
+            .await?;
+        let ret = state
+            .create_bookmark(1, 1, input(Some("https://example.org"), None))
+            .await;
+        assert!(matches!(ret, Err(AppError::BookmarkError(_))));
+        Ok(())
+    }
+}
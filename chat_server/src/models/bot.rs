@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AppError, AppState};
+
+use chat_core::{ChatUser, User};
+
+const MAX_BOT_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateBot {
+    pub name: String,
+}
+
+impl AppState {
+    /// Create a bot user in the owner's workspace, bots can't sign in with a password
+    pub async fn create_bot(&self, name: &str, owner: &User) -> Result<ChatUser, AppError> {
+        // the name is part of the bot's email
+        if !is_valid_bot_name(name) {
+            return Err(AppError::CreateBotError(format!(
+                "Bot name must be 1 to {} lowercase letters, digits or dashes: {}",
+                MAX_BOT_NAME_LEN, name
+            )));
+        }
+        let taken = sqlx::query("SELECT 1 FROM users WHERE fullname = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        if taken.is_some() {
+            return Err(AppError::CreateBotError(format!(
+                "Name {} is already taken",
+                name
+            )));
+        }
+
+        let bot = sqlx::query_as(
+            r#"
+            INSERT INTO users (workspace_id, workspace, fullname, email, is_bot, bot_owner_id)
+            SELECT workspace_id, workspace, $2, $3, TRUE, id
+            FROM users
+            WHERE id = $1
+            RETURNING id, fullname, email, is_bot, created_at
+            "#,
+        )
+        .bind(owner.id)
+        .bind(name)
+        .bind(format!("{}@bot.{}.local", name, owner.workspace_id))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(bot)
+    }
+
+    pub async fn list_bots(&self, owner_id: u64) -> Result<Vec<ChatUser>, AppError> {
+        let bots = sqlx::query_as(
+            r#"
+            SELECT id, fullname, email, is_bot, created_at
+            FROM users
+            WHERE bot_owner_id = $1 AND is_bot
+            ORDER BY id
+            "#,
+        )
+        .bind(owner_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bots)
+    }
+
+    pub async fn verify_bot_owner(&self, bot_id: u64, owner_id: u64) -> Result<(), AppError> {
+        let owned =
+            sqlx::query("SELECT 1 FROM users WHERE id = $1 AND is_bot AND bot_owner_id = $2")
+                .bind(bot_id as i64)
+                .bind(owner_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        match owned {
+            Some(_) => Ok(()),
+            None => Err(AppError::PermissionDenied(format!(
+                "user {} doesn't own bot {}",
+                owner_id, bot_id
+            ))),
+        }
+    }
+}
+
+fn is_valid_bot_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_BOT_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::SigninUser;
+
+    #[tokio::test]
+    async fn create_and_list_bots_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        let bot = state.create_bot("ci-bot", &user).await?;
+        assert!(bot.is_bot);
+
+        let long = "b".repeat(MAX_BOT_NAME_LEN + 1);
+        for name in ["", "CI", "ci bot", "x@evil.com", "ci.bot", long.as_str()] {
+            let ret = state.create_bot(name, &user).await;
+            assert!(matches!(ret, Err(AppError::CreateBotError(_))), "{}", name);
+        }
+
+        // names are unique across users and bots
+        let ret = state.create_bot("wiki", &user).await;
+        assert!(matches!(ret, Err(AppError::CreateBotError(_))));
+
+        let bots = state.list_bots(1).await?;
+        assert_eq!(bots, vec![bot.clone()]);
+        assert!(state.list_bots(2).await?.is_empty());
+
+        // bot shows up as a workspace member and can't sign in
+        let users = state.fetch_chat_users(1).await?;
+        assert!(users.iter().any(|u| u.id == bot.id && u.is_bot));
+        let input = SigninUser::new(&bot.email, "");
+        assert!(state.verify_user(&input).await?.is_none());
+
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::{AppError, AppState};
@@ -55,7 +56,7 @@ impl AppState {
             r#"
                     INSERT INTO chats (workspace_id, name, type, members)
                     VALUES ($1, $2, $3, $4)
-                    RETURNING id, workspace_id, name, type, members, created_at
+                    RETURNING id, workspace_id, name, topic, type, members, created_at
                     "#,
         )
         .bind(workspace_id as i64)
@@ -71,7 +72,7 @@ impl AppState {
     pub async fn fetch_chats(&self, workspace_id: u64) -> Result<Vec<Chat>, AppError> {
         let chats = sqlx::query_as(
             r#"
-                SELECT id, workspace_id, name, type, members, created_at
+                SELECT id, workspace_id, name, topic, type, members, created_at
                 FROM chats
                 WHERE workspace_id = $1
                 ORDER BY created_at DESC
@@ -87,7 +88,7 @@ impl AppState {
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             r#"
-                SELECT id, workspace_id, name, type, members, created_at
+                SELECT id, workspace_id, name, topic, type, members, created_at
                 FROM chats
                 WHERE id = $1
             "#,
@@ -114,6 +115,106 @@ impl AppState {
 
         Ok(is_member.is_some())
     }
+
+    pub async fn set_chat_topic(&self, id: u64, topic: Option<&str>) -> Result<Chat, AppError> {
+        let chat = sqlx::query_as(
+            r#"
+                UPDATE chats
+                SET topic = $2
+                WHERE id = $1
+                RETURNING id, workspace_id, name, topic, type, members, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(topic)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        chat.ok_or_else(|| AppError::NotFound(format!("chat id {} not found", id)))
+    }
+
+    /// Add a member, returns false if the user is already in the chat
+    pub async fn add_chat_member(&self, id: u64, user_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+                UPDATE chats
+                SET members = array_append(members, $2)
+                WHERE id = $1 AND type != 'single' AND NOT ($2 = ANY(members))
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    pub async fn remove_chat_member(&self, id: u64, user_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+                UPDATE chats
+                SET members = array_remove(members, $2)
+                WHERE id = $1 AND type != 'single' AND $2 = ANY(members)
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Mute the chat for the user, until it's unmuted if `until` is None
+    pub async fn mute_chat(
+        &self,
+        id: u64,
+        user_id: u64,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+                INSERT INTO chat_mutes (chat_id, user_id, muted_until)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (chat_id, user_id) DO UPDATE SET muted_until = EXCLUDED.muted_until
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .bind(until)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn unmute_chat(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM chat_mutes WHERE chat_id = $1 AND user_id = $2")
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_chat_muted(&self, id: u64, user_id: u64) -> Result<bool, AppError> {
+        let muted = sqlx::query(
+            r#"
+                SELECT 1
+                FROM chat_mutes
+                WHERE chat_id = $1 AND user_id = $2
+                AND (muted_until IS NULL OR muted_until > CURRENT_TIMESTAMP)
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(muted.is_some())
+    }
 }
 
 #[cfg(test)]
@@ -135,9 +236,14 @@ impl CreateChat {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use anyhow::Result;
+    use chat_core::MessageFormat;
+    use sqlx::postgres::PgListener;
 
     use super::*;
+    use crate::CreateMessage;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> Result<()> {
@@ -234,4 +340,41 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn muted_members_should_only_be_notified_of_mentions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener
+            .listen_all(["chat_message_created", "mention_created"])
+            .await?;
+
+        state.mute_chat(1, 2, None).await?;
+        // an expired mute doesn't count
+        let until = Utc::now() - chrono::Duration::hours(1);
+        state.mute_chat(1, 3, Some(until)).await?;
+        let input = CreateMessage {
+            content: "@channel deploy is done".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        state.create_message(input, 1, 1).await?;
+
+        let mut members: Vec<i64> = vec![];
+        let mut mentioned = vec![];
+        while let Ok(notif) = tokio::time::timeout(Duration::from_secs(1), listener.recv()).await {
+            let payload: serde_json::Value = serde_json::from_str(notif?.payload())?;
+            match payload.get("members") {
+                Some(v) => members = serde_json::from_value(v.clone())?,
+                None => mentioned.push(payload["mention"]["user_id"].as_i64().unwrap_or_default()),
+            }
+        }
+        mentioned.sort();
+        assert_eq!(members, vec![1, 3, 4, 5]);
+        // mentions still reach muted members
+        assert_eq!(mentioned, vec![2, 3, 4]);
+        Ok(())
+    }
 }
@@ -0,0 +1,796 @@
+use std::time::{Duration as StdDuration, SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::http::header::CONTENT_TYPE;
+use chat_core::{
+    resolve_public_addrs, sign_payload, Chat, ChatType, Message, MessageFormat, User,
+    SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use url::{Host, Url};
+
+use crate::{AppError, AppState, CreateMessage};
+
+/// Built-in commands as (name, usage, description)
+const BUILTIN_COMMANDS: &[(&str, &str, &str)] = &[
+    ("invite", "@user", "Invite a user to this chat"),
+    ("leave", "", "Leave this chat"),
+    ("me", "<action>", "Post an action in the third person"),
+    (
+        "mute",
+        "[duration]",
+        "Mute this chat, run it again to unmute",
+    ),
+    (
+        "remind",
+        "<duration> <what>",
+        "Get a direct message reminder, e.g. /remind 10m stand-up",
+    ),
+    ("topic", "[topic]", "Set or clear the topic of this chat"),
+];
+
+// bots have to answer quickly, the user is waiting for the response
+const BOT_COMMAND_TIMEOUT: StdDuration = StdDuration::from_secs(3);
+const MAX_COMMAND_NAME_LEN: usize = 32;
+// longest /remind or /mute, anything longer is a typo
+const MAX_COMMAND_DURATION_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseType {
+    /// only shown to the user who ran the command
+    #[default]
+    Ephemeral,
+    InChannel,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandResponse {
+    pub response_type: ResponseType,
+    pub text: String,
+    /// the message posted to the chat for in channel responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Message>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterCommand {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub usage: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct CustomCommand {
+    pub id: i64,
+    pub workspace_id: i64,
+    pub bot_id: i64,
+    pub name: String,
+    pub description: String,
+    pub usage: String,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returned once on registration, the secret signs every command request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredCommand {
+    pub secret: String,
+    #[serde(flatten)]
+    pub info: CustomCommand,
+}
+
+/// Autocomplete entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandInfo {
+    pub name: String,
+    pub usage: String,
+    pub description: String,
+    /// the bot handling the command, None for built-in commands
+    pub bot_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListCommands {
+    pub prefix: Option<String>,
+}
+
+/// Request posted to the url of a custom command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotCommandRequest {
+    pub command: String,
+    pub text: String,
+    pub user_id: i64,
+    pub user_name: String,
+    pub chat_id: i64,
+    pub workspace_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BotCommandReply {
+    #[serde(default)]
+    response_type: ResponseType,
+    #[serde(default)]
+    text: String,
+}
+
+/// Split `/name args` into its parts, anything else isn't a command
+fn parse_command(content: &str) -> Option<(String, &str)> {
+    let rest = content.strip_prefix('/')?;
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((name.to_lowercase(), args.trim()))
+}
+
+/// Parse durations like 30s, 10m, 2h, 1d or 1w, up to a year
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let unit = s.chars().last()?;
+    let n: i64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    if n <= 0 {
+        return None;
+    }
+    let duration = match unit {
+        's' => Duration::try_seconds(n),
+        'm' => Duration::try_minutes(n),
+        'h' => Duration::try_hours(n),
+        'd' => Duration::try_days(n),
+        'w' => Duration::try_weeks(n),
+        _ => None,
+    }?;
+    (duration <= Duration::days(MAX_COMMAND_DURATION_DAYS)).then_some(duration)
+}
+
+/// The time the duration from now ends at, a usage error if it's out of range
+fn parse_deadline(name: &str, s: &str) -> Result<DateTime<Utc>, AppError> {
+    parse_duration(s)
+        .and_then(|after| Utc::now().checked_add_signed(after))
+        .ok_or_else(|| usage_error(name))
+}
+
+fn usage_error(name: &str) -> AppError {
+    let usage = BUILTIN_COMMANDS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, usage, _)| *usage)
+        .unwrap_or_default();
+    AppError::CommandError(format!("usage: /{} {}", name, usage))
+}
+
+impl CommandResponse {
+    fn ephemeral(text: impl Into<String>) -> Self {
+        Self {
+            response_type: ResponseType::Ephemeral,
+            text: text.into(),
+            message: None,
+        }
+    }
+
+    fn in_channel(message: Message) -> Self {
+        Self {
+            response_type: ResponseType::InChannel,
+            text: message.content.clone(),
+            message: Some(message),
+        }
+    }
+}
+
+impl AppState {
+    /// Run the command if the message is one, it isn't stored as a message then
+    pub async fn run_command(
+        &self,
+        input: &CreateMessage,
+        chat_id: u64,
+        user: &User,
+    ) -> Result<Option<CommandResponse>, AppError> {
+        let Some((name, args)) = parse_command(&input.content) else {
+            return Ok(None);
+        };
+        // api tokens may post messages, but not change the chat itself
+        if user.scopes.is_some() && matches!(name.as_str(), "invite" | "leave" | "topic") {
+            return Err(AppError::PermissionDenied(format!(
+                "/{} is not available to api tokens",
+                name
+            )));
+        }
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {} not found", chat_id)))?;
+
+        let res = match name.as_str() {
+            "me" => {
+                if args.is_empty() {
+                    return Err(usage_error("me"));
+                }
+                let content = format!("_{} {}_", user.fullname, args);
+                let message = self
+                    .post_as(user, chat_id, content, MessageFormat::Markdown)
+                    .await?;
+                CommandResponse::in_channel(message)
+            }
+            "topic" => {
+                let topic = (!args.is_empty()).then_some(args);
+                self.set_chat_topic(chat_id, topic).await?;
+                let content = match topic {
+                    Some(topic) => format!("set the topic: {}", topic),
+                    None => "cleared the topic".to_string(),
+                };
+                let message = self
+                    .post_as(user, chat_id, content, MessageFormat::Plain)
+                    .await?;
+                CommandResponse::in_channel(message)
+            }
+            "invite" => self.invite_command(&chat, user, args).await?,
+            "leave" => {
+                if chat.r#type == ChatType::Single {
+                    return Err(AppError::CommandError(
+                        "can't leave a direct message".to_string(),
+                    ));
+                }
+                self.remove_chat_member(chat_id, user.id as _).await?;
+                let name = chat.name.as_deref().unwrap_or("the chat");
+                CommandResponse::ephemeral(format!("You left {}", name))
+            }
+            "remind" => {
+                let args = args.strip_prefix("in ").unwrap_or(args);
+                let (when, what) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                let what = what.trim();
+                if what.is_empty() {
+                    return Err(usage_error("remind"));
+                }
+                let remind_at = parse_deadline("remind", when)?;
+                self.create_reminder(user.id as _, chat_id, what, remind_at)
+                    .await?;
+                CommandResponse::ephemeral(format!("I will remind you in {}: {}", when, what))
+            }
+            "mute" => {
+                if args.is_empty() && self.is_chat_muted(chat_id, user.id as _).await? {
+                    self.unmute_chat(chat_id, user.id as _).await?;
+                    CommandResponse::ephemeral("Chat unmuted")
+                } else if args.is_empty() {
+                    self.mute_chat(chat_id, user.id as _, None).await?;
+                    CommandResponse::ephemeral("Chat muted, run /mute again to unmute")
+                } else {
+                    let until = parse_deadline("mute", args)?;
+                    self.mute_chat(chat_id, user.id as _, Some(until)).await?;
+                    CommandResponse::ephemeral(format!("Chat muted for {}", args))
+                }
+            }
+            _ => self.run_custom_command(&name, args, &chat, user).await?,
+        };
+
+        Ok(Some(res))
+    }
+
+    async fn invite_command(
+        &self,
+        chat: &Chat,
+        user: &User,
+        args: &str,
+    ) -> Result<CommandResponse, AppError> {
+        let name = args.strip_prefix('@').unwrap_or(args);
+        if name.is_empty() {
+            return Err(usage_error("invite"));
+        }
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::CommandError(
+                "can't invite to a direct message".to_string(),
+            ));
+        }
+        let Some(invitee) = self
+            .find_chat_user_by_fullname(user.workspace_id as _, name)
+            .await?
+        else {
+            return Err(AppError::NotFound(format!("user {} not found", name)));
+        };
+
+        if !self.add_chat_member(chat.id as _, invitee.id as _).await? {
+            return Ok(CommandResponse::ephemeral(format!(
+                "{} is already in this chat",
+                invitee.fullname
+            )));
+        }
+        let content = format!("invited @{}", invitee.fullname);
+        let message = self
+            .post_as(user, chat.id as _, content, MessageFormat::Plain)
+            .await?;
+        Ok(CommandResponse::in_channel(message))
+    }
+
+    /// Forward the command to the bot owning it and post its reply
+    async fn run_custom_command(
+        &self,
+        name: &str,
+        args: &str,
+        chat: &Chat,
+        user: &User,
+    ) -> Result<CommandResponse, AppError> {
+        let command: Option<(i64, String, String)> = sqlx::query_as(
+            "SELECT bot_id, url, secret FROM slash_commands WHERE workspace_id = $1 AND name = $2",
+        )
+        .bind(user.workspace_id)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((bot_id, url, secret)) = command else {
+            return Err(AppError::CommandError(format!("unknown command /{}", name)));
+        };
+        // host names are checked by the resolver of the client, ips here
+        let url = Url::parse(&url).map_err(|e| AppError::CommandError(e.to_string()))?;
+        if let Some(Host::Ipv4(_) | Host::Ipv6(_)) = url.host() {
+            self.check_command_host(&url).await?;
+        }
+
+        let req = BotCommandRequest {
+            command: format!("/{}", name),
+            text: args.to_string(),
+            user_id: user.id,
+            user_name: user.fullname.clone(),
+            chat_id: chat.id,
+            workspace_id: user.workspace_id,
+        };
+        let payload =
+            serde_json::to_string(&req).map_err(|e| AppError::CommandError(e.to_string()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let reply: BotCommandReply = self
+            .bot_http
+            .post(url)
+            .timeout(BOT_COMMAND_TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign_payload(&secret, timestamp, &payload))
+            .body(payload)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        match reply.response_type {
+            ResponseType::InChannel if !reply.text.is_empty() => {
+                let input = CreateMessage {
+                    content: reply.text,
+                    format: MessageFormat::Markdown,
+                    files: vec![],
+                    sender_name: None,
+                    client_msg_id: None,
+                };
+                let message = self
+                    .create_message(input, chat.id as _, bot_id as _)
+                    .await?;
+                Ok(CommandResponse::in_channel(message))
+            }
+            _ => Ok(CommandResponse::ephemeral(reply.text)),
+        }
+    }
+
+    /// Commands are sent from inside the network, they can't reach internal services
+    async fn check_command_host(&self, url: &Url) -> Result<(), AppError> {
+        if self.config.outbound.allow_private_addrs {
+            return Ok(());
+        }
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or_default();
+        resolve_public_addrs(host, port)
+            .await
+            .map_err(|e| AppError::CommandError(format!("invalid url {}: {}", url, e)))?;
+        Ok(())
+    }
+
+    async fn post_as(
+        &self,
+        user: &User,
+        chat_id: u64,
+        content: String,
+        format: MessageFormat,
+    ) -> Result<Message, AppError> {
+        let input = CreateMessage {
+            content,
+            format,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        self.create_message(input, chat_id, user.id as _).await
+    }
+
+    /// Register a custom command for the workspace, handled by a bot the user owns
+    pub async fn register_command(
+        &self,
+        input: &RegisterCommand,
+        bot_id: u64,
+        owner: &User,
+    ) -> Result<RegisteredCommand, AppError> {
+        self.verify_bot_owner(bot_id, owner.id as _).await?;
+        let name = input.name.trim_start_matches('/').to_lowercase();
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_COMMAND_NAME_LEN
+            && name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            return Err(AppError::CommandError(format!(
+                "invalid command name: {}",
+                input.name
+            )));
+        }
+        if BUILTIN_COMMANDS.iter().any(|(n, _, _)| *n == name) {
+            return Err(AppError::CommandError(format!(
+                "/{} is a built-in command",
+                name
+            )));
+        }
+        let url = match Url::parse(&input.url) {
+            Ok(url) if self.config.outbound.allows_scheme(url.scheme()) => url,
+            _ => {
+                return Err(AppError::CommandError(format!(
+                    "invalid url: {}",
+                    input.url
+                )))
+            }
+        };
+        self.check_command_host(&url).await?;
+
+        let mut buf = [0u8; 32];
+        OsRng.fill_bytes(&mut buf);
+        let secret = hex::encode(buf);
+        let ret = sqlx::query_as(
+            r#"
+            INSERT INTO slash_commands (workspace_id, bot_id, name, description, usage, url, secret)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, workspace_id, bot_id, name, description, usage, url, created_at
+            "#,
+        )
+        .bind(owner.workspace_id)
+        .bind(bot_id as i64)
+        .bind(&name)
+        .bind(&input.description)
+        .bind(&input.usage)
+        .bind(&input.url)
+        .bind(&secret)
+        .fetch_one(&self.pool)
+        .await;
+
+        match ret {
+            Ok(info) => Ok(RegisteredCommand { secret, info }),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(
+                AppError::CommandError(format!("/{} already exists in the workspace", name)),
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn list_bot_commands(
+        &self,
+        bot_id: u64,
+        owner_id: u64,
+    ) -> Result<Vec<CustomCommand>, AppError> {
+        self.verify_bot_owner(bot_id, owner_id).await?;
+        let commands = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, bot_id, name, description, usage, url, created_at
+            FROM slash_commands
+            WHERE bot_id = $1
+            ORDER BY name
+            "#,
+        )
+        .bind(bot_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(commands)
+    }
+
+    pub async fn delete_command(
+        &self,
+        id: u64,
+        bot_id: u64,
+        owner_id: u64,
+    ) -> Result<(), AppError> {
+        self.verify_bot_owner(bot_id, owner_id).await?;
+        let ret = sqlx::query("DELETE FROM slash_commands WHERE id = $1 AND bot_id = $2")
+            .bind(id as i64)
+            .bind(bot_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("command id {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Commands available in the workspace for autocomplete, sorted by name
+    pub async fn list_commands(
+        &self,
+        workspace_id: u64,
+        input: &ListCommands,
+    ) -> Result<Vec<CommandInfo>, AppError> {
+        let prefix = input
+            .prefix
+            .as_deref()
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_lowercase();
+        let custom: Vec<CustomCommand> = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, bot_id, name, description, usage, url, created_at
+            FROM slash_commands
+            WHERE workspace_id = $1 AND starts_with(name, $2)
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .bind(&prefix)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let builtin = BUILTIN_COMMANDS
+            .iter()
+            .filter(|(name, _, _)| name.starts_with(&prefix))
+            .map(|(name, usage, description)| CommandInfo {
+                name: name.to_string(),
+                usage: usage.to_string(),
+                description: description.to_string(),
+                bot_id: None,
+            });
+        let mut commands: Vec<_> = builtin
+            .chain(custom.into_iter().map(|c| CommandInfo {
+                name: c.name,
+                usage: c.usage,
+                description: c.description,
+                bot_id: Some(c.bot_id),
+            }))
+            .collect();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(commands)
+    }
+}
+
+#[cfg(test)]
+impl RegisterCommand {
+    pub fn new(name: &str, url: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            description: String::new(),
+            usage: String::new(),
+            url: url.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{routing::post, Json, Router};
+    use chat_core::TokenScope;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::AppConfig;
+
+    fn message(content: &str) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            format: MessageFormat::Plain,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        }
+    }
+
+    #[test]
+    fn parse_command_should_work() {
+        assert_eq!(
+            parse_command("/Invite  @wukun "),
+            Some(("invite".to_string(), "@wukun"))
+        );
+        assert_eq!(parse_command("/leave"), Some(("leave".to_string(), "")));
+        assert_eq!(parse_command("/ not a command"), None);
+        assert_eq!(parse_command("//escaped"), None);
+        assert_eq!(parse_command("hello /me"), None);
+
+        assert_eq!(parse_duration("10m"), Some(Duration::minutes(10)));
+        assert_eq!(parse_duration("2d"), Some(Duration::days(2)));
+        assert_eq!(parse_duration("0h"), None);
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("52w"), Some(Duration::weeks(52)));
+        assert_eq!(parse_duration("53w"), None);
+        assert_eq!(parse_duration("99999999999999w"), None);
+        assert_eq!(parse_duration("9223372036854775807s"), None);
+    }
+
+    #[tokio::test]
+    async fn builtin_commands_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        assert!(state
+            .run_command(&message("hello"), 4, &user)
+            .await?
+            .is_none());
+
+        let res = state
+            .run_command(&message("/me waves"), 4, &user)
+            .await?
+            .expect("should be a command");
+        assert_eq!(res.response_type, ResponseType::InChannel);
+        assert_eq!(res.text, "_wiki waves_");
+
+        state
+            .run_command(&message("/topic release 1.0"), 4, &user)
+            .await?;
+        let chat = state.get_chat_by_id(4).await?.expect("chat should exist");
+        assert_eq!(chat.topic.as_deref(), Some("release 1.0"));
+
+        let res = state
+            .run_command(&message("/invite @wukun"), 4, &user)
+            .await?
+            .expect("should be a command");
+        assert_eq!(res.text, "invited @wukun");
+        assert!(state.is_chat_member(4, 2).await?);
+        let res = state
+            .run_command(&message("/invite wukun"), 4, &user)
+            .await?
+            .expect("should be a command");
+        assert_eq!(res.response_type, ResponseType::Ephemeral);
+
+        // direct messages can't be left
+        let ret = state.run_command(&message("/leave"), 3, &user).await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        state.run_command(&message("/leave"), 4, &user).await?;
+        assert!(!state.is_chat_member(4, 1).await?);
+
+        state.run_command(&message("/mute"), 1, &user).await?;
+        assert!(state.is_chat_muted(1, 1).await?);
+        state.run_command(&message("/mute"), 1, &user).await?;
+        assert!(!state.is_chat_muted(1, 1).await?);
+
+        let ret = state.run_command(&message("/remind soon"), 1, &user).await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        let ret = state
+            .run_command(&message("/remind 99999999999999w x"), 1, &user)
+            .await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        let ret = state
+            .run_command(&message("/mute 99999999999999w"), 1, &user)
+            .await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        let res = state
+            .run_command(&message("/remind in 10m stand-up"), 1, &user)
+            .await?
+            .expect("should be a command");
+        assert_eq!(res.text, "I will remind you in 10m: stand-up");
+
+        let ret = state.run_command(&message("/deploy"), 1, &user).await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+
+        let mut token_user = user.clone();
+        token_user.scopes = Some(vec![TokenScope::WriteMessages]);
+        let ret = state
+            .run_command(&message("/topic x"), 1, &token_user)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        Ok(())
+    }
+
+    async fn deploy_handler(Json(req): Json<BotCommandRequest>) -> Json<serde_json::Value> {
+        let response_type = if req.text == "public" {
+            "in_channel"
+        } else {
+            "ephemeral"
+        };
+        Json(serde_json::json!({
+            "response_type": response_type,
+            "text": format!("deploying for {}", req.user_name),
+        }))
+    }
+
+    #[tokio::test]
+    async fn custom_commands_should_work() -> Result<()> {
+        // the bot listens on loopback without tls
+        let mut config = AppConfig::load()?;
+        config.outbound.allowed_schemes.push("http".to_string());
+        config.outbound.allow_private_addrs = true;
+        let (_tdb, state) = AppState::new_for_test_with_config(config).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("deploy-bot", &user).await?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/deploy", listener.local_addr()?);
+        let app = Router::new().route("/deploy", post(deploy_handler));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let ret = state
+            .register_command(&RegisterCommand::new("me", &url), bot.id as _, &user)
+            .await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        let registered = state
+            .register_command(&RegisterCommand::new("/Deploy", &url), bot.id as _, &user)
+            .await?;
+        assert_eq!(registered.info.name, "deploy");
+
+        let res = state
+            .run_command(&message("/deploy staging"), 1, &user)
+            .await?
+            .expect("should be a command");
+        assert_eq!(res.response_type, ResponseType::Ephemeral);
+        assert_eq!(res.text, "deploying for wiki");
+        assert!(res.message.is_none());
+
+        let res = state
+            .run_command(&message("/deploy public"), 1, &user)
+            .await?
+            .expect("should be a command");
+        let message = res.message.expect("should be posted");
+        assert_eq!(message.sender_id, bot.id);
+
+        let input = ListCommands {
+            prefix: Some("/de".to_string()),
+        };
+        let commands = state.list_commands(1, &input).await?;
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].bot_id, Some(bot.id));
+        assert_eq!(
+            state
+                .list_commands(1, &ListCommands::default())
+                .await?
+                .len(),
+            BUILTIN_COMMANDS.len() + 1
+        );
+
+        state
+            .delete_command(registered.info.id as _, bot.id as _, 1)
+            .await?;
+        assert!(state.list_bot_commands(bot.id as _, 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn custom_commands_should_only_reach_public_https_urls() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("deploy-bot", &user).await?;
+
+        for url in [
+            "http://1.1.1.1/deploy",
+            "https://127.0.0.1/deploy",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]/deploy",
+            "https://localhost/deploy",
+        ] {
+            let ret = state
+                .register_command(&RegisterCommand::new("deploy", url), bot.id as _, &user)
+                .await;
+            assert!(matches!(ret, Err(AppError::CommandError(_))), "{}", url);
+        }
+
+        // registered before the checks, still not sent
+        sqlx::query(
+            r#"
+            INSERT INTO slash_commands (workspace_id, bot_id, name, url, secret)
+            VALUES (1, $1, 'deploy', 'https://127.0.0.1/deploy', 'secret')
+            "#,
+        )
+        .bind(bot.id)
+        .execute(&state.pool)
+        .await?;
+        let ret = state.run_command(&message("/deploy"), 1, &user).await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::resolve_public_addrs;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use url::Url;
+
+use crate::{AppError, AppState};
+
+/// Event types a bot can subscribe to, `Mention` is sent when the bot is mentioned
+pub const EVENT_TYPES: &[&str] = &[
+    "NewChat",
+    "AddToChat",
+    "RemoveFromChat",
+    "NewMessage",
+    "Mention",
+    "MessagePinned",
+    "MessageUnpinned",
+    "BookmarkAdded",
+    "BookmarkUpdated",
+    "BookmarkRemoved",
+];
+
+const MAX_DELIVERIES: u64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateEventSubscription {
+    pub url: String,
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub chat_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct EventSubscription {
+    pub id: i64,
+    pub bot_id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub chat_ids: Vec<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returned once on creation, the secret signs every delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedEventSubscription {
+    pub secret: String,
+    #[serde(flatten)]
+    pub info: EventSubscription,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct EventDelivery {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub event_type: String,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListEventDeliveries {
+    pub last_id: Option<u64>,
+    pub limit: u64,
+}
+
+impl AppState {
+    pub async fn create_event_subscription(
+        &self,
+        input: &CreateEventSubscription,
+        bot_id: u64,
+        owner_id: u64,
+    ) -> Result<CreatedEventSubscription, AppError> {
+        self.verify_bot_owner(bot_id, owner_id).await?;
+        let url = match Url::parse(&input.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+            _ => {
+                return Err(AppError::EventSubscriptionError(format!(
+                    "invalid url: {}",
+                    input.url
+                )))
+            }
+        };
+        if input.events.is_empty() {
+            return Err(AppError::EventSubscriptionError(
+                "subscribe to at least one event".to_string(),
+            ));
+        }
+        if let Some(event) = input
+            .events
+            .iter()
+            .find(|e| !EVENT_TYPES.contains(&e.as_str()))
+        {
+            return Err(AppError::EventSubscriptionError(format!(
+                "unknown event type: {}",
+                event
+            )));
+        }
+        // checked again by notify_server before each delivery
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or_default();
+        if let Err(e) = resolve_public_addrs(host, port).await {
+            return Err(AppError::EventSubscriptionError(format!(
+                "invalid url {}: {}",
+                input.url, e
+            )));
+        }
+
+        let mut buf = [0u8; 32];
+        OsRng.fill_bytes(&mut buf);
+        let secret = hex::encode(buf);
+        let info = sqlx::query_as(
+            r#"
+            INSERT INTO event_subscriptions (bot_id, url, secret, events, chat_ids)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, bot_id, url, events, chat_ids, created_at
+            "#,
+        )
+        .bind(bot_id as i64)
+        .bind(&input.url)
+        .bind(&secret)
+        .bind(&input.events)
+        .bind(&input.chat_ids)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedEventSubscription { secret, info })
+    }
+
+    pub async fn list_event_subscriptions(
+        &self,
+        bot_id: u64,
+        owner_id: u64,
+    ) -> Result<Vec<EventSubscription>, AppError> {
+        self.verify_bot_owner(bot_id, owner_id).await?;
+        let subscriptions = sqlx::query_as(
+            r#"
+            SELECT id, bot_id, url, events, chat_ids, created_at
+            FROM event_subscriptions
+            WHERE bot_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(bot_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    pub async fn delete_event_subscription(
+        &self,
+        id: u64,
+        bot_id: u64,
+        owner_id: u64,
+    ) -> Result<(), AppError> {
+        self.verify_bot_owner(bot_id, owner_id).await?;
+        let ret = sqlx::query("DELETE FROM event_subscriptions WHERE id = $1 AND bot_id = $2")
+            .bind(id as i64)
+            .bind(bot_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "event subscription id {} not found",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Delivery log of all subscriptions of the bot, newest first
+    pub async fn list_event_deliveries(
+        &self,
+        input: ListEventDeliveries,
+        bot_id: u64,
+        owner_id: u64,
+    ) -> Result<Vec<EventDelivery>, AppError> {
+        self.verify_bot_owner(bot_id, owner_id).await?;
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let deliveries = sqlx::query_as(
+            r#"
+            SELECT d.id, d.subscription_id, d.event_type, d.status, d.attempts,
+                d.response_status, d.error, d.created_at, d.updated_at
+            FROM event_deliveries d
+            JOIN event_subscriptions s ON s.id = d.subscription_id
+            WHERE s.bot_id = $1 AND d.id < $2
+            ORDER BY d.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(bot_id as i64)
+        .bind(last_id as i64)
+        .bind(input.limit.min(MAX_DELIVERIES) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+}
+
+#[cfg(test)]
+impl CreateEventSubscription {
+    pub fn new(url: &str, events: &[&str]) -> Self {
+        Self {
+            url: url.to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+            chat_ids: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn event_subscription_should_validate_input() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+        let bot_id = bot.id as u64;
+
+        let input = CreateEventSubscription::new("ftp://acme.org", &["NewMessage"]);
+        let ret = state.create_event_subscription(&input, bot_id, 1).await;
+        assert!(matches!(ret, Err(AppError::EventSubscriptionError(_))));
+
+        let input = CreateEventSubscription::new("https://acme.org/hook", &["Typing"]);
+        let ret = state.create_event_subscription(&input, bot_id, 1).await;
+        assert!(matches!(ret, Err(AppError::EventSubscriptionError(_))));
+
+        // internal services can't be reached
+        for url in [
+            "http://localhost:6687/events",
+            "http://127.0.0.1/hook",
+            "http://10.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+        ] {
+            let input = CreateEventSubscription::new(url, &["NewMessage"]);
+            let ret = state.create_event_subscription(&input, bot_id, 1).await;
+            assert!(matches!(ret, Err(AppError::EventSubscriptionError(_))));
+        }
+
+        // only the owner of the bot can subscribe
+        let input = CreateEventSubscription::new("https://acme.org/hook", &["Mention"]);
+        let ret = state.create_event_subscription(&input, bot_id, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn event_subscription_and_deliveries_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+        let bot_id = bot.id as u64;
+
+        let input = CreateEventSubscription::new("https://1.1.1.1/hook", &["NewMessage"]);
+        let created = state.create_event_subscription(&input, bot_id, 1).await?;
+        assert_eq!(created.secret.len(), 64);
+        assert_eq!(
+            state.list_event_subscriptions(bot_id, 1).await?,
+            vec![created.info.clone()]
+        );
+
+        // deliveries are written by notify_server
+        sqlx::query(
+            "INSERT INTO event_deliveries (subscription_id, event_type, status, attempts) VALUES ($1, 'NewMessage', 'failed', 5), ($1, 'NewMessage', 'delivered', 1)",
+        )
+        .bind(created.info.id)
+        .execute(&state.pool)
+        .await?;
+        let input = ListEventDeliveries {
+            last_id: None,
+            limit: 10,
+        };
+        let deliveries = state
+            .list_event_deliveries(input.clone(), bot_id, 1)
+            .await?;
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Delivered);
+        assert_eq!(deliveries[1].attempts, 5);
+        assert!(state.list_event_deliveries(input, bot_id, 2).await.is_err());
+
+        state
+            .delete_event_subscription(created.info.id as _, bot_id, 1)
+            .await?;
+        assert!(state.list_event_subscriptions(bot_id, 1).await?.is_empty());
+
+        Ok(())
+    }
+}
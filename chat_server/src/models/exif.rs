@@ -0,0 +1,288 @@
+use std::borrow::Cow;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Remove EXIF and XMP metadata, e.g. the GPS location, from jpeg, png and
+/// webp images. The jpeg orientation is kept. Other content, or images that
+/// can't be parsed, is kept as is.
+pub(crate) fn strip_metadata(data: &[u8]) -> Cow<'_, [u8]> {
+    let stripped = match format(data) {
+        Some(Format::Jpeg) => strip_jpeg(data),
+        Some(Format::Png) => strip_png(data),
+        Some(Format::Webp) => strip_webp(data),
+        None => None,
+    };
+    match stripped {
+        Some(data) => Cow::Owned(data),
+        None => Cow::Borrowed(data),
+    }
+}
+
+/// Whether content starting with the header can have metadata to remove
+pub(crate) fn has_metadata(header: &[u8]) -> bool {
+    format(header).is_some()
+}
+
+enum Format {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+fn format(data: &[u8]) -> Option<Format> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        Some(Format::Jpeg)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        Some(Format::Png)
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some(Format::Webp)
+    } else {
+        None
+    }
+}
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(i + 1)?;
+        match marker {
+            // fill byte
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            // start of scan or end of image, the rest is image data
+            0xDA | 0xD9 => {
+                out.extend_from_slice(&data[i..]);
+                return Some(out);
+            }
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&data[i..i + 2]);
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = u16::from_be_bytes([*data.get(i + 2)?, *data.get(i + 3)?]) as usize;
+        let end = i + 2 + len;
+        if len < 2 || end > data.len() {
+            return None;
+        }
+        // APP1 holds EXIF and XMP, APP13 holds IPTC
+        match marker {
+            0xE1 => {
+                // keep the orientation so photos aren't shown sideways
+                if let Some(orientation) = jpeg_orientation(&data[i + 4..end]) {
+                    out.extend_from_slice(&orientation_segment(orientation));
+                }
+            }
+            0xED => {}
+            _ => out.extend_from_slice(&data[i..end]),
+        }
+        i = end;
+    }
+}
+
+/// The EXIF orientation in an APP1 segment, if it isn't the default
+fn jpeg_orientation(segment: &[u8]) -> Option<u16> {
+    let tiff = segment.strip_prefix(b"Exif\0\0")?;
+    let big_endian = match tiff.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let bytes: [u8; 4] = tiff.get(4..8)?.try_into().ok()?;
+    let ifd = if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    } as usize;
+    let entries = u16_at(ifd)? as usize;
+    (0..entries)
+        .map(|n| ifd + 2 + n * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|orientation| (2..=8).contains(orientation))
+}
+
+/// A minimal APP1 segment with only the orientation tag
+pub(super) fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut segment = vec![0xFF, 0xE1, 0, 34];
+    segment.extend_from_slice(b"Exif\0\0MM\0*");
+    // the only IFD starts right after the header and has one entry
+    segment.extend_from_slice(&[0, 0, 0, 8, 0, 1]);
+    segment.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    // a single SHORT, padded to 4 bytes
+    segment.extend_from_slice(&[0, 3, 0, 0, 0, 1]);
+    segment.extend_from_slice(&orientation.to_be_bytes());
+    // no next IFD
+    segment.extend_from_slice(&[0; 6]);
+    segment
+}
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut i = PNG_SIGNATURE.len();
+    while i < data.len() {
+        let len = u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?) as usize;
+        let kind = data.get(i + 4..i + 8)?;
+        // length, type and crc around the chunk data
+        let end = i.checked_add(len)?.checked_add(12)?;
+        if end > data.len() {
+            return None;
+        }
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"iTXt" | b"zTXt") {
+            out.extend_from_slice(&data[i..end]);
+        }
+        i = end;
+    }
+    Some(out)
+}
+
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut i = 12;
+    while i < data.len() {
+        let kind = data.get(i..i + 4)?;
+        let len = u32::from_le_bytes(data.get(i + 4..i + 8)?.try_into().ok()?) as usize;
+        let data_end = i.checked_add(8)?.checked_add(len)?;
+        if data_end > data.len() {
+            return None;
+        }
+        // chunks are padded to an even size
+        let end = (data_end + len % 2).min(data.len());
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = out.len();
+                out.extend_from_slice(&data[i..end]);
+                // clear the exif and xmp flags
+                if let Some(flags) = out.get_mut(start + 8) {
+                    *flags &= !0x0C;
+                }
+            }
+            _ => out.extend_from_slice(&data[i..end]),
+        }
+        i = end;
+    }
+    let size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&size.to_le_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    use anyhow::Result;
+    use image::{metadata::Orientation, ImageDecoder, ImageFormat, RgbImage};
+
+    fn encode(format: ImageFormat) -> Result<Vec<u8>> {
+        let mut buf = Cursor::new(vec![]);
+        RgbImage::new(4, 4).write_to(&mut buf, format)?;
+        Ok(buf.into_inner())
+    }
+
+    #[test]
+    fn strip_jpeg_metadata_should_work() -> Result<()> {
+        let original = encode(ImageFormat::Jpeg)?;
+        let exif = b"Exif\0\0GPS 52.52N 13.40E";
+        let mut data = original[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1, 0, exif.len() as u8 + 2]);
+        data.extend_from_slice(exif);
+        data.extend_from_slice(&original[2..]);
+
+        let stripped = strip_metadata(&data);
+        assert_eq!(stripped.as_ref(), original.as_slice());
+        image::load_from_memory(&stripped)?;
+        Ok(())
+    }
+
+    #[test]
+    fn strip_jpeg_metadata_should_keep_orientation() -> Result<()> {
+        let original = encode(ImageFormat::Jpeg)?;
+        // little endian, IFD0 with the orientation (6) and a GPS IFD pointer
+        let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x02\0".to_vec();
+        exif.extend_from_slice(b"\x12\x01\x03\0\x01\0\0\0\x06\0\0\0");
+        exif.extend_from_slice(b"\x25\x88\x04\0\x01\0\0\0\x26\0\0\0");
+        exif.extend_from_slice(b"\0\0\0\0GPS 52.52N 13.40E");
+        let mut data = original[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1, 0, exif.len() as u8 + 2]);
+        data.extend_from_slice(&exif);
+        data.extend_from_slice(&original[2..]);
+
+        let stripped = strip_metadata(&data);
+        let mut expected = original[..2].to_vec();
+        expected.extend_from_slice(&orientation_segment(6));
+        expected.extend_from_slice(&original[2..]);
+        assert_eq!(stripped.as_ref(), expected.as_slice());
+
+        let mut decoder = image::ImageReader::new(Cursor::new(stripped.as_ref()))
+            .with_guessed_format()?
+            .into_decoder()?;
+        assert_eq!(decoder.orientation()?, Orientation::Rotate90);
+        Ok(())
+    }
+
+    #[test]
+    fn strip_png_metadata_should_work() -> Result<()> {
+        let original = encode(ImageFormat::Png)?;
+        let mut data = original[..33].to_vec();
+        data.extend_from_slice(&[0, 0, 0, 3]);
+        data.extend_from_slice(b"eXIfGPS");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&original[33..]);
+
+        assert_eq!(strip_metadata(&data).as_ref(), original.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn strip_webp_metadata_should_work() {
+        let chunks: &[&[u8]] = &[
+            b"VP8X\x0a\0\0\0\x08\0\0\0\0\0\0\0\0\0",
+            b"VP8L\x02\0\0\0ab",
+            b"EXIF\x03\0\0\0GPS\0",
+        ];
+        let webp = |chunks: &[&[u8]]| {
+            let body: Vec<u8> = chunks.concat();
+            let mut data = b"RIFF".to_vec();
+            data.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+            data.extend_from_slice(b"WEBP");
+            data.extend_from_slice(&body);
+            data
+        };
+
+        let expected = webp(&[b"VP8X\x0a\0\0\0\0\0\0\0\0\0\0\0\0\0", chunks[1]]);
+        assert_eq!(strip_metadata(&webp(chunks)).as_ref(), expected.as_slice());
+    }
+
+    #[test]
+    fn strip_metadata_should_keep_other_content() {
+        assert_eq!(strip_metadata(b"hello").as_ref(), b"hello");
+        // truncated jpeg
+        assert_eq!(
+            strip_metadata(&[0xFF, 0xD8, 0xFF, 0xE1, 0]).as_ref(),
+            &[0xFF, 0xD8, 0xFF, 0xE1, 0]
+        );
+    }
+}
@@ -1,20 +1,110 @@
 use std::{
+    borrow::Cow,
+    ops::Range,
     path::{Path, PathBuf},
     str::FromStr,
 };
 
-use crate::{AppError, ChatFile};
+use crate::{
+    models::{
+        exif::{has_metadata, strip_metadata},
+        file_policy::reserve_quota,
+    },
+    AppError, AppState, ChatFile, HashAlgorithm, ScanVerdict,
+};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::{Attachment, ScanStatus};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use image::{metadata::Orientation, ImageDecoder, ImageReader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgConnection;
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::warn;
+
+const MAX_FILENAME_LEN: usize = 255;
+const MAX_EXT_LEN: usize = 16;
+// uploads are received here, on the same file system as the stored files
+pub(crate) const TEMP_DIR: &str = "tmp";
+// infected uploads are moved here, out of the storage, for review
+pub(crate) const QUARANTINE_DIR: &str = "quarantine";
+// enough to tell the image formats apart
+const HEADER_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileQuery {
+    /// serve the thumbnail of this size instead of the original
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedFileQuery {
+    pub size: Option<u32>,
+    /// unix timestamp
+    pub expires: i64,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignFileUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SignedFileUrl {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
 
-use sha1::{Digest, Sha1};
+impl HashAlgorithm {
+    /// Length of the hash in hex
+    pub fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 40,
+            HashAlgorithm::Sha256 => 64,
+        }
+    }
+
+    /// SHA-1 files keep their original paths, others are under a directory
+    /// named after the algorithm
+    fn dir(self) -> Option<&'static str> {
+        match self {
+            HashAlgorithm::Sha1 => None,
+            HashAlgorithm::Sha256 => Some("sha256"),
+        }
+    }
+}
 
 impl ChatFile {
     pub fn new(workspace_id: u64, filename: &str, data: &[u8]) -> Self {
-        let hash = Sha1::digest(data);
+        let hash = Sha256::digest(data);
+        Self::with_hash(
+            workspace_id,
+            filename,
+            HashAlgorithm::Sha256,
+            hex::encode(hash),
+        )
+    }
 
+    pub fn with_hash(
+        workspace_id: u64,
+        filename: &str,
+        algorithm: HashAlgorithm,
+        hash: String,
+    ) -> Self {
+        // the extension ends up in the path, anything unusual is dropped
+        let ext = filename
+            .split('.')
+            .next_back()
+            .filter(|ext| is_valid_ext(ext))
+            .unwrap_or("txt");
         Self {
             workspace_id,
-            ext: filename.split('.').last().unwrap_or("txt").to_string(),
-            hash: hex::encode(hash),
+            ext: ext.to_string(),
+            hash,
+            algorithm,
         }
     }
 
@@ -22,19 +112,21 @@ impl ChatFile {
         format!("/files/{}", self.hash_to_path())
     }
 
-    pub fn path(&self, base_dir: &Path) -> PathBuf {
-        base_dir.join(self.hash_to_path())
-    }
-
     // split hash init 3 parts, first 2 with 3 chars
     pub fn hash_to_path(&self) -> String {
         let (part1, part2) = self.hash.split_at(3);
         let (part2, part3) = part2.split_at(3);
 
-        format!(
-            "{}/{}/{}/{}.{}",
-            self.workspace_id, part1, part2, part3, self.ext
-        )
+        match self.algorithm.dir() {
+            Some(dir) => format!(
+                "{}/{}/{}/{}/{}.{}",
+                self.workspace_id, dir, part1, part2, part3, self.ext
+            ),
+            None => format!(
+                "{}/{}/{}/{}.{}",
+                self.workspace_id, part1, part2, part3, self.ext
+            ),
+        }
     }
 }
 
@@ -48,19 +140,31 @@ impl FromStr for ChatFile {
                 s
             )));
         };
-        let parts: Vec<&str> = s.split('/').collect();
-        if parts.len() != 4 {
-            return Err(AppError::ChatFileError(format!(
-                "File path does not valid: {}",
-                s
-            )));
-        }
+        let mut parts: Vec<&str> = s.split('/').collect();
+        let sha256_dir = HashAlgorithm::Sha256.dir();
+        let algorithm = match parts.len() {
+            4 => HashAlgorithm::Sha1,
+            5 if parts.get(1).copied() == sha256_dir => {
+                parts.remove(1);
+                HashAlgorithm::Sha256
+            }
+            _ => {
+                return Err(AppError::ChatFileError(format!(
+                    "File path does not valid: {}",
+                    s
+                )))
+            }
+        };
 
-        let Ok(workspace_id) = parts[0].parse::<u64>() else {
-            return Err(AppError::ChatFileError(format!(
-                "Invalid workspace id: {}",
-                parts[1]
-            )));
+        // digits only, "+1" or "01" would be another url for the same file
+        let workspace_id = match parts[0].parse::<u64>() {
+            Ok(id) if id.to_string() == parts[0] => id,
+            _ => {
+                return Err(AppError::ChatFileError(format!(
+                    "Invalid workspace id: {}",
+                    parts[0]
+                )))
+            }
         };
 
         let Some((part3, ext)) = parts[3].split_once('.') else {
@@ -69,26 +173,674 @@ impl FromStr for ChatFile {
                 parts[3]
             )));
         };
+        if !is_valid_ext(ext) {
+            return Err(AppError::ChatFileError(format!(
+                "Invalid file extension: {}",
+                ext
+            )));
+        }
 
+        // nothing but the hash split as in `hash_to_path`, so no `..` or other tricks
         let hash = format!("{}{}{}", parts[1], parts[2], part3);
+        let is_hex = |s: &str| s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if parts[1].len() != 3
+            || parts[2].len() != 3
+            || hash.len() != algorithm.hex_len()
+            || !is_hex(&hash)
+        {
+            return Err(AppError::ChatFileError(format!("Invalid file hash: {}", s)));
+        }
 
         Ok(Self {
             workspace_id,
             ext: ext.to_string(),
             hash,
+            algorithm,
         })
     }
 }
 
+/// An upload received chunk by chunk and hashed on the way, the temp file is
+/// removed on drop unless it was moved into the storage
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    file: fs::File,
+    hasher: Sha256,
+    header: Vec<u8>,
+    size: u64,
+    max_size: u64,
+}
+
+impl TempFile {
+    pub async fn new(base_dir: &Path, max_size: u64) -> Result<Self, AppError> {
+        let dir = base_dir.join(TEMP_DIR);
+        fs::create_dir_all(&dir).await?;
+        let mut buf = [0u8; 16];
+        OsRng.fill_bytes(&mut buf);
+        let path = dir.join(hex::encode(buf));
+        let file = fs::File::create(&path).await?;
+
+        Ok(Self {
+            path,
+            file,
+            hasher: Sha256::new(),
+            header: Vec::with_capacity(HEADER_LEN),
+            size: 0,
+            max_size,
+        })
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), AppError> {
+        self.size += chunk.len() as u64;
+        if self.size > self.max_size {
+            return Err(AppError::PayloadTooLarge(format!(
+                "file is larger than {} bytes",
+                self.max_size
+            )));
+        }
+        let missing = HEADER_LEN
+            .saturating_sub(self.header.len())
+            .min(chunk.len());
+        self.header.extend_from_slice(&chunk[..missing]);
+        self.hasher.update(chunk);
+        self.file.write_all(chunk).await?;
+        Ok(())
+    }
+
+    /// Replace the content written so far
+    async fn rewrite(&mut self, data: &[u8]) -> Result<(), AppError> {
+        self.file = fs::File::create(&self.path).await?;
+        self.hasher = Sha256::new();
+        self.header.clear();
+        self.size = 0;
+        self.write(data).await
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn flush(&mut self) -> Result<(), AppError> {
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl AppState {
+    /// A temp file for an upload, limited to the configured file size
+    pub async fn temp_file(&self) -> Result<TempFile, AppError> {
+        TempFile::new(
+            &self.config.server.base_dir,
+            self.config.files.max_file_size,
+        )
+        .await
+    }
+
+    /// Store the content under its hash and record the upload with its metadata
+    pub async fn create_file(
+        &self,
+        workspace_id: u64,
+        uploader_id: u64,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<Attachment, AppError> {
+        let mut temp = self.temp_file().await?;
+        temp.write(data).await?;
+        self.store_file(workspace_id, uploader_id, filename, temp)
+            .await
+    }
+
+    /// Move a received upload into the storage and record it with its metadata
+    pub async fn store_file(
+        &self,
+        workspace_id: u64,
+        uploader_id: u64,
+        filename: &str,
+        mut temp: TempFile,
+    ) -> Result<Attachment, AppError> {
+        temp.flush().await?;
+        // only images are read back into memory to remove their metadata
+        if self.config.files.strip_metadata && has_metadata(&temp.header) {
+            let data = fs::read(&temp.path).await?;
+            if let Cow::Owned(stripped) = strip_metadata(&data) {
+                temp.rewrite(&stripped).await?;
+                temp.flush().await?;
+            }
+        }
+
+        let hash = hex::encode(temp.hasher.clone().finalize());
+        let file = ChatFile::with_hash(workspace_id, filename, HashAlgorithm::Sha256, hash);
+        let key = file.hash_to_path();
+        let (width, height) = match image_dimensions(&temp.path) {
+            Some((width, height)) => (Some(width as i32), Some(height as i32)),
+            None => (None, None),
+        };
+        let (scan_status, scan_result) = match self.scanner.scan(&temp.path).await? {
+            ScanVerdict::Unscanned => (ScanStatus::Unscanned, None),
+            ScanVerdict::Clean => (ScanStatus::Clean, None),
+            ScanVerdict::Infected(signature) => (ScanStatus::Infected, Some(signature)),
+        };
+        // the file gc checks the url is unused with the same lock, so the object
+        // can't be deleted between the check below and the insert
+        let mut tx = self.pool.begin().await?;
+        lock_in_tx(&mut tx, FileLock::Object, &file.url()).await?;
+        let mut put = false;
+        if let Some(signature) = &scan_result {
+            warn!(
+                "Quarantined upload {} of user {}: {}",
+                key, uploader_id, signature
+            );
+            self.quarantine(&key, &file.url(), signature, &temp.path)
+                .await?;
+        } else if !self.storage.exists(&key).await? {
+            // the same content is only stored once
+            self.storage.put_file(&key, &temp.path).await?;
+            put = true;
+        }
+
+        // the policy only saw a snapshot of the usage, checked again with the
+        // quota locked until the file is recorded
+        if let Err(e) = reserve_quota(&mut tx, workspace_id, &file.url(), temp.size).await {
+            if put {
+                self.storage.delete(&key).await?;
+            }
+            return Err(e);
+        }
+
+        let name: String = filename.chars().take(MAX_FILENAME_LEN).collect();
+        let mime = mime_guess::from_path(filename).first_or_octet_stream();
+        let attachment: Attachment = sqlx::query_as(
+            r#"
+            INSERT INTO files (workspace_id, uploader_id, name, size, mime, hash, url, width, height,
+                scan_status, scan_result)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            RETURNING id, workspace_id, uploader_id, name, size, mime, hash, url, width, height,
+                blurhash, scan_status, created_at
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .bind(uploader_id as i64)
+        .bind(&name)
+        .bind(temp.size as i64)
+        .bind(mime.to_string())
+        .bind(&file.hash)
+        .bind(file.url())
+        .bind(width)
+        .bind(height)
+        .bind(scan_status)
+        .bind(scan_result)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if attachment.width.is_some() && scan_status != ScanStatus::Infected {
+            self.spawn_previews(&attachment, key);
+        }
+
+        Ok(attachment)
+    }
+
+    /// Move an infected upload out of the storage, earlier uploads of the same
+    /// content are marked as well since they can't be downloaded anymore
+    async fn quarantine(
+        &self,
+        key: &str,
+        url: &str,
+        signature: &str,
+        path: &Path,
+    ) -> Result<(), AppError> {
+        let target = self.config.server.base_dir.join(QUARANTINE_DIR).join(key);
+        fs::create_dir_all(target.parent().expect("file path parent should exists")).await?;
+        fs::rename(path, &target).await?;
+        self.storage.delete(key).await?;
+        sqlx::query("UPDATE files SET scan_status = 'infected', scan_result = $1 WHERE url = $2")
+            .bind(signature)
+            .bind(url)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The urls the user uploaded or received in one of their chats
+    pub async fn accessible_files(
+        &self,
+        user_id: u64,
+        urls: &[String],
+    ) -> Result<Vec<String>, AppError> {
+        if urls.is_empty() {
+            return Ok(vec![]);
+        }
+        let urls = sqlx::query_scalar(
+            r#"
+            SELECT u.url
+            FROM UNNEST($2::TEXT[]) AS u(url)
+            WHERE EXISTS (SELECT 1 FROM files f WHERE f.url = u.url AND f.uploader_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM chat_files cf
+                JOIN chats c ON c.id = cf.chat_id
+                WHERE cf.url = u.url AND $1 = ANY(c.members)
+            )
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(urls)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(urls)
+    }
+
+    pub async fn can_access_file(&self, user_id: u64, url: &str) -> Result<bool, AppError> {
+        let urls = self.accessible_files(user_id, &[url.to_string()]).await?;
+        Ok(!urls.is_empty())
+    }
+
+    /// A url serving the file without authorization until it expires, for `<img>` tags
+    pub fn sign_file_url(&self, url: &str) -> SignedFileUrl {
+        let expires_at =
+            Utc::now() + Duration::seconds(self.config.files.signed_url_expires_secs as i64);
+        let expires = expires_at.timestamp();
+        let signature = hex::encode(self.file_url_mac(url, expires).finalize().into_bytes());
+        SignedFileUrl {
+            url: format!(
+                "/api/signed{}?expires={}&signature={}",
+                url, expires, signature
+            ),
+            expires_at,
+        }
+    }
+
+    pub fn verify_file_signature(
+        &self,
+        url: &str,
+        expires: i64,
+        signature: &str,
+    ) -> Result<(), AppError> {
+        let signature = hex::decode(signature).unwrap_or_default();
+        if self
+            .file_url_mac(url, expires)
+            .verify_slice(&signature)
+            .is_err()
+        {
+            return Err(AppError::PermissionDenied(
+                "invalid file url signature".to_string(),
+            ));
+        }
+        if expires < Utc::now().timestamp() {
+            return Err(AppError::PermissionDenied("file url expired".to_string()));
+        }
+        Ok(())
+    }
+
+    // keyed by the signing key of the tokens, so every instance agrees on it
+    fn file_url_mac(&self, url: &str, expires: i64) -> Hmac<Sha256> {
+        let key = Sha256::digest(format!("file url:{}", self.config.auth.ek));
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("hmac takes keys of any size");
+        mac.update(format!("{}\n{}", url, expires).as_bytes());
+        mac
+    }
+
+    /// Original name of a stored file, the first upload wins if it was uploaded twice
+    pub async fn find_file_name(
+        &self,
+        workspace_id: u64,
+        url: &str,
+    ) -> Result<Option<String>, AppError> {
+        let name = sqlx::query_scalar(
+            "SELECT name FROM files WHERE workspace_id = $1 AND url = $2 ORDER BY id LIMIT 1",
+        )
+        .bind(workspace_id as i64)
+        .bind(url)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(name)
+    }
+}
+
+/// Namespaces of the advisory locks on files, so their keys can't collide
+/// with other locks
+#[derive(Debug, Clone, Copy)]
+#[repr(i32)]
+pub(crate) enum FileLock {
+    /// the usage of a workspace, keyed by its id
+    Quota = 1,
+    /// a stored object, keyed by its url, so it isn't collected while reused
+    Object = 2,
+}
+
+/// Lock `key` until the transaction of `conn` ends
+pub(crate) async fn lock_in_tx(
+    conn: &mut PgConnection,
+    lock: FileLock,
+    key: &str,
+) -> Result<(), AppError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+        .bind(lock as i32)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+fn is_valid_ext(ext: &str) -> bool {
+    !ext.is_empty() && ext.len() <= MAX_EXT_LEN && ext.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// The single byte range requested in a `Range` header, `None` to serve the
+/// whole file. Malformed headers and multiple ranges are ignored.
+pub(crate) fn parse_range(value: &str, len: u64) -> Result<Option<Range<u64>>, AppError> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(n) if n > 0 => len.saturating_sub(n)..len,
+            Ok(_) => return Err(AppError::RangeNotSatisfiable(len)),
+            Err(_) => return Ok(None),
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => start..len,
+            Err(_) => return Ok(None),
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(len),
+            _ => return Ok(None),
+        },
+    };
+    if range.start >= len {
+        return Err(AppError::RangeNotSatisfiable(len));
+    }
+    Ok(Some(range))
+}
+
+/// The dimensions of the image as it's displayed, i.e. after its orientation
+fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut decoder = ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let (width, height) = decoder.dimensions();
+    match decoder.orientation().ok()? {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => Some((height, width)),
+        _ => Some((width, height)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        scanner::{fake_clamd, EICAR},
+        storage::validate_key,
+        AppConfig, CreateMessage, FileRef, ScannerConfig,
+    };
+
+    use anyhow::Result;
+    use axum::extract::Query;
+    use proptest::prelude::*;
+
+    const URL: &str = "/files/1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg";
+
+    #[test]
+    fn chat_file_from_str_should_reject_non_canonical_urls() {
+        assert!(ChatFile::from_str(URL).is_ok());
+        for url in [
+            "/files/1/../../2/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/1/c13/../f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/1/c13/504/../../../../etc/passwd",
+            "/files/1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.j/../x",
+            "/files/1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e..",
+            "/files/1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.",
+            "/files/1/c13/504/f6f2e1.jpeg",
+            "/files/1/C13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/+1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/01/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/1/c1/3504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jp%2fg",
+            "/files/1/sha256/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+            "/files/1/sha1/c13/504/f6f2e198b8751c5f6030e27bc626f27a6e.jpeg",
+        ] {
+            assert!(
+                ChatFile::from_str(url).is_err(),
+                "{} should be rejected",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn chat_file_ext_should_be_sanitized() {
+        let ext = |name| ChatFile::with_hash(1, name, HashAlgorithm::Sha256, "0".repeat(64)).ext;
+        assert_eq!(ext("a.tar.gz"), "gz");
+        assert_eq!(ext("../../evil"), "txt");
+        assert_eq!(ext("x./etc/passwd"), "txt");
+        assert_eq!(ext("x.j\\pg"), "txt");
+        assert_eq!(ext("README"), "README");
+    }
+
+    // the pieces of an url, with the usual traversal tricks
+    fn url_part() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just("..".to_string()),
+            Just(".".to_string()),
+            Just(String::new()),
+            Just("+1".to_string()),
+            Just("%2e%2e".to_string()),
+            Just("\\".to_string()),
+            "[0-9]{1,3}",
+            "[0-9a-f]{3}",
+            Just("sha256".to_string()),
+            "[0-9a-f]{34}\\.[a-z.]{0,4}",
+            "[0-9a-f]{58}\\.[a-z.]{0,4}",
+            any::<String>(),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn chat_file_url_should_round_trip(
+            workspace_id in any::<u64>(),
+            algorithm in prop_oneof![Just(HashAlgorithm::Sha1), Just(HashAlgorithm::Sha256)],
+            hash in "[0-9a-f]{64}",
+            ext in "[a-zA-Z0-9]{1,16}",
+        ) {
+            let hash = hash[..algorithm.hex_len()].to_string();
+            let file = ChatFile::with_hash(workspace_id, &format!("name.{}", ext), algorithm, hash);
+            let parsed = ChatFile::from_str(&file.url());
+            prop_assert_eq!(parsed.ok(), Some(file));
+        }
+
+        #[test]
+        fn chat_file_from_str_should_only_accept_safe_urls(
+            parts in prop::collection::vec(url_part(), 0..6),
+        ) {
+            let url = format!("/files/{}", parts.join("/"));
+            if let Ok(file) = ChatFile::from_str(&url) {
+                // the same file has a single url, which maps to a plain relative key
+                prop_assert_eq!(file.url(), url);
+                let key = file.hash_to_path();
+                prop_assert!(validate_key(&key).is_ok());
+                let workspace = file.workspace_id.to_string();
+                prop_assert_eq!(key.split('/').next(), Some(workspace.as_str()));
+            }
+        }
+
+        #[test]
+        fn chat_file_from_str_should_not_panic(url in "/files/\\PC*") {
+            let _ = ChatFile::from_str(&url);
+        }
+    }
 
     #[test]
     fn chat_file_new_should_work() {
         let file = ChatFile::new(1, "test.txt", b"hello world");
         assert_eq!(file.workspace_id, 1);
         assert_eq!(file.ext, "txt");
-        assert_eq!(file.hash, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+        assert_eq!(file.algorithm, HashAlgorithm::Sha256);
+        assert_eq!(
+            file.hash,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(
+            file.url(),
+            "/files/1/sha256/b94/d27/b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9.txt"
+        );
+
+        // files stored before SHA-256 keep their urls
+        let file = ChatFile::from_str(URL).expect("url should be valid");
+        assert_eq!(file.algorithm, HashAlgorithm::Sha1);
+        assert_eq!(file.url(), URL);
+    }
+
+    #[tokio::test]
+    async fn create_file_should_record_metadata() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let data = include_bytes!("../../assets/2.jpeg");
+        let file = state.create_file(1, 1, "2.jpeg", data).await?;
+        assert_eq!(file.name, "2.jpeg");
+        assert_eq!(file.size, data.len() as i64);
+        assert_eq!(file.mime, "image/jpeg");
+        assert!(file.width.is_some() && file.height.is_some());
+        assert!(file.url.starts_with("/files/1/"));
+
+        // the same content uploaded again is a new file sharing the url
+        let other = state.create_file(1, 2, "copy.jpeg", data).await?;
+        assert_ne!(other.id, file.id);
+        assert_eq!(other.url, file.url);
+
+        let file = state.create_file(1, 1, "notes.txt", b"hello").await?;
+        assert_eq!(file.mime, "text/plain");
+        assert_eq!(file.width, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_file_should_wait_for_the_object_lock() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = state.create_file(1, 1, "notes.txt", b"hello").await?;
+
+        // held by the file gc while it checks the url is unused
+        let mut tx = state.pool.begin().await?;
+        lock_in_tx(&mut tx, FileLock::Object, &file.url).await?;
+        let handle = tokio::spawn({
+            let state = state.clone();
+            async move { state.create_file(1, 2, "copy.txt", b"hello").await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!handle.is_finished());
+
+        tx.commit().await?;
+        let copy = handle.await??;
+        assert_eq!(copy.url, file.url);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn infected_files_should_be_quarantined() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        config.scanner = ScannerConfig::Clamd {
+            address: fake_clamd().await?,
+            timeout_secs: 5,
+        };
+        let (_tdb, state) = AppState::new_for_test_with_config(config).await?;
+        let file = state.create_file(1, 1, "notes.txt", b"hello").await?;
+        assert_eq!(file.scan_status, ScanStatus::Clean);
+
+        let file = state
+            .create_file(1, 1, "eicar.com", EICAR.as_bytes())
+            .await?;
+        assert_eq!(file.scan_status, ScanStatus::Infected);
+        let key = ChatFile::from_str(&file.url)?.hash_to_path();
+        assert!(!state.storage.exists(&key).await?);
+        let quarantined = state.config.server.base_dir.join(QUARANTINE_DIR).join(&key);
+        assert!(quarantined.exists());
+
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            format: Default::default(),
+            files: vec![FileRef::Id(file.id)],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_signature_should_expire() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let url = "/files/1/c13/504/f6f2e1.jpeg";
+        let signed = state.sign_file_url(url);
+        let uri = signed.url.parse()?;
+        let Query(query) = Query::<SignedFileQuery>::try_from_uri(&uri)?;
+        assert_eq!(query.expires, signed.expires_at.timestamp());
+        state.verify_file_signature(url, query.expires, &query.signature)?;
+
+        let other = "/files/1/c13/504/f6f2e2.jpeg";
+        assert!(state
+            .verify_file_signature(other, query.expires, &query.signature)
+            .is_err());
+        assert!(state
+            .verify_file_signature(url, query.expires + 60, &query.signature)
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn temp_file_should_enforce_max_size() -> Result<()> {
+        let base_dir = std::env::temp_dir().join("chat_server_temp_file_test");
+        let mut temp = TempFile::new(&base_dir, 8).await?;
+        let path = temp.path.clone();
+        temp.write(b"hello").await?;
+        let ret = temp.write(b"world").await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(_))));
+
+        drop(temp);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_range_should_work() {
+        let range = |value| parse_range(value, 100).ok().flatten();
+        assert_eq!(range("bytes=0-9"), Some(0..10));
+        assert_eq!(range("bytes=90-"), Some(90..100));
+        assert_eq!(range("bytes=-10"), Some(90..100));
+        assert_eq!(range("bytes=-200"), Some(0..100));
+        assert_eq!(range("bytes=50-500"), Some(50..100));
+        // ignored
+        assert_eq!(range("bytes=0-1,5-6"), None);
+        assert_eq!(range("bytes=9-1"), None);
+        assert_eq!(range("items=0-9"), None);
+
+        assert!(matches!(
+            parse_range("bytes=100-", 100),
+            Err(AppError::RangeNotSatisfiable(100))
+        ));
+        assert!(parse_range("bytes=-0", 100).is_err());
     }
 }
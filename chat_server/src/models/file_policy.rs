@@ -0,0 +1,459 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use thiserror::Error;
+
+use crate::{
+    models::file::{lock_in_tx, FileLock},
+    AppError, AppState,
+};
+
+/// File settings of a workspace, set by its owner. Empty lists allow everything
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceFileSettings {
+    /// unreferenced uploads are kept at least this long
+    #[serde(default)]
+    pub retention_days: Option<i32>,
+    /// total bytes stored by the workspace, the same content counts once
+    #[serde(default)]
+    pub quota_bytes: Option<i64>,
+    /// lower than the server limit to have any effect
+    #[serde(default)]
+    pub max_file_size: Option<i64>,
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    #[serde(default)]
+    pub blocked_extensions: Vec<String>,
+    /// e.g. `image/png` or `image/*`
+    #[serde(default)]
+    pub allowed_mime_types: Vec<String>,
+    #[serde(default)]
+    pub blocked_mime_types: Vec<String>,
+    #[serde(default = "default_guest_uploads")]
+    pub guest_uploads: bool,
+}
+
+/// Why an upload was refused, returned with the error so clients can explain it
+#[derive(Debug, Clone, Error, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum UploadRejection {
+    #[error("workspace quota of {quota} bytes exceeded, {used} bytes used")]
+    QuotaExceeded { quota: u64, used: u64 },
+    #[error("file is larger than {max_file_size} bytes")]
+    FileTooLarge { max_file_size: u64 },
+    #[error("file extension {extension} is not allowed")]
+    ExtensionNotAllowed { extension: String },
+    #[error("mime type {mime_type} is not allowed")]
+    MimeTypeNotAllowed { mime_type: String },
+    #[error("guests are not allowed to upload files")]
+    GuestUploadsDisabled,
+}
+
+/// The policy an upload is checked against, before anything is written.
+/// The quota is checked against a snapshot of the usage, [`reserve_quota`]
+/// checks it again when the file is recorded
+#[derive(Debug, Clone)]
+pub struct UploadPolicy {
+    settings: WorkspaceFileSettings,
+    max_file_size: u64,
+    used: u64,
+}
+
+impl Default for WorkspaceFileSettings {
+    fn default() -> Self {
+        Self {
+            retention_days: None,
+            quota_bytes: None,
+            max_file_size: None,
+            allowed_extensions: vec![],
+            blocked_extensions: vec![],
+            allowed_mime_types: vec![],
+            blocked_mime_types: vec![],
+            guest_uploads: default_guest_uploads(),
+        }
+    }
+}
+
+impl UploadPolicy {
+    /// Reject the file by its name and the declared content type
+    pub fn check_file(&self, filename: &str, content_type: Option<&str>) -> Result<(), AppError> {
+        let settings = &self.settings;
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        let listed = |list: &[String]| list.iter().any(|e| e.eq_ignore_ascii_case(&extension));
+        if listed(&settings.blocked_extensions)
+            || (!settings.allowed_extensions.is_empty() && !listed(&settings.allowed_extensions))
+        {
+            return Err(UploadRejection::ExtensionNotAllowed { extension }.into());
+        }
+
+        let guessed = mime_guess::from_path(filename)
+            .first_or_octet_stream()
+            .to_string();
+        for mime_type in std::iter::once(guessed.as_str()).chain(content_type) {
+            let listed = |list: &[String]| list.iter().any(|p| mime_matches(p, mime_type));
+            if listed(&settings.blocked_mime_types)
+                || (!settings.allowed_mime_types.is_empty()
+                    && !listed(&settings.allowed_mime_types))
+            {
+                return Err(UploadRejection::MimeTypeNotAllowed {
+                    mime_type: mime_type.to_string(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the size of the file so far, called before each chunk is written
+    pub fn check_size(&self, size: u64) -> Result<(), AppError> {
+        if size > self.max_file_size {
+            return Err(UploadRejection::FileTooLarge {
+                max_file_size: self.max_file_size,
+            }
+            .into());
+        }
+        match self.settings.quota_bytes {
+            Some(quota) if self.used + size > quota.max(0) as u64 => {
+                Err(UploadRejection::QuotaExceeded {
+                    quota: quota.max(0) as u64,
+                    used: self.used,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Account for a stored file when several are uploaded at once
+    pub fn add_usage(&mut self, size: u64) {
+        self.used += size;
+    }
+}
+
+impl AppState {
+    pub async fn get_file_settings(
+        &self,
+        workspace_id: u64,
+    ) -> Result<WorkspaceFileSettings, AppError> {
+        let settings = sqlx::query_as(
+            r#"
+            SELECT retention_days, quota_bytes, max_file_size, allowed_extensions,
+                blocked_extensions, allowed_mime_types, blocked_mime_types, guest_uploads
+            FROM workspace_file_settings
+            WHERE workspace_id = $1
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(settings.unwrap_or_default())
+    }
+
+    /// Only the owner can change the file settings of the workspace
+    pub async fn update_file_settings(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+        input: &WorkspaceFileSettings,
+    ) -> Result<WorkspaceFileSettings, AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        if input.retention_days.is_some_and(|days| days < 0) {
+            return Err(AppError::ChatFileError(
+                "retention days can't be negative".to_string(),
+            ));
+        }
+        if input.quota_bytes.is_some_and(|n| n < 0) || input.max_file_size.is_some_and(|n| n < 0) {
+            return Err(AppError::ChatFileError(
+                "quota and max file size can't be negative".to_string(),
+            ));
+        }
+        let normalize = |list: &[String]| -> Vec<String> {
+            list.iter()
+                .map(|s| s.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+
+        let settings = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_file_settings (workspace_id, retention_days, quota_bytes,
+                max_file_size, allowed_extensions, blocked_extensions, allowed_mime_types,
+                blocked_mime_types, guest_uploads)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (workspace_id) DO UPDATE SET
+                retention_days = EXCLUDED.retention_days,
+                quota_bytes = EXCLUDED.quota_bytes,
+                max_file_size = EXCLUDED.max_file_size,
+                allowed_extensions = EXCLUDED.allowed_extensions,
+                blocked_extensions = EXCLUDED.blocked_extensions,
+                allowed_mime_types = EXCLUDED.allowed_mime_types,
+                blocked_mime_types = EXCLUDED.blocked_mime_types,
+                guest_uploads = EXCLUDED.guest_uploads
+            RETURNING retention_days, quota_bytes, max_file_size, allowed_extensions,
+                blocked_extensions, allowed_mime_types, blocked_mime_types, guest_uploads
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .bind(input.retention_days)
+        .bind(input.quota_bytes)
+        .bind(input.max_file_size)
+        .bind(normalize(&input.allowed_extensions))
+        .bind(normalize(&input.blocked_extensions))
+        .bind(normalize(&input.allowed_mime_types))
+        .bind(normalize(&input.blocked_mime_types))
+        .bind(input.guest_uploads)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(settings)
+    }
+
+    /// The upload policy of the user, guests are refused right away if the
+    /// workspace doesn't allow them to upload
+    pub async fn upload_policy(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+    ) -> Result<UploadPolicy, AppError> {
+        let settings = self.get_file_settings(workspace_id).await?;
+        if !settings.guest_uploads {
+            let is_guest: bool = sqlx::query_scalar("SELECT is_guest FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?
+                .unwrap_or_default();
+            if is_guest {
+                return Err(UploadRejection::GuestUploadsDisabled.into());
+            }
+        }
+
+        let max_file_size = match settings.max_file_size {
+            Some(size) => (size.max(0) as u64).min(self.config.files.max_file_size),
+            None => self.config.files.max_file_size,
+        };
+        let used = match settings.quota_bytes {
+            Some(_) => stored_bytes(&mut *self.pool.acquire().await?, workspace_id).await?,
+            None => 0,
+        };
+        Ok(UploadPolicy {
+            settings,
+            max_file_size,
+            used,
+        })
+    }
+}
+
+/// Bytes stored by the workspace, the same content uploaded twice is stored once
+pub(crate) async fn stored_bytes(
+    conn: &mut PgConnection,
+    workspace_id: u64,
+) -> Result<u64, AppError> {
+    let bytes: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(SUM(size), 0)::BIGINT
+        FROM (SELECT DISTINCT ON (url) size FROM files WHERE workspace_id = $1) objects
+        "#,
+    )
+    .bind(workspace_id as i64)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(bytes as u64)
+}
+
+/// Check the quota for a file about to be recorded in the transaction of
+/// `conn`. Concurrent uploads of the workspace wait for the transaction, so
+/// they can't all pass on the same usage
+pub(crate) async fn reserve_quota(
+    conn: &mut PgConnection,
+    workspace_id: u64,
+    url: &str,
+    size: u64,
+) -> Result<(), AppError> {
+    let quota: Option<i64> = sqlx::query_scalar(
+        "SELECT quota_bytes FROM workspace_file_settings WHERE workspace_id = $1",
+    )
+    .bind(workspace_id as i64)
+    .fetch_optional(&mut *conn)
+    .await?
+    .flatten();
+    let Some(quota) = quota else {
+        return Ok(());
+    };
+    lock_in_tx(conn, FileLock::Quota, &workspace_id.to_string()).await?;
+    let stored: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM files WHERE workspace_id = $1 AND url = $2)",
+    )
+    .bind(workspace_id as i64)
+    .bind(url)
+    .fetch_one(&mut *conn)
+    .await?;
+    if stored {
+        return Ok(());
+    }
+    let used = stored_bytes(conn, workspace_id).await?;
+    let quota = quota.max(0) as u64;
+    if used + size > quota {
+        return Err(UploadRejection::QuotaExceeded { quota, used }.into());
+    }
+    Ok(())
+}
+
+fn default_guest_uploads() -> bool {
+    true
+}
+
+/// `image/*` matches any image
+fn mime_matches(pattern: &str, mime_type: &str) -> bool {
+    let mime_type = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(top) => mime_type
+            .split_once('/')
+            .is_some_and(|(t, _)| t == top.to_ascii_lowercase()),
+        None => mime_type == pattern.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+    use super::*;
+    use crate::ChatFile;
+
+    #[tokio::test]
+    async fn upload_policy_should_check_the_file() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let settings = WorkspaceFileSettings {
+            blocked_extensions: vec![".EXE".to_string()],
+            allowed_mime_types: vec!["image/*".to_string(), "text/plain".to_string()],
+            ..Default::default()
+        };
+        let settings = state.update_file_settings(1, 1, &settings).await?;
+        assert_eq!(settings.blocked_extensions, vec!["exe"]);
+
+        let policy = state.upload_policy(1, 2).await?;
+        policy.check_file("cat.JPG", Some("image/jpeg"))?;
+        policy.check_file("notes.txt", None)?;
+        let ret = policy.check_file("setup.exe", None);
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(
+                UploadRejection::ExtensionNotAllowed { .. }
+            ))
+        ));
+        let ret = policy.check_file("doc.pdf", None);
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(
+                UploadRejection::MimeTypeNotAllowed { .. }
+            ))
+        ));
+        let ret = policy.check_file("cat.png", Some("text/html; charset=utf-8"));
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(
+                UploadRejection::MimeTypeNotAllowed { .. }
+            ))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_policy_should_check_the_size_and_quota() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        state.create_file(1, 1, "a.txt", b"hello").await?;
+        state.create_file(1, 2, "b.txt", b"hello").await?;
+        assert_eq!(stored_bytes(&mut *state.pool.acquire().await?, 1).await?, 5);
+
+        let settings = WorkspaceFileSettings {
+            quota_bytes: Some(16),
+            max_file_size: Some(8),
+            guest_uploads: false,
+            ..Default::default()
+        };
+        state.update_file_settings(1, 1, &settings).await?;
+        let mut policy = state.upload_policy(1, 1).await?;
+        policy.check_size(8)?;
+        let ret = policy.check_size(9);
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(UploadRejection::FileTooLarge {
+                max_file_size: 8
+            }))
+        ));
+        policy.add_usage(8);
+        let ret = policy.check_size(4);
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(UploadRejection::QuotaExceeded {
+                quota: 16,
+                used: 13
+            }))
+        ));
+
+        sqlx::query("UPDATE users SET is_guest = TRUE WHERE id = 2")
+            .execute(&state.pool)
+            .await?;
+        let ret = state.upload_policy(1, 2).await;
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(
+                UploadRejection::GuestUploadsDisabled
+            ))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_uploads_should_not_exceed_the_quota() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let settings = WorkspaceFileSettings {
+            quota_bytes: Some(8),
+            ..Default::default()
+        };
+        state.update_file_settings(1, 1, &settings).await?;
+
+        // both pass the policy on the same usage, only one fits
+        let policy = state.upload_policy(1, 1).await?;
+        policy.check_size(5)?;
+        // the storage outlives the test database, so the content is new each run
+        let (mut first, mut other) = ([0u8; 5], [0u8; 5]);
+        OsRng.fill_bytes(&mut first);
+        OsRng.fill_bytes(&mut other);
+        let (a, b) = tokio::join!(
+            state.create_file(1, 1, "a.txt", &first),
+            state.create_file(1, 2, "b.txt", &other)
+        );
+        let (stored, dropped, rejected) = match (a, b) {
+            (Ok(_), Err(e)) => (first, other, e),
+            (Err(e), Ok(_)) => (other, first, e),
+            ret => panic!("expected exactly one upload to fail, got {:?}", ret),
+        };
+        assert!(matches!(
+            rejected,
+            AppError::UploadRejected(UploadRejection::QuotaExceeded { quota: 8, used: 5 })
+        ));
+
+        // the content of the rejected upload isn't kept
+        let key = ChatFile::new(1, "b.txt", &dropped).hash_to_path();
+        assert!(!state.storage.exists(&key).await?);
+
+        // the same content is stored once, so it still fits
+        state.create_file(1, 2, "copy.txt", &stored).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chat_core::{Message, MessageFormat, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+
+use crate::{AppError, AppState, CreateMessage};
+
+const MAX_USERNAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateIncomingWebhook {
+    pub name: String,
+    /// the bot messages are posted as, must be owned by the creator
+    pub bot_id: u64,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct IncomingWebhook {
+    pub id: i64,
+    pub chat_id: i64,
+    pub bot_id: i64,
+    pub creator_id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returned on creation and regeneration, the secret can't be retrieved afterwards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedIncomingWebhook {
+    pub secret: String,
+    /// relative url to post payloads to, with the secret in the
+    /// `X-Webhook-Secret` header
+    pub path: String,
+    #[serde(flatten)]
+    pub info: IncomingWebhook,
+}
+
+/// Slack compatible incoming webhook payload
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    #[serde(default)]
+    pub text: String,
+    pub username: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<WebhookAttachment>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebhookAttachment {
+    pub fallback: Option<String>,
+    pub pretext: Option<String>,
+    pub title: Option<String>,
+    pub title_link: Option<String>,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub fields: Vec<WebhookAttachmentField>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookAttachmentField {
+    pub title: String,
+    pub value: String,
+}
+
+impl AppState {
+    pub async fn create_incoming_webhook(
+        &self,
+        input: &CreateIncomingWebhook,
+        chat_id: u64,
+        creator: &User,
+    ) -> Result<CreatedIncomingWebhook, AppError> {
+        if input.name.is_empty() {
+            return Err(AppError::WebhookError(
+                "Webhook name cannot be empty".to_string(),
+            ));
+        }
+        self.verify_bot_owner(input.bot_id, creator.id as _).await?;
+
+        let secret = generate_secret();
+        let info: IncomingWebhook = sqlx::query_as(
+            r#"
+            INSERT INTO incoming_webhooks (chat_id, bot_id, creator_id, name, secret_hash)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, chat_id, bot_id, creator_id, name, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(input.bot_id as i64)
+        .bind(creator.id)
+        .bind(&input.name)
+        .bind(hash_secret(&secret))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedIncomingWebhook::new(secret, info))
+    }
+
+    pub async fn list_incoming_webhooks(
+        &self,
+        chat_id: u64,
+    ) -> Result<Vec<IncomingWebhook>, AppError> {
+        let hooks = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, bot_id, creator_id, name, created_at
+            FROM incoming_webhooks
+            WHERE chat_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(hooks)
+    }
+
+    /// Replace the secret of a webhook, the old url stops working immediately
+    pub async fn regenerate_webhook_secret(
+        &self,
+        id: u64,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<CreatedIncomingWebhook, AppError> {
+        let secret = generate_secret();
+        let info: Option<IncomingWebhook> = sqlx::query_as(
+            r#"
+            UPDATE incoming_webhooks
+            SET secret_hash = $4
+            WHERE id = $1 AND chat_id = $2 AND creator_id = $3
+            RETURNING id, chat_id, bot_id, creator_id, name, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(hash_secret(&secret))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match info {
+            Some(info) => Ok(CreatedIncomingWebhook::new(secret, info)),
+            None => Err(AppError::NotFound(format!("webhook id {} not found", id))),
+        }
+    }
+
+    pub async fn delete_incoming_webhook(
+        &self,
+        id: u64,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            "DELETE FROM incoming_webhooks WHERE id = $1 AND chat_id = $2 AND creator_id = $3",
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("webhook id {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Post a payload to the chat of the webhook as its bot
+    pub async fn post_incoming_webhook(
+        &self,
+        id: u64,
+        secret: &str,
+        payload: WebhookPayload,
+    ) -> Result<Message, AppError> {
+        let hook: Option<IncomingWebhook> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, bot_id, creator_id, name, created_at
+            FROM incoming_webhooks
+            WHERE id = $1 AND secret_hash = $2
+            "#,
+        )
+        .bind(id as i64)
+        .bind(hash_secret(secret))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(hook) = hook else {
+            return Err(AppError::NotFound(format!("webhook id {} not found", id)));
+        };
+
+        // only count verified requests, so a wrong secret can't exhaust the limit
+        if let Err(retry_after) = self.webhook_limiter.check(&format!("hook:{}", hook.id)) {
+            return Err(AppError::TooManyRequests(retry_after.as_secs().max(1)));
+        }
+
+        let content = payload.to_content();
+        if content.is_empty() {
+            return Err(AppError::WebhookError(
+                "Payload has no text or attachments".to_string(),
+            ));
+        }
+        let sender_name = payload
+            .username
+            .map(|name| {
+                name.trim()
+                    .chars()
+                    .take(MAX_USERNAME_LEN)
+                    .collect::<String>()
+            })
+            .filter(|name| !name.is_empty());
+        // webhook text is markdown, as with slack
+        let input = CreateMessage {
+            content,
+            format: MessageFormat::Markdown,
+            files: vec![],
+            sender_name,
+            client_msg_id: None,
+        };
+
+        self.create_message(input, hook.chat_id as _, hook.bot_id as _)
+            .await
+    }
+}
+
+impl CreatedIncomingWebhook {
+    fn new(secret: String, info: IncomingWebhook) -> Self {
+        Self {
+            path: format!("/api/hooks/{}", info.id),
+            secret,
+            info,
+        }
+    }
+}
+
+impl WebhookPayload {
+    /// Messages are plain text, attachments are rendered below the text
+    fn to_content(&self) -> String {
+        let mut lines = vec![];
+        if !self.text.trim().is_empty() {
+            lines.push(self.text.trim().to_string());
+        }
+        for attachment in &self.attachments {
+            attachment.render(&mut lines);
+        }
+        lines.join("\n")
+    }
+}
+
+impl WebhookAttachment {
+    fn render(&self, lines: &mut Vec<String>) {
+        let start = lines.len();
+        if let Some(pretext) = &self.pretext {
+            lines.push(pretext.clone());
+        }
+        match (&self.title, &self.title_link) {
+            (Some(title), Some(link)) => lines.push(format!("{} ({})", title, link)),
+            (Some(title), None) => lines.push(title.clone()),
+            (None, Some(link)) => lines.push(link.clone()),
+            (None, None) => {}
+        }
+        if let Some(text) = &self.text {
+            lines.push(text.clone());
+        }
+        for field in &self.fields {
+            lines.push(format!("{}: {}", field.title, field.value));
+        }
+
+        // fallback is the plain text summary for clients that can't render the rest
+        if lines.len() == start {
+            if let Some(fallback) = &self.fallback {
+                lines.push(fallback.clone());
+            }
+        }
+    }
+}
+
+fn generate_secret() -> String {
+    let mut buf = [0u8; 24];
+    OsRng.fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+impl CreateIncomingWebhook {
+    pub fn new(name: &str, bot_id: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            bot_id: bot_id as _,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    fn payload(json: &str) -> Result<WebhookPayload> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    #[test]
+    fn webhook_payload_should_render_attachments() -> Result<()> {
+        let p = payload(
+            r#"{
+                "text": "Deploy finished",
+                "attachments": [
+                    {"title": "build #42", "title_link": "https://ci.acme.org/42", "fields": [{"title": "env", "value": "prod", "short": true}]},
+                    {"fallback": "only fallback"}
+                ]
+            }"#,
+        )?;
+        assert_eq!(
+            p.to_content(),
+            "Deploy finished\nbuild #42 (https://ci.acme.org/42)\nenv: prod\nonly fallback"
+        );
+
+        assert_eq!(payload(r#"{"text": "  "}"#)?.to_content(), "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_should_post_as_bot() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+
+        let input = CreateIncomingWebhook::new("ci", bot.id);
+        let created = state.create_incoming_webhook(&input, 1, &user).await?;
+        assert_eq!(created.path, format!("/api/hooks/{}", created.info.id));
+
+        let p = payload(r#"{"text": "hello", "username": "Jenkins"}"#)?;
+        let msg = state
+            .post_incoming_webhook(created.info.id as _, &created.secret, p)
+            .await?;
+        assert_eq!(msg.chat_id, 1);
+        assert_eq!(msg.sender_id, bot.id);
+        assert_eq!(msg.sender_name.as_deref(), Some("Jenkins"));
+        assert_eq!(msg.content, "hello");
+
+        let ret = state
+            .post_incoming_webhook(created.info.id as _, "bad", payload(r#"{"text": "x"}"#)?)
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let ret = state
+            .post_incoming_webhook(created.info.id as _, &created.secret, payload("{}")?)
+            .await;
+        assert!(matches!(ret, Err(AppError::WebhookError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_should_require_bot_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let other = state.find_user_by_id(2).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+
+        let input = CreateIncomingWebhook::new("ci", bot.id);
+        let ret = state.create_incoming_webhook(&input, 1, &other).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_secret_regenerate_and_delete_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let bot = state.create_bot("ci-bot", &user).await?;
+        let input = CreateIncomingWebhook::new("ci", bot.id);
+        let created = state.create_incoming_webhook(&input, 1, &user).await?;
+        let id = created.info.id as u64;
+
+        // only the creator can manage it
+        assert!(state.regenerate_webhook_secret(id, 1, 2).await.is_err());
+        let regenerated = state.regenerate_webhook_secret(id, 1, 1).await?;
+        assert_ne!(regenerated.secret, created.secret);
+
+        let p = payload(r#"{"text": "hello"}"#)?;
+        let ret = state
+            .post_incoming_webhook(id, &created.secret, p.clone())
+            .await;
+        assert!(ret.is_err());
+        state
+            .post_incoming_webhook(id, &regenerated.secret, p)
+            .await?;
+
+        assert_eq!(state.list_incoming_webhooks(1).await?.len(), 1);
+        assert!(state.delete_incoming_webhook(id, 1, 2).await.is_err());
+        state.delete_incoming_webhook(id, 1, 1).await?;
+        assert!(state.list_incoming_webhooks(1).await?.is_empty());
+        Ok(())
+    }
+}
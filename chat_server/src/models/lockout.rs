@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chat_core::middlewares::{LockoutConfig, LockoutStore};
+use sqlx::PgPool;
+use tracing::warn;
+
+/// Signin lockouts in Postgres, so they hold across servers and restarts
+pub struct PgLockouts {
+    pool: PgPool,
+}
+
+impl PgLockouts {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl LockoutStore for PgLockouts {
+    async fn locked_for(&self, key: &str) -> anyhow::Result<Option<Duration>> {
+        let secs: Option<f64> = sqlx::query_scalar(
+            r#"
+            SELECT EXTRACT(EPOCH FROM locked_until - NOW())::FLOAT8
+            FROM auth_lockouts
+            WHERE key = $1 AND locked_until > NOW()
+            "#,
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(secs.map(Duration::from_secs_f64))
+    }
+
+    async fn record_failure(&self, key: &str, config: &LockoutConfig) -> anyhow::Result<()> {
+        let (failures, level): (i32, i32) = sqlx::query_as(
+            r#"
+            INSERT INTO auth_lockouts AS l (key, failures)
+            VALUES ($1, 1)
+            ON CONFLICT (key) DO UPDATE
+            SET failures = l.failures + 1, updated_at = NOW()
+            RETURNING failures, level
+            "#,
+        )
+        .bind(key)
+        .fetch_one(&self.pool)
+        .await?;
+        if (failures as u32) < config.max_failures {
+            return Ok(());
+        }
+
+        let secs = config.lock_secs(level as u32);
+        // concurrent failures lock only once
+        let ret = sqlx::query(
+            r#"
+            UPDATE auth_lockouts
+            SET failures = 0, level = level + 1, updated_at = NOW(),
+                locked_until = NOW() + make_interval(secs => $2)
+            WHERE key = $1 AND failures >= $3
+            "#,
+        )
+        .bind(key)
+        .bind(secs as f64)
+        .bind(config.max_failures as i32)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() > 0 {
+            warn!("lock {} for {}s after {} failures", key, secs, failures);
+        }
+
+        // lockouts that expired long ago don't make the next one longer
+        sqlx::query(
+            r#"
+            DELETE FROM auth_lockouts
+            WHERE COALESCE(locked_until, updated_at) < NOW() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(config.max_secs as f64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_success(&self, key: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM auth_lockouts WHERE key = $1")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pg_lockouts_should_be_progressive() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let lockouts = PgLockouts::new(state.pool.clone());
+        let config = LockoutConfig {
+            max_failures: 2,
+            base_secs: 60,
+            max_secs: 150,
+        };
+        let key = "email:wiki@acme.org|ip:10.0.0.1";
+
+        lockouts.record_failure(key, &config).await?;
+        assert_eq!(lockouts.locked_for(key).await?, None);
+        lockouts.record_failure(key, &config).await?;
+        let locked = lockouts.locked_for(key).await?.expect("should be locked");
+        assert!(locked > Duration::from_secs(55) && locked <= Duration::from_secs(60));
+        // other clients of the account are not locked
+        assert_eq!(lockouts.locked_for("email:wiki@acme.org").await?, None);
+
+        // the next lockout doubles
+        sqlx::query("UPDATE auth_lockouts SET locked_until = NOW()")
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(lockouts.locked_for(key).await?, None);
+        lockouts.record_failure(key, &config).await?;
+        lockouts.record_failure(key, &config).await?;
+        let locked = lockouts.locked_for(key).await?.expect("should be locked");
+        assert!(locked > Duration::from_secs(115) && locked <= Duration::from_secs(120));
+
+        lockouts.record_success(key).await?;
+        assert_eq!(lockouts.locked_for(key).await?, None);
+        Ok(())
+    }
+}
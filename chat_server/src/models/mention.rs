@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+#[cfg(test)]
+use chat_core::MessageFormat;
+use chat_core::{Mention, MentionKind, Message};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+
+use crate::{AppError, AppState};
+
+const MAX_MENTIONS: u64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListMentions {
+    pub last_id: Option<u64>,
+    pub limit: u64,
+}
+
+/// A mention in the inbox, with the message it was made in
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct MentionInboxItem {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub mention: Mention,
+    pub content: String,
+}
+
+#[derive(Debug, FromRow)]
+struct Member {
+    id: i64,
+    fullname: String,
+}
+
+/// Names after an `@` at the start of a word, e.g. not in emails. Names with
+/// spaces are written `@[Full Name]`
+pub(crate) fn parse_mentions(content: &str) -> Vec<&str> {
+    let is_name_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.');
+    content
+        .match_indices('@')
+        .filter(|(i, _)| {
+            content[..*i]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !is_name_char(c))
+        })
+        .map(|(i, _)| {
+            let rest = &content[i + 1..];
+            if let Some(quoted) = rest.strip_prefix('[') {
+                return match quoted.find([']', '\n']) {
+                    Some(end) if quoted[end..].starts_with(']') => quoted[..end].trim(),
+                    _ => "",
+                };
+            }
+            let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            // a trailing dot ends the sentence rather than the name
+            rest[..end].trim_end_matches('.')
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Store the mentions of a new message, only chat members can be mentioned
+pub(crate) async fn insert_mentions(
+    conn: &mut PgConnection,
+    message: &Message,
+) -> Result<Vec<Mention>, AppError> {
+    let names = parse_mentions(&message.content);
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let members: Vec<Member> = sqlx::query_as(
+        r#"
+        SELECT u.id, u.fullname
+        FROM chats c
+        JOIN users u ON u.id = ANY(c.members)
+        WHERE c.id = $1
+        "#,
+    )
+    .bind(message.chat_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // a direct mention wins over a broadcast one
+    let mut mentioned: HashMap<i64, MentionKind> = HashMap::new();
+    for name in names {
+        let broadcast = match name.to_lowercase().as_str() {
+            "here" => Some(MentionKind::Here),
+            "channel" => Some(MentionKind::Channel),
+            "group" => Some(MentionKind::Group),
+            _ => None,
+        };
+        match broadcast {
+            Some(kind) => {
+                for member in &members {
+                    mentioned.entry(member.id).or_insert(kind);
+                }
+            }
+            None => {
+                if let Some(id) = find_member(&members, name) {
+                    mentioned.insert(id, MentionKind::User);
+                }
+            }
+        }
+    }
+    mentioned.remove(&message.sender_id);
+    if mentioned.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let (user_ids, kinds): (Vec<i64>, Vec<String>) = mentioned
+        .into_iter()
+        .map(|(id, kind)| (id, kind_name(kind).to_string()))
+        .unzip();
+    let mentions = sqlx::query_as(
+        r#"
+        INSERT INTO mentions (message_id, chat_id, user_id, sender_id, kind)
+        SELECT $1, $2, t.user_id, $3, t.kind::mention_kind
+        FROM UNNEST($4::BIGINT[], $5::TEXT[]) AS t(user_id, kind)
+        RETURNING id, message_id, chat_id, user_id, sender_id, kind, created_at
+        "#,
+    )
+    .bind(message.id)
+    .bind(message.chat_id)
+    .bind(message.sender_id)
+    .bind(&user_ids)
+    .bind(&kinds)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(mentions)
+}
+
+/// Names are matched ignoring case, unless that matches several members and
+/// only one of them has the exact name. Ambiguous names mention no one
+fn find_member(members: &[Member], name: &str) -> Option<i64> {
+    let matches: Vec<&Member> = members
+        .iter()
+        .filter(|m| m.fullname.eq_ignore_ascii_case(name))
+        .collect();
+    match matches.as_slice() {
+        [member] => Some(member.id),
+        _ => {
+            let mut exact = matches.iter().filter(|m| m.fullname == name);
+            match (exact.next(), exact.next()) {
+                (Some(member), None) => Some(member.id),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn kind_name(kind: MentionKind) -> &'static str {
+    match kind {
+        MentionKind::User => "user",
+        MentionKind::Here => "here",
+        MentionKind::Channel => "channel",
+        MentionKind::Group => "group",
+    }
+}
+
+impl AppState {
+    /// Mentions of the user across all chats, newest first
+    pub async fn list_mentions(
+        &self,
+        input: ListMentions,
+        user_id: u64,
+    ) -> Result<Vec<MentionInboxItem>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let mentions = sqlx::query_as(
+            r#"
+            SELECT mt.id, mt.message_id, mt.chat_id, mt.user_id, mt.sender_id, mt.kind,
+                mt.created_at, m.content
+            FROM mentions mt
+            JOIN messages m ON m.id = mt.message_id
+            WHERE mt.user_id = $1 AND mt.id < $2
+            ORDER BY mt.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(last_id as i64)
+        .bind(input.limit.min(MAX_MENTIONS) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(mentions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::CreateMessage;
+
+    #[test]
+    fn parse_mentions_should_work() {
+        assert_eq!(
+            parse_mentions("@wukun can you ask @ci-bot? cc @here."),
+            vec!["wukun", "ci-bot", "here"]
+        );
+        assert!(parse_mentions("mail wiki@acme.org or @ alone").is_empty());
+        assert_eq!(parse_mentions("(@foo)"), vec!["foo"]);
+        assert_eq!(
+            parse_mentions("ping @[Wu Kun] and @[ unclosed\n]"),
+            vec!["Wu Kun"]
+        );
+    }
+
+    fn message(content: &str) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            format: MessageFormat::Plain,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_stored_for_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        // chat 4 has members 1, 3 and 4, wukun (2) isn't one of them
+        let msg = state
+            .create_message(message("@foo @WUKUN please review"), 4, 1)
+            .await?;
+        let inbox = state
+            .list_mentions(
+                ListMentions {
+                    last_id: None,
+                    limit: 10,
+                },
+                3,
+            )
+            .await?;
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].mention.message_id, msg.id);
+        assert_eq!(inbox[0].mention.kind, MentionKind::User);
+        assert_eq!(inbox[0].content, "@foo @WUKUN please review");
+
+        let input = ListMentions {
+            last_id: None,
+            limit: 10,
+        };
+        assert!(state.list_mentions(input.clone(), 2).await?.is_empty());
+
+        // broadcast mentions reach every member but the sender
+        state
+            .create_message(message("@channel and @foo, deploy is done"), 4, 1)
+            .await?;
+        let inbox = state.list_mentions(input.clone(), 4).await?;
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].mention.kind, MentionKind::Channel);
+        let inbox = state.list_mentions(input.clone(), 3).await?;
+        assert_eq!(inbox.len(), 2);
+        assert_eq!(inbox[0].mention.kind, MentionKind::User);
+        assert!(state.list_mentions(input.clone(), 1).await?.is_empty());
+
+        // names with spaces are quoted, names matching several members ignoring case
+        // have to be exact
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (workspace_id, workspace, fullname, email)
+            VALUES (1, 'acme', 'Wu Kun', 'wu.kun@acme.org'), (1, 'acme', 'ANN', 'ann1@acme.org'),
+                (1, 'acme', 'Ann', 'ann2@acme.org')
+            RETURNING id
+            "#,
+        )
+        .fetch_all(&state.pool)
+        .await?;
+        sqlx::query("UPDATE chats SET members = members || $1 WHERE id = 4")
+            .bind(&ids)
+            .execute(&state.pool)
+            .await?;
+        let quoted = state
+            .create_message(message("@[wu kun] @ann @Ann"), 4, 1)
+            .await?;
+        let mut mentioned: Vec<i64> =
+            sqlx::query_scalar("SELECT user_id FROM mentions WHERE message_id = $1")
+                .bind(quoted.id)
+                .fetch_all(&state.pool)
+                .await?;
+        mentioned.sort();
+        assert_eq!(mentioned, vec![ids[0], ids[2]]);
+
+        // pagination
+        let input = ListMentions {
+            last_id: Some(inbox[0].mention.id as _),
+            limit: 10,
+        };
+        let inbox = state.list_mentions(input, 3).await?;
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].mention.message_id, msg.id);
+
+        Ok(())
+    }
+}
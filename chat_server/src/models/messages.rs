@@ -1,20 +1,75 @@
-use crate::{AppError, AppState, ChatFile};
-use chat_core::Message;
+use crate::{models::mention::insert_mentions, AppError, AppState, ChatFile};
+use chat_core::{render_message, Attachment, Message, MessageFormat, ScanStatus};
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use std::str::FromStr;
 
+const MAX_CLIENT_MSG_ID_LEN: usize = 64;
+const DEFAULT_MESSAGE_LIMIT: u64 = 50;
+const MAX_MESSAGE_LIMIT: u64 = 200;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateMessage {
     pub content: String,
-    pub files: Vec<String>,
+    #[serde(default)]
+    pub format: MessageFormat,
+    pub files: Vec<FileRef>,
+    // only set internally, clients can't pick a display name
+    #[serde(skip)]
+    pub sender_name: Option<String>,
+    /// unique per chat and sender, a retried send returns the first message
+    #[serde(default)]
+    pub client_msg_id: Option<String>,
+}
+
+/// A file id returned by upload, or the legacy file url
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FileRef {
+    Id(i64),
+    Url(String),
 }
 
+#[derive(Debug, FromRow)]
+struct MessageAttachment {
+    message_id: i64,
+    #[sqlx(flatten)]
+    attachment: Attachment,
+}
+
+/// At most one cursor, without any the newest messages are listed
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ListMessages {
-    pub last_id: Option<u64>,
+    /// older than this id, `last_id` is the former name
+    #[serde(alias = "last_id")]
+    pub before: Option<u64>,
+    /// newer than this id, e.g. what was missed while disconnected
+    pub after: Option<u64>,
+    /// this message with the context on both sides, for search hits and permalinks
+    pub around: Option<u64>,
+    #[serde(default = "default_message_limit")]
     pub limit: u64,
 }
 
+/// Messages are always newest first, whichever way the page was loaded
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    pub has_more_before: bool,
+    pub has_more_after: bool,
+}
+
+impl Default for ListMessages {
+    fn default() -> Self {
+        Self {
+            before: None,
+            after: None,
+            around: None,
+            limit: default_message_limit(),
+        }
+    }
+}
+
 #[allow(unused)]
 impl AppState {
     pub async fn create_message(
@@ -23,51 +78,246 @@ impl AppState {
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
-        let base_dir = &self.config.server.base_dir;
         // verify content - not empty
         if input.content.is_empty() {
             return Err(AppError::CreateMessageError(
                 "Content cannot be empty".to_string(),
             ));
         }
-
-        for s in &input.files {
-            let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
+        if let Some(id) = &input.client_msg_id {
+            if id.is_empty() || id.len() > MAX_CLIENT_MSG_ID_LEN {
                 return Err(AppError::CreateMessageError(format!(
-                    "File {} does not exist",
-                    s
+                    "client_msg_id must have 1 to {} characters",
+                    MAX_CLIENT_MSG_ID_LEN
                 )));
             }
+            // a retry, nothing else to check
+            if let Some(message) = self.find_message_by_client_id(chat_id, user_id, id).await? {
+                return Ok(message);
+            }
         }
 
-        // create message
-        let message: Message = sqlx::query_as(
+        let (urls, attachments) = self.resolve_files(&input.files, chat_id, user_id).await?;
+
+        // create message, mentions are stored with it
+        let mut tx = self.pool.begin().await?;
+        let message: Option<Message> = sqlx::query_as(
             r#"
-            INSERT INTO messages (chat_id, sender_id, content, files)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, chat_id, sender_id, content, files, created_at
+            INSERT INTO messages (chat_id, sender_id, content, format, html, files, sender_name,
+                client_msg_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (chat_id, sender_id, client_msg_id) WHERE client_msg_id IS NOT NULL
+            DO NOTHING
+            RETURNING id, chat_id, sender_id, sender_name, content, format, html, files,
+                client_msg_id, created_at
             "#,
         )
         .bind(chat_id as i64)
         .bind(user_id as i64)
         .bind(&input.content)
-        .bind(&input.files)
-        .fetch_one(&self.pool)
+        .bind(input.format)
+        .bind(render_message(&input.content, input.format))
+        .bind(&urls)
+        .bind(&input.sender_name)
+        .bind(&input.client_msg_id)
+        .fetch_optional(&mut *tx)
         .await?;
+        // the same key was sent concurrently and won
+        let Some(mut message) = message else {
+            tx.rollback().await?;
+            let id = input.client_msg_id.as_deref().unwrap_or_default();
+            return self
+                .find_message_by_client_id(chat_id, user_id, id)
+                .await?
+                .ok_or_else(|| {
+                    AppError::CreateMessageError("Message was not created".to_string())
+                });
+        };
+        if !attachments.is_empty() {
+            let file_ids: Vec<i64> = attachments.iter().map(|a| a.id).collect();
+            sqlx::query(
+                r#"
+                INSERT INTO message_files (message_id, file_id, position)
+                SELECT $1, t.file_id, t.position - 1
+                FROM UNNEST($2::BIGINT[]) WITH ORDINALITY AS t(file_id, position)
+                "#,
+            )
+            .bind(message.id)
+            .bind(&file_ids)
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !urls.is_empty() {
+            sqlx::query(
+                r#"
+                INSERT INTO chat_files (chat_id, url)
+                SELECT $1, UNNEST($2::TEXT[])
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(chat_id as i64)
+            .bind(&urls)
+            .execute(&mut *tx)
+            .await?;
+        }
+        insert_mentions(&mut tx, &message).await?;
+        tx.commit().await?;
+        message.attachments = attachments;
+
         Ok(message)
     }
 
+    /// The urls of the files to attach, with the uploads referenced by id.
+    /// Uploaded files must belong to the workspace of the chat
+    pub(crate) async fn resolve_files(
+        &self,
+        files: &[FileRef],
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<(Vec<String>, Vec<Attachment>), AppError> {
+        let ids: Vec<i64> = files
+            .iter()
+            .filter_map(|f| match f {
+                FileRef::Id(id) => Some(*id),
+                FileRef::Url(_) => None,
+            })
+            .collect();
+        let uploaded: Vec<Attachment> = if ids.is_empty() {
+            vec![]
+        } else {
+            sqlx::query_as(
+                r#"
+                SELECT f.id, f.workspace_id, f.uploader_id, f.name, f.size, f.mime, f.hash, f.url,
+                    f.width, f.height, f.blurhash, f.scan_status, f.created_at
+                FROM files f
+                JOIN chats c ON c.workspace_id = f.workspace_id
+                WHERE c.id = $1 AND f.id = ANY($2)
+                "#,
+            )
+            .bind(chat_id as i64)
+            .bind(&ids)
+            .fetch_all(&self.pool)
+            .await?
+        };
+
+        let mut urls = Vec::with_capacity(files.len());
+        let mut attachments: Vec<Attachment> = Vec::with_capacity(uploaded.len());
+        for f in files {
+            match f {
+                FileRef::Id(id) => {
+                    let Some(attachment) = uploaded.iter().find(|a| a.id == *id) else {
+                        return Err(AppError::CreateMessageError(format!(
+                            "File {} does not exist",
+                            id
+                        )));
+                    };
+                    // scanned when uploaded, infected files can't be shared
+                    if attachment.scan_status == ScanStatus::Infected {
+                        return Err(AppError::CreateMessageError(format!(
+                            "File {} is quarantined",
+                            id
+                        )));
+                    }
+                    if attachments.iter().any(|a| a.id == *id) {
+                        continue;
+                    }
+                    urls.push(attachment.url.clone());
+                    attachments.push(attachment.clone());
+                }
+                FileRef::Url(s) => {
+                    let file = ChatFile::from_str(s)?;
+                    if !self.storage.exists(&file.hash_to_path()).await? {
+                        return Err(AppError::CreateMessageError(format!(
+                            "File {} does not exist",
+                            s
+                        )));
+                    }
+                    urls.push(s.clone());
+                }
+            }
+        }
+        // knowing the url of a file isn't enough to share it
+        let accessible = self.accessible_files(user_id, &urls).await?;
+        if let Some(url) = urls.iter().find(|url| !accessible.contains(url)) {
+            return Err(AppError::CreateMessageError(format!(
+                "File {} does not exist",
+                url
+            )));
+        }
+
+        Ok((urls, attachments))
+    }
+
     pub async fn list_message(
         &self,
         input: ListMessages,
         chat_id: u64,
-    ) -> Result<Vec<Message>, AppError> {
-        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+    ) -> Result<MessagePage, AppError> {
+        let limit = input.limit.clamp(1, MAX_MESSAGE_LIMIT) as i64;
+        let chat_id = chat_id as i64;
 
-        let messages: Vec<Message> = sqlx::query_as(
+        let page = match (input.before, input.after, input.around) {
+            (before, None, None) => {
+                let before = before.map_or(Ok(i64::MAX), cursor)?;
+                let (messages, has_more_before) =
+                    self.older_messages(chat_id, before, limit).await?;
+                MessagePage {
+                    messages,
+                    has_more_before,
+                    has_more_after: self
+                        .has_newer_messages(chat_id, before.saturating_sub(1))
+                        .await?,
+                }
+            }
+            (None, Some(after), None) => {
+                let after = cursor(after)?;
+                let (messages, has_more_after) = self.newer_messages(chat_id, after, limit).await?;
+                MessagePage {
+                    messages,
+                    has_more_before: self.has_older_messages(chat_id, next_id(after)?).await?,
+                    has_more_after,
+                }
+            }
+            (None, None, Some(around)) => {
+                let around = cursor(around)?;
+                // the message itself is the first of the older half
+                let (older, has_more_before) = self
+                    .older_messages(chat_id, next_id(around)?, limit - limit / 2)
+                    .await?;
+                if older.first().map(|m| m.id) != Some(around) {
+                    return Err(AppError::NotFound(format!("message {} not found", around)));
+                }
+                let (mut messages, has_more_after) =
+                    self.newer_messages(chat_id, around, limit / 2).await?;
+                messages.extend(older);
+                MessagePage {
+                    messages,
+                    has_more_before,
+                    has_more_after,
+                }
+            }
+            _ => {
+                return Err(AppError::ListMessagesError(
+                    "only one of before, after and around can be set".to_string(),
+                ))
+            }
+        };
+
+        Ok(page)
+    }
+
+    /// Up to `limit` messages older than `before`, newest first, and whether
+    /// there are more
+    async fn older_messages(
+        &self,
+        chat_id: i64,
+        before: i64,
+        limit: i64,
+    ) -> Result<(Vec<Message>, bool), AppError> {
+        let mut messages: Vec<Message> = sqlx::query_as(
             r#"
-            SELECT id, chat_id, sender_id, content, files, created_at
+            SELECT id, chat_id, sender_id, sender_name, content, format, html, files,
+                client_msg_id, created_at
             FROM messages
             WHERE chat_id = $1
             AND id < $2
@@ -75,20 +325,170 @@ impl AppState {
             LIMIT $3
             "#,
         )
+        .bind(chat_id)
+        .bind(before)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?;
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+        self.load_attachments(&mut messages).await?;
+        Ok((messages, has_more))
+    }
+
+    /// Up to `limit` messages newer than `after`, the oldest of them are
+    /// returned but still newest first
+    async fn newer_messages(
+        &self,
+        chat_id: i64,
+        after: i64,
+        limit: i64,
+    ) -> Result<(Vec<Message>, bool), AppError> {
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, sender_name, content, format, html, files,
+                client_msg_id, created_at
+            FROM messages
+            WHERE chat_id = $1
+            AND id > $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+        )
+        .bind(chat_id)
+        .bind(after)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?;
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+        messages.reverse();
+        self.load_attachments(&mut messages).await?;
+        Ok((messages, has_more))
+    }
+
+    async fn has_older_messages(&self, chat_id: i64, before: i64) -> Result<bool, AppError> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE chat_id = $1 AND id < $2)",
+        )
+        .bind(chat_id)
+        .bind(before)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    async fn has_newer_messages(&self, chat_id: i64, after: i64) -> Result<bool, AppError> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE chat_id = $1 AND id > $2)",
+        )
+        .bind(chat_id)
+        .bind(after)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    /// The message the sender already sent with this key
+    pub async fn find_message_by_client_id(
+        &self,
+        chat_id: u64,
+        sender_id: u64,
+        client_msg_id: &str,
+    ) -> Result<Option<Message>, AppError> {
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, sender_name, content, format, html, files,
+                client_msg_id, created_at
+            FROM messages
+            WHERE chat_id = $1 AND sender_id = $2 AND client_msg_id = $3
+            "#,
+        )
         .bind(chat_id as i64)
-        .bind(last_id as i64)
-        .bind(input.limit as i64)
+        .bind(sender_id as i64)
+        .bind(client_msg_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(mut message) = message else {
+            return Ok(None);
+        };
+        self.load_attachments(std::slice::from_mut(&mut message))
+            .await?;
+        Ok(Some(message))
+    }
+
+    /// A message in one of the user's chats, others are reported as not found
+    pub async fn find_visible_message(
+        &self,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.sender_name, m.content, m.format, m.html,
+                m.files, m.client_msg_id, m.created_at
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.id = $1 AND $2 = ANY(c.members)
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(mut message) = message else {
+            return Err(AppError::NotFound(format!(
+                "message {} not found",
+                message_id
+            )));
+        };
+        self.load_attachments(std::slice::from_mut(&mut message))
+            .await?;
+        Ok(message)
+    }
+
+    pub(crate) async fn load_attachments(&self, messages: &mut [Message]) -> Result<(), AppError> {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let rows: Vec<MessageAttachment> = sqlx::query_as(
+            r#"
+            SELECT mf.message_id, f.id, f.workspace_id, f.uploader_id, f.name, f.size, f.mime,
+                f.hash, f.url, f.width, f.height, f.blurhash, f.scan_status, f.created_at
+            FROM message_files mf
+            JOIN files f ON f.id = mf.file_id
+            WHERE mf.message_id = ANY($1)
+            ORDER BY mf.message_id, mf.position
+            "#,
+        )
+        .bind(&ids)
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(messages)
+        for row in rows {
+            if let Some(message) = messages.iter_mut().find(|m| m.id == row.message_id) {
+                message.attachments.push(row.attachment);
+            }
+        }
+        Ok(())
     }
 }
 
+fn default_message_limit() -> u64 {
+    DEFAULT_MESSAGE_LIMIT
+}
+
+/// Message ids are BIGINT, larger cursors can't match any message
+fn cursor(id: u64) -> Result<i64, AppError> {
+    i64::try_from(id)
+        .map_err(|_| AppError::ListMessagesError(format!("cursor {} is out of range", id)))
+}
+
+fn next_id(id: i64) -> Result<i64, AppError> {
+    id.checked_add(1)
+        .ok_or_else(|| AppError::ListMessagesError(format!("cursor {} is out of range", id)))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::fs;
-
     use super::*;
 
     use anyhow::Result;
@@ -98,7 +498,10 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateMessage {
             content: "Hello, world!".to_string(),
+            format: MessageFormat::Plain,
             files: vec![],
+            sender_name: None,
+            client_msg_id: None,
         };
 
         let message = state
@@ -110,7 +513,10 @@ mod tests {
         // invalid files should fail
         let input = CreateMessage {
             content: "Hello, world!".to_string(),
-            files: vec!["invalid_file".to_string()],
+            format: MessageFormat::Plain,
+            files: vec![FileRef::Url("invalid_file".to_string())],
+            sender_name: None,
+            client_msg_id: None,
         };
 
         let err = state.create_message(input, 1, 1).await.unwrap_err();
@@ -120,10 +526,13 @@ mod tests {
         );
 
         // valid files should work
-        let url = upload_dummy_file(&state)?;
+        let url = upload_dummy_file(&state).await?;
         let input = CreateMessage {
             content: "Hello, world!".to_string(),
-            files: vec![url],
+            format: MessageFormat::Plain,
+            files: vec![FileRef::Url(url)],
+            sender_name: None,
+            client_msg_id: None,
         };
 
         let message = state
@@ -137,37 +546,247 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_message_should_be_idempotent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = state.create_file(1, 1, "a.txt", b"first").await?;
+        let input = |client_msg_id: &str| CreateMessage {
+            content: "Hello, world!".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![FileRef::Id(file.id)],
+            sender_name: None,
+            client_msg_id: Some(client_msg_id.to_string()),
+        };
+
+        let first = state.create_message(input("c1"), 1, 1).await?;
+        assert_eq!(first.client_msg_id.as_deref(), Some("c1"));
+        let retry = state.create_message(input("c1"), 1, 1).await?;
+        assert_eq!(retry, first);
+
+        // the key is scoped to the chat and the sender
+        let other = state.create_message(input("c1"), 1, 2).await?;
+        assert_ne!(other.id, first.id);
+        let other = state.create_message(input("c2"), 1, 1).await?;
+        assert_ne!(other.id, first.id);
+
+        let ret = state.create_message(input(""), 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let ret = state.create_message(input(&"x".repeat(65)), 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE client_msg_id IS NOT NULL")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(count, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_message_with_uploaded_files_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let first = state.create_file(1, 1, "a.txt", b"first").await?;
+        let second = state.create_file(1, 1, "b.txt", b"second").await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![FileRef::Id(second.id), FileRef::Id(first.id)],
+            sender_name: None,
+            client_msg_id: None,
+        };
+
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.files, vec![second.url.clone(), first.url.clone()]);
+        assert_eq!(message.attachments, vec![second.clone(), first.clone()]);
+
+        let input = ListMessages {
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1).await?.messages;
+        assert_eq!(messages[0].attachments, vec![second, first]);
+
+        // files of another workspace can't be attached
+        let other = state.create_file(2, 1, "c.txt", b"third").await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![FileRef::Id(other.id)],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_message_should_only_share_accessible_files() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = state.create_file(1, 1, "dm.txt", b"secret").await?;
+        let input = |files| CreateMessage {
+            content: "see attached".to_string(),
+            format: MessageFormat::Plain,
+            files,
+            sender_name: None,
+            client_msg_id: None,
+        };
+        // user 3 isn't in the direct chat of user 1 and 2
+        state
+            .create_message(input(vec![FileRef::Id(file.id)]), 3, 1)
+            .await?;
+        for files in [
+            vec![FileRef::Id(file.id)],
+            vec![FileRef::Url(file.url.clone())],
+        ] {
+            let ret = state.create_message(input(files), 4, 3).await;
+            assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        }
+
+        // members can forward what they received
+        state
+            .create_message(input(vec![FileRef::Url(file.url)]), 1, 2)
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_markdown_message_should_render_html() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "**ship it** <b>now</b>".to_string(),
+            format: MessageFormat::Markdown,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        };
+
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.format, MessageFormat::Markdown);
+        assert_eq!(message.content, "**ship it** <b>now</b>");
+        assert_eq!(
+            message.html,
+            "<p><strong>ship it</strong> &lt;b&gt;now&lt;/b&gt;</p>\n"
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_message_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = ListMessages {
-            last_id: None,
             limit: 6,
+            ..Default::default()
         };
 
-        let messages = state.list_message(input, 1).await?;
+        let page = state.list_message(input, 1).await?;
 
-        assert_eq!(messages.len(), 6);
+        assert_eq!(page.messages.len(), 6);
+        assert!(page.has_more_before);
+        assert!(!page.has_more_after);
 
-        let last_id = messages.last().expect("last message should exists").id;
+        let last_id = page.messages.last().expect("last message should exists").id;
 
         let input = ListMessages {
-            last_id: Some(last_id as _),
+            before: Some(last_id as _),
             limit: 6,
+            ..Default::default()
         };
 
-        let messages = state.list_message(input, 1).await?;
-        assert_eq!(messages.len(), 4);
+        let page = state.list_message(input, 1).await?;
+        assert_eq!(page.messages.len(), 4);
+        assert!(!page.has_more_before);
+        assert!(page.has_more_after);
 
         Ok(())
     }
 
-    fn upload_dummy_file(state: &AppState) -> Result<String> {
-        let file = ChatFile::new(1, "test.txt", b"hello world2");
-        let path = file.path(&state.config.server.base_dir);
-        fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
-        fs::write(path, b"hello world2")?;
+    #[tokio::test]
+    async fn list_message_after_and_around_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let all = state.list_message(ListMessages::default(), 1).await?;
+        let ids: Vec<i64> = all.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids.len(), 10);
+
+        // catch up from the third oldest message
+        let input = ListMessages {
+            after: Some(ids[7] as _),
+            limit: 4,
+            ..Default::default()
+        };
+        let page = state.list_message(input, 1).await?;
+        let got: Vec<i64> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(got, ids[3..7]);
+        assert!(page.has_more_before);
+        assert!(page.has_more_after);
+
+        let input = ListMessages {
+            around: Some(ids[5] as _),
+            limit: 5,
+            ..Default::default()
+        };
+        let page = state.list_message(input, 1).await?;
+        let got: Vec<i64> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(got, ids[3..8]);
+        assert!(page.has_more_before);
+        assert!(page.has_more_after);
+
+        // a message of another chat can't be jumped to
+        let input = CreateMessage {
+            content: "elsewhere".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        let other = state.create_message(input, 2, 1).await?;
+        let input = ListMessages {
+            around: Some(other.id as _),
+            ..Default::default()
+        };
+        let ret = state.list_message(input, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let input = ListMessages {
+            before: Some(ids[0] as _),
+            after: Some(ids[9] as _),
+            ..Default::default()
+        };
+        let ret = state.list_message(input, 1).await;
+        assert!(matches!(ret, Err(AppError::ListMessagesError(_))));
+
+        // cursors beyond the id range are rejected, not wrapped
+        for input in [
+            ListMessages {
+                before: Some(u64::MAX),
+                ..Default::default()
+            },
+            ListMessages {
+                after: Some(i64::MAX as u64),
+                ..Default::default()
+            },
+            ListMessages {
+                around: Some(u64::MAX),
+                ..Default::default()
+            },
+        ] {
+            let ret = state.list_message(input, 1).await;
+            assert!(matches!(ret, Err(AppError::ListMessagesError(_))));
+        }
+
+        // limits are clamped
+        let input = ListMessages {
+            limit: 0,
+            ..Default::default()
+        };
+        assert_eq!(state.list_message(input, 1).await?.messages.len(), 1);
+        Ok(())
+    }
 
-        Ok(file.url())
+    async fn upload_dummy_file(state: &AppState) -> Result<String> {
+        let file = state.create_file(1, 1, "test.txt", b"hello world2").await?;
+        Ok(file.url)
     }
 }
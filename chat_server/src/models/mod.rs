@@ -1,17 +1,85 @@
+mod api_token;
+mod bookmark;
+mod bot;
 mod chat;
+mod command;
+mod event_subscription;
+mod exif;
 mod file;
+mod file_policy;
+mod incoming_webhook;
+mod lockout;
+mod mention;
 mod messages;
+mod oidc;
+mod pin;
+mod rehash;
+mod reminder;
+mod retention;
+mod saved_message;
+mod scheduled_message;
+mod thumbnail;
+mod two_factor;
+mod upload;
 mod user;
 mod workspace;
 
+pub use api_token::{
+    ApiToken, ApiTokenVerifier, CreateApiToken, CreatedApiToken, API_TOKEN_PREFIX,
+};
+pub use bookmark::{CreateBookmark, UpdateBookmark};
+pub use bot::CreateBot;
 pub use chat::CreateChat;
-pub use messages::{CreateMessage, ListMessages};
+pub use command::{
+    BotCommandRequest, CommandInfo, CommandResponse, CustomCommand, ListCommands, RegisterCommand,
+    RegisteredCommand, ResponseType,
+};
+pub use event_subscription::{
+    CreateEventSubscription, CreatedEventSubscription, DeliveryStatus, EventDelivery,
+    EventSubscription, ListEventDeliveries, EVENT_TYPES,
+};
+pub(crate) use file::{parse_range, QUARANTINE_DIR, TEMP_DIR};
+pub use file::{FileQuery, SignFileUrl, SignedFileQuery, SignedFileUrl, TempFile};
+pub use file_policy::{UploadPolicy, UploadRejection, WorkspaceFileSettings};
+pub use incoming_webhook::{
+    CreateIncomingWebhook, CreatedIncomingWebhook, IncomingWebhook, WebhookAttachment,
+    WebhookAttachmentField, WebhookPayload,
+};
+pub use lockout::PgLockouts;
+pub use mention::{ListMentions, MentionInboxItem};
+pub use messages::{CreateMessage, FileRef, ListMessages, MessagePage};
+pub use oidc::{OidcCallback, OidcProvider, OidcSignin, UpsertOidcProvider};
+pub use pin::PinnedMessage;
+pub use reminder::{CreateReminder, Reminder};
+pub use retention::{CreateLegalHold, FileGcReport, LegalHold, StorageUsage};
+pub use saved_message::{ListSavedMessages, SavedMessage};
+pub use scheduled_message::{CreateScheduledMessage, ScheduledMessage, UpdateScheduledMessage};
 use serde::{Deserialize, Serialize};
+pub(crate) use thumbnail::thumbnail_key;
+pub use thumbnail::THUMBNAIL_SIZES;
+pub use two_factor::{RecoveryCodes, TotpEnrollment, TwoFactorCode, TwoFactorStatus};
+pub use upload::{Upload, UploadLocks, TUS_VERSION};
+pub(crate) use upload::{TUS_RESUMABLE_HEADER, TUS_VERSION_HEADER};
 pub use user::{CreateUser, SigninUser};
+pub use workspace::UpdateWorkspace;
+
+#[cfg(test)]
+pub(crate) use two_factor::totp_code;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ChatFile {
     pub workspace_id: u64,
     pub ext: String, // extract ext from filename or mime type
     pub hash: String,
+    pub algorithm: HashAlgorithm,
+}
+
+/// How the content of a file is addressed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// files uploaded before SHA-256, until they are rehashed
+    Sha1,
+    #[default]
+    Sha256,
 }
@@ -0,0 +1,777 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jwt_simple::{
+    prelude::{
+        ECDSAP256PublicKeyLike, ES256PublicKey, NoCustomClaims, RS256PublicKey, RSAPublicKeyLike,
+        VerificationOptions,
+    },
+    token::Token,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use url::Url;
+
+use crate::{AppError, AppState};
+
+use chat_core::User;
+
+const OIDC_SCOPE: &str = "openid email profile";
+// authorization requests not finished within 10 minutes are rejected
+const OIDC_REQUEST_TTL_SECS: i64 = 60 * 10;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct OidcProvider {
+    pub id: i64,
+    pub workspace_id: i64,
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(skip)]
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub auto_provision: bool,
+    // the IdP enforces mfa itself, otherwise the usual 2fa challenge applies
+    pub enforces_mfa: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpsertOidcProvider {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub auto_provision: bool,
+    #[serde(default)]
+    pub enforces_mfa: bool,
+}
+
+/// A user signed in through the IdP
+#[derive(Debug, Clone)]
+pub struct OidcSignin {
+    pub user: User,
+    pub enforces_mfa: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcCallback {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct OidcAuthRequest {
+    provider_id: i64,
+    nonce: String,
+    code_verifier: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcDiscovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+/// A public key of the IdP, RSA keys have `n` and `e`, EC keys `x` and `y`
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcTokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+}
+
+impl AppState {
+    /// Create or replace the identity provider of a workspace, owner only
+    pub async fn upsert_oidc_provider(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+        input: &UpsertOidcProvider,
+    ) -> Result<OidcProvider, AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        let issuer = Url::parse(&input.issuer).map_err(|e| AppError::OidcError(e.to_string()))?;
+        if !self.config.outbound.allows_scheme(issuer.scheme()) {
+            return Err(AppError::OidcError(format!(
+                "issuer scheme is not allowed: {}",
+                input.issuer
+            )));
+        }
+        Url::parse(&input.redirect_uri).map_err(|e| AppError::OidcError(e.to_string()))?;
+
+        let provider = sqlx::query_as(
+            r#"
+            INSERT INTO oidc_providers (workspace_id, issuer, client_id, client_secret, redirect_uri, auto_provision, enforces_mfa)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (workspace_id) DO UPDATE
+            SET issuer = EXCLUDED.issuer,
+                client_id = EXCLUDED.client_id,
+                client_secret = EXCLUDED.client_secret,
+                redirect_uri = EXCLUDED.redirect_uri,
+                auto_provision = EXCLUDED.auto_provision,
+                enforces_mfa = EXCLUDED.enforces_mfa
+            RETURNING id, workspace_id, issuer, client_id, client_secret, redirect_uri, auto_provision, enforces_mfa, created_at
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .bind(input.issuer.trim_end_matches('/'))
+        .bind(&input.client_id)
+        .bind(&input.client_secret)
+        .bind(&input.redirect_uri)
+        .bind(input.auto_provision)
+        .bind(input.enforces_mfa)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(provider)
+    }
+
+    pub async fn delete_oidc_provider(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        sqlx::query("DELETE FROM oidc_providers WHERE workspace_id = $1")
+            .bind(workspace_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn find_oidc_provider_by_workspace_name(
+        &self,
+        name: &str,
+    ) -> Result<Option<OidcProvider>, AppError> {
+        let provider = sqlx::query_as(
+            r#"
+            SELECT p.id, p.workspace_id, p.issuer, p.client_id, p.client_secret, p.redirect_uri, p.auto_provision, p.enforces_mfa, p.created_at
+            FROM oidc_providers p
+            JOIN workspaces w ON w.id = p.workspace_id
+            WHERE w.name = $1
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(provider)
+    }
+
+    /// Start an authorization code + PKCE flow, returns the url to redirect the user to
+    pub async fn oidc_authorize_url(&self, workspace: &str) -> Result<String, AppError> {
+        let Some(provider) = self.find_oidc_provider_by_workspace_name(workspace).await? else {
+            return Err(AppError::NotFound(format!(
+                "openid connect is not configured for workspace {}",
+                workspace
+            )));
+        };
+        let discovery = self.oidc_discover(&provider.issuer).await?;
+        self.purge_expired_oidc_requests().await?;
+
+        let state = gen_random_token();
+        let nonce = gen_random_token();
+        let code_verifier = gen_random_token();
+
+        sqlx::query(
+            r#"
+            INSERT INTO oidc_auth_requests (state, provider_id, nonce, code_verifier)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(&state)
+        .bind(provider.id)
+        .bind(&nonce)
+        .bind(&code_verifier)
+        .execute(&self.pool)
+        .await?;
+
+        let mut url = Url::parse(&discovery.authorization_endpoint)
+            .map_err(|e| AppError::OidcError(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &provider.redirect_uri)
+            .append_pair("scope", OIDC_SCOPE)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &pkce_challenge(&code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+
+    /// Finish the flow started by `oidc_authorize_url`, map the IdP email to a user
+    pub async fn oidc_signin(&self, code: &str, state: &str) -> Result<OidcSignin, AppError> {
+        self.purge_expired_oidc_requests().await?;
+        // a state can only be used once
+        let req: Option<OidcAuthRequest> = sqlx::query_as(
+            r#"
+            DELETE FROM oidc_auth_requests
+            WHERE state = $1 AND created_at > NOW() - make_interval(secs => $2)
+            RETURNING provider_id, nonce, code_verifier
+            "#,
+        )
+        .bind(state)
+        .bind(OIDC_REQUEST_TTL_SECS as f64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(req) = req else {
+            return Err(AppError::OidcError("invalid or expired state".to_string()));
+        };
+
+        let provider: OidcProvider = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, issuer, client_id, client_secret, redirect_uri, auto_provision, enforces_mfa, created_at
+            FROM oidc_providers
+            WHERE id = $1
+            "#,
+        )
+        .bind(req.provider_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::OidcError("provider no longer exists".to_string()))?;
+
+        let discovery = self.oidc_discover(&provider.issuer).await?;
+        let ret: OidcTokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &provider.redirect_uri),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+                ("code_verifier", &req.code_verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        self.verify_id_token_signature(&ret.id_token, &discovery.jwks_uri)
+            .await?;
+        let claims = decode_id_token(&ret.id_token)?;
+        let email = validate_id_token(&claims, &provider, &req.nonce, Utc::now().timestamp())?;
+
+        let user = match self.find_user_by_email(email).await? {
+            Some(user) if user.workspace_id == provider.workspace_id => user,
+            Some(_) => {
+                return Err(AppError::OidcError(format!(
+                    "user {} belongs to another workspace",
+                    email
+                )))
+            }
+            None if provider.auto_provision => {
+                let fullname = claims.name.as_deref().unwrap_or(email);
+                self.provision_oidc_user(provider.workspace_id as _, fullname, email)
+                    .await?
+            }
+            None => return Err(AppError::OidcError(format!("user {} not found", email))),
+        };
+        Ok(OidcSignin {
+            user,
+            enforces_mfa: provider.enforces_mfa,
+        })
+    }
+
+    async fn oidc_discover(&self, issuer: &str) -> Result<OidcDiscovery, AppError> {
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let discovery = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(discovery)
+    }
+
+    /// Verify the id token is signed by a key of the IdP, RS256 or ES256
+    async fn verify_id_token_signature(&self, token: &str, jwks_uri: &str) -> Result<(), AppError> {
+        let metadata = Token::decode_metadata(token)
+            .map_err(|e| AppError::OidcError(format!("malformed id token: {}", e)))?;
+        let kty = match metadata.algorithm() {
+            "RS256" => "RSA",
+            "ES256" => "EC",
+            alg => {
+                return Err(AppError::OidcError(format!(
+                    "unsupported id token algorithm {}",
+                    alg
+                )))
+            }
+        };
+        let jwks: Jwks = self
+            .http
+            .get(jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwk = jwks
+            .keys
+            .iter()
+            .find(|k| {
+                k.kty == kty
+                    && (metadata.key_id().is_none() || metadata.key_id() == k.kid.as_deref())
+            })
+            .ok_or_else(|| AppError::OidcError("id token signing key not found".to_string()))?;
+
+        let options = Some(VerificationOptions::default());
+        let ret = if kty == "RSA" {
+            RS256PublicKey::from_components(&jwk_param(&jwk.n)?, &jwk_param(&jwk.e)?)
+                .and_then(|key| key.verify_token::<NoCustomClaims>(token, options))
+        } else {
+            // uncompressed SEC1 point
+            let mut point = vec![0x04];
+            point.extend(jwk_param(&jwk.x)?);
+            point.extend(jwk_param(&jwk.y)?);
+            ES256PublicKey::from_bytes(&point)
+                .and_then(|key| key.verify_token::<NoCustomClaims>(token, options))
+        };
+        ret.map_err(|e| AppError::OidcError(format!("invalid id token: {}", e)))?;
+        Ok(())
+    }
+
+    /// Authorization requests that weren't finished in time
+    async fn purge_expired_oidc_requests(&self) -> Result<(), AppError> {
+        sqlx::query(
+            "DELETE FROM oidc_auth_requests WHERE created_at <= NOW() - make_interval(secs => $1)",
+        )
+        .bind(OIDC_REQUEST_TTL_SECS as f64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn provision_oidc_user(
+        &self,
+        workspace_id: u64,
+        fullname: &str,
+        email: &str,
+    ) -> Result<User, AppError> {
+        let Some(ws) = self.find_workspace_by_id(workspace_id).await? else {
+            return Err(AppError::NotFound(format!(
+                "workspace id {} not found",
+                workspace_id
+            )));
+        };
+
+        // fullname is unique, fall back to the email if it's taken
+        let taken = sqlx::query("SELECT 1 FROM users WHERE fullname = $1")
+            .bind(fullname)
+            .fetch_optional(&self.pool)
+            .await?;
+        let fullname = if taken.is_some() { email } else { fullname };
+
+        let user = sqlx::query_as(
+            r#"
+            INSERT INTO users (workspace_id, workspace, fullname, email)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, workspace_id, fullname, email, created_at
+            "#,
+        )
+        .bind(ws.id)
+        .bind(&ws.name)
+        .bind(fullname)
+        .bind(email)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+}
+
+fn jwk_param(param: &Option<String>) -> Result<Vec<u8>, AppError> {
+    param
+        .as_deref()
+        .and_then(|v| URL_SAFE_NO_PAD.decode(v).ok())
+        .ok_or_else(|| AppError::OidcError("malformed id token signing key".to_string()))
+}
+
+fn decode_id_token(token: &str) -> Result<IdTokenClaims, AppError> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| AppError::OidcError("malformed id token".to_string()))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|e| AppError::OidcError(format!("malformed id token: {}", e)))?;
+    serde_json::from_slice(&payload)
+        .map_err(|e| AppError::OidcError(format!("malformed id token: {}", e)))
+}
+
+// returns the verified email of the id token
+fn validate_id_token<'a>(
+    claims: &'a IdTokenClaims,
+    provider: &OidcProvider,
+    nonce: &str,
+    now: i64,
+) -> Result<&'a str, AppError> {
+    if claims.iss.trim_end_matches('/') != provider.issuer {
+        return Err(AppError::OidcError(format!(
+            "invalid issuer {}",
+            claims.iss
+        )));
+    }
+    let aud_ok = match &claims.aud {
+        Audience::One(aud) => aud == &provider.client_id,
+        Audience::Many(auds) => auds.contains(&provider.client_id),
+    };
+    if !aud_ok {
+        return Err(AppError::OidcError("invalid audience".to_string()));
+    }
+    if claims.exp <= now {
+        return Err(AppError::OidcError("id token expired".to_string()));
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(AppError::OidcError("invalid nonce".to_string()));
+    }
+    if claims.email_verified != Some(true) {
+        return Err(AppError::OidcError("email is not verified".to_string()));
+    }
+    claims
+        .email
+        .as_deref()
+        .ok_or_else(|| AppError::OidcError("id token has no email".to_string()))
+}
+
+fn gen_random_token() -> String {
+    let mut buf = [0u8; 32];
+    OsRng.fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[cfg(test)]
+impl UpsertOidcProvider {
+    pub fn new(issuer: &str, auto_provision: bool) -> Self {
+        Self {
+            issuer: issuer.to_string(),
+            client_id: "chat".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_uri: "http://localhost:6688/api/oidc/callback".to_string(),
+            auto_provision,
+            enforces_mfa: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::Result;
+    use axum::{
+        body::to_bytes,
+        extract::{Query, State},
+        http::StatusCode,
+        response::IntoResponse,
+        routing::get,
+        routing::post,
+        Form, Json, Router,
+    };
+    use jwt_simple::prelude::{Claims, Duration, ECDSAP256KeyPairLike, ES256KeyPair};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        handlers::{oidc_callback_handler, AuthOutput, TwoFactorChallenge},
+        AppConfig, SigninUser, UpdateWorkspace,
+    };
+
+    #[derive(Clone)]
+    struct MockIdp(Arc<Mutex<MockIdpInner>>);
+
+    struct MockIdpInner {
+        issuer: String,
+        key: ES256KeyPair,
+        email: String,
+        email_verified: Option<bool>,
+        // sign with a key that isn't published
+        forged: bool,
+        nonce: String,
+        code_challenge: String,
+    }
+
+    // the mock idp doesn't speak tls
+    async fn new_state() -> Result<(sqlx_db_tester::TestPg, AppState)> {
+        let mut config = AppConfig::load()?;
+        config.outbound.allowed_schemes.push("http".to_string());
+        Ok(AppState::new_for_test_with_config(config).await?)
+    }
+
+    async fn start_mock_idp() -> Result<MockIdp> {
+        let idp = MockIdp(Arc::new(Mutex::new(MockIdpInner {
+            issuer: String::new(),
+            key: ES256KeyPair::generate().with_key_id("k1"),
+            email: String::new(),
+            email_verified: Some(true),
+            forged: false,
+            nonce: String::new(),
+            code_challenge: String::new(),
+        })));
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(idp.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        idp.0.lock().unwrap().issuer = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Ok(idp)
+    }
+
+    async fn discovery(State(idp): State<MockIdp>) -> Json<serde_json::Value> {
+        let issuer = idp.0.lock().unwrap().issuer.clone();
+        Json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        }))
+    }
+
+    async fn jwks(State(idp): State<MockIdp>) -> Json<serde_json::Value> {
+        let idp = idp.0.lock().unwrap();
+        let point = idp.key.public_key().public_key().to_bytes_uncompressed();
+        Json(json!({
+            "keys": [{
+                "kty": "EC",
+                "kid": "k1",
+                "crv": "P-256",
+                "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            }]
+        }))
+    }
+
+    async fn token(
+        State(idp): State<MockIdp>,
+        Form(input): Form<HashMap<String, String>>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        let idp = idp.0.lock().unwrap();
+        let verifier = input.get("code_verifier").cloned().unwrap_or_default();
+        if input.get("code").map(|v| v.as_str()) != Some("mock-code")
+            || input.get("client_secret").map(|v| v.as_str()) != Some("secret")
+            || pkce_challenge(&verifier) != idp.code_challenge
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let custom = json!({
+            "email": idp.email,
+            "email_verified": idp.email_verified,
+            "name": "Tyr",
+        });
+        let claims = Claims::with_custom_claims(custom, Duration::from_secs(60))
+            .with_issuer(&idp.issuer)
+            .with_audience("chat")
+            .with_nonce(&idp.nonce);
+        let id_token = if idp.forged {
+            ES256KeyPair::generate().with_key_id("k1").sign(claims)
+        } else {
+            idp.key.sign(claims)
+        }
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        Ok(Json(json!({ "id_token": id_token, "access_token": "at" })))
+    }
+
+    // run the authorize step and let the mock idp accept it for the given email
+    async fn authorize(state: &AppState, idp: &MockIdp, email: &str) -> Result<String> {
+        let url = Url::parse(&state.oidc_authorize_url("acme").await?)?;
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(params["client_id"], "chat");
+
+        let mut inner = idp.0.lock().unwrap();
+        inner.email = email.to_string();
+        inner.nonce = params["nonce"].clone();
+        inner.code_challenge = params["code_challenge"].clone();
+        Ok(params["state"].clone())
+    }
+
+    #[tokio::test]
+    async fn oidc_signin_should_map_existing_user() -> Result<()> {
+        let (_tdb, state) = new_state().await?;
+        let idp = start_mock_idp().await?;
+        let issuer = idp.0.lock().unwrap().issuer.clone();
+
+        // only the owner can configure the provider
+        let input = UpsertOidcProvider::new(&issuer, false);
+        assert!(state.upsert_oidc_provider(1, 1, &input).await.is_err());
+        state.update_workspace_owner(1, 1).await?;
+        let provider = state.upsert_oidc_provider(1, 1, &input).await?;
+        assert_eq!(provider.issuer, issuer);
+
+        let oidc_state = authorize(&state, &idp, "wukun@gmail.com").await?;
+        let user = state.oidc_signin("mock-code", &oidc_state).await?.user;
+        assert_eq!(user.id, 2);
+
+        // state can't be replayed
+        let ret = state.oidc_signin("mock-code", &oidc_state).await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+
+        // bad code is rejected by the idp
+        let oidc_state = authorize(&state, &idp, "wukun@gmail.com").await?;
+        assert!(state.oidc_signin("bad-code", &oidc_state).await.is_err());
+
+        // unknown user without auto provision
+        let oidc_state = authorize(&state, &idp, "tyr@acme.org").await?;
+        let ret = state.oidc_signin("mock-code", &oidc_state).await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_callback_should_challenge_2fa() -> Result<()> {
+        let (_tdb, state) = new_state().await?;
+        let idp = start_mock_idp().await?;
+        let issuer = idp.0.lock().unwrap().issuer.clone();
+        state.update_workspace_owner(1, 1).await?;
+        let mut input = UpsertOidcProvider::new(&issuer, false);
+        state.upsert_oidc_provider(1, 1, &input).await?;
+        state
+            .update_workspace(1, 1, &UpdateWorkspace::new(true))
+            .await?;
+
+        let callback = |state: String| {
+            Query(OidcCallback {
+                code: Some("mock-code".to_string()),
+                state,
+                error: None,
+                error_description: None,
+            })
+        };
+        let oidc_state = authorize(&state, &idp, "wukun@gmail.com").await?;
+        let ret = oidc_callback_handler(State(state.clone()), callback(oidc_state))
+            .await?
+            .into_response();
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let challenge = serde_json::from_slice::<TwoFactorChallenge>(&body)?;
+        assert!(challenge.enrollment_required);
+        assert!(state.dk.verify(&challenge.challenge_token).is_err());
+
+        // the idp asks for a second factor itself
+        input.enforces_mfa = true;
+        state.upsert_oidc_provider(1, 1, &input).await?;
+        let oidc_state = authorize(&state, &idp, "wukun@gmail.com").await?;
+        let ret = oidc_callback_handler(State(state.clone()), callback(oidc_state))
+            .await?
+            .into_response();
+        let body = to_bytes(ret.into_body(), usize::MAX).await?;
+        let ret = serde_json::from_slice::<AuthOutput>(&body)?;
+        assert_eq!(state.dk.verify(&ret.token)?.id, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_signin_should_provision_user() -> Result<()> {
+        let (_tdb, state) = new_state().await?;
+        let idp = start_mock_idp().await?;
+        let issuer = idp.0.lock().unwrap().issuer.clone();
+        state.update_workspace_owner(1, 1).await?;
+        state
+            .upsert_oidc_provider(1, 1, &UpsertOidcProvider::new(&issuer, true))
+            .await?;
+
+        let oidc_state = authorize(&state, &idp, "tyr@acme.org").await?;
+        let user = state.oidc_signin("mock-code", &oidc_state).await?.user;
+        assert_eq!(user.email, "tyr@acme.org");
+        assert_eq!(user.fullname, "Tyr");
+        assert_eq!(user.workspace_id, 1);
+
+        // provisioned users can't sign in with a password
+        let input = SigninUser::new("tyr@acme.org", "");
+        assert!(state.verify_user(&input).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_signin_should_reject_untrusted_id_tokens() -> Result<()> {
+        let (_tdb, state) = new_state().await?;
+        let idp = start_mock_idp().await?;
+        let issuer = idp.0.lock().unwrap().issuer.clone();
+        state.update_workspace_owner(1, 1).await?;
+        let input = UpsertOidcProvider::new("ftp://idp.acme.org", false);
+        let ret = state.upsert_oidc_provider(1, 1, &input).await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+        state
+            .upsert_oidc_provider(1, 1, &UpsertOidcProvider::new(&issuer, false))
+            .await?;
+
+        idp.0.lock().unwrap().forged = true;
+        let oidc_state = authorize(&state, &idp, "wukun@gmail.com").await?;
+        let ret = state.oidc_signin("mock-code", &oidc_state).await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+        idp.0.lock().unwrap().forged = false;
+
+        for email_verified in [None, Some(false)] {
+            idp.0.lock().unwrap().email_verified = email_verified;
+            let oidc_state = authorize(&state, &idp, "wukun@gmail.com").await?;
+            let ret = state.oidc_signin("mock-code", &oidc_state).await;
+            assert!(matches!(ret, Err(AppError::OidcError(_))));
+        }
+
+        // expired authorization requests are purged
+        authorize(&state, &idp, "wukun@gmail.com").await?;
+        sqlx::query("UPDATE oidc_auth_requests SET created_at = NOW() - INTERVAL '1 hour'")
+            .execute(&state.pool)
+            .await?;
+        authorize(&state, &idp, "wukun@gmail.com").await?;
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM oidc_auth_requests")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(count, 1);
+        Ok(())
+    }
+}
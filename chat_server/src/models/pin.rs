@@ -0,0 +1,194 @@
+use chat_core::{Message, Pin};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{AppError, AppState};
+
+/// A pinned message with who pinned it, most recently pinned first
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PinnedMessage {
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+    pub message: Message,
+}
+
+#[derive(Debug, FromRow)]
+struct PinRow {
+    pinned_by: i64,
+    pinned_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    message: Message,
+}
+
+impl AppState {
+    /// Pinning a pinned message again returns the existing pin
+    pub async fn pin_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Pin, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // serializes pins of the chat, so the cap can't be raced past
+        let exists: Option<i64> =
+            sqlx::query_scalar("SELECT id FROM chats WHERE id = $1 FOR UPDATE")
+                .bind(chat_id as i64)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if exists.is_none() {
+            return Err(AppError::NotFound(format!("chat id {} not found", chat_id)));
+        }
+        let in_chat: Option<i64> =
+            sqlx::query_scalar("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(message_id as i64)
+                .bind(chat_id as i64)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if in_chat.is_none() {
+            return Err(AppError::NotFound(format!(
+                "message {} not found",
+                message_id
+            )));
+        }
+
+        let pin: Option<Pin> = sqlx::query_as(
+            r#"
+            SELECT chat_id, message_id, pinned_by, created_at
+            FROM pinned_messages
+            WHERE chat_id = $1 AND message_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(pin) = pin {
+            return Ok(pin);
+        }
+
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM pinned_messages WHERE chat_id = $1")
+                .bind(chat_id as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+        let max_pins = self.config.chats.max_pins;
+        if count as u64 >= max_pins {
+            return Err(AppError::PinError(format!(
+                "a chat can have at most {} pinned messages",
+                max_pins
+            )));
+        }
+
+        let pin = sqlx::query_as(
+            r#"
+            INSERT INTO pinned_messages (chat_id, message_id, pinned_by)
+            VALUES ($1, $2, $3)
+            RETURNING chat_id, message_id, pinned_by, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(pin)
+    }
+
+    pub async fn unpin_message(&self, chat_id: u64, message_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM pinned_messages WHERE chat_id = $1 AND message_id = $2")
+            .bind(chat_id as i64)
+            .bind(message_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "message {} is not pinned",
+                message_id
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn list_pins(&self, chat_id: u64) -> Result<Vec<PinnedMessage>, AppError> {
+        let rows: Vec<PinRow> = sqlx::query_as(
+            r#"
+            SELECT p.pinned_by, p.created_at AS pinned_at, m.id, m.chat_id, m.sender_id,
+                m.sender_name, m.content, m.format, m.html, m.files, m.client_msg_id,
+                m.created_at
+            FROM pinned_messages p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.chat_id = $1
+            ORDER BY p.created_at DESC, p.message_id DESC
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut messages: Vec<Message> = rows.iter().map(|r| r.message.clone()).collect();
+        self.load_attachments(&mut messages).await?;
+        let pins = rows
+            .into_iter()
+            .zip(messages)
+            .map(|(row, message)| PinnedMessage {
+                pinned_by: row.pinned_by,
+                pinned_at: row.pinned_at,
+                message,
+            })
+            .collect();
+
+        Ok(pins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pin_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let messages = state.list_message(Default::default(), 1).await?.messages;
+        let first = state.pin_message(1, messages[3].id as _, 1).await?;
+        state.pin_message(1, messages[0].id as _, 2).await?;
+        // pinning twice keeps the first pin
+        let again = state.pin_message(1, messages[3].id as _, 3).await?;
+        assert_eq!(again, first);
+
+        let pins = state.list_pins(1).await?;
+        let ids: Vec<i64> = pins.iter().map(|p| p.message.id).collect();
+        assert_eq!(ids, vec![messages[0].id, messages[3].id]);
+        assert_eq!(pins[1].pinned_by, 1);
+
+        state.unpin_message(1, messages[3].id as _).await?;
+        assert_eq!(state.list_pins(1).await?.len(), 1);
+        let ret = state.unpin_message(1, messages[3].id as _).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // messages of other chats can't be pinned
+        let ret = state.pin_message(2, messages[1].id as _, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_message_should_respect_the_cap() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        config.chats.max_pins = 2;
+        let (_tdb, state) = AppState::new_for_test_with_config(config).await?;
+        let messages = state.list_message(Default::default(), 1).await?.messages;
+        state.pin_message(1, messages[0].id as _, 1).await?;
+        state.pin_message(1, messages[1].id as _, 1).await?;
+        let ret = state.pin_message(1, messages[2].id as _, 1).await;
+        assert!(matches!(ret, Err(AppError::PinError(_))));
+
+        state.unpin_message(1, messages[0].id as _).await?;
+        state.pin_message(1, messages[2].id as _, 1).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use std::{path::Path, str::FromStr};
+
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncReadExt};
+use tracing::{info, warn};
+
+use crate::{
+    models::thumbnail_key, AppError, AppState, ChatFile, HashAlgorithm, TempFile, THUMBNAIL_SIZES,
+};
+
+const REHASH_BATCH_SIZE: i64 = 100;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+impl AppState {
+    /// Move the next batch of SHA-1 files after `after` to their SHA-256
+    /// address, returns the last url of the batch or `None` once all are done.
+    /// Files that fail are logged and skipped.
+    pub async fn rehash_files(&self, after: &str) -> Result<Option<String>, AppError> {
+        // quarantined files aren't in the storage
+        let urls: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT url FROM files
+            WHERE length(hash) = 40 AND scan_status <> 'infected' AND url > $1
+            ORDER BY url
+            LIMIT $2
+            "#,
+        )
+        .bind(after)
+        .bind(REHASH_BATCH_SIZE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for url in &urls {
+            if let Err(e) = self.rehash_file(url).await {
+                warn!("Failed to rehash file {}: {}", url, e);
+            }
+        }
+        Ok(urls.last().cloned())
+    }
+
+    /// Copy the content and its thumbnails to the SHA-256 address, then point
+    /// the files, chats and messages at the new url and drop the old content
+    pub async fn rehash_file(&self, url: &str) -> Result<String, AppError> {
+        let old = ChatFile::from_str(url)?;
+        if old.algorithm != HashAlgorithm::Sha1 {
+            return Ok(url.to_string());
+        }
+        let base_dir = &self.config.server.base_dir;
+        let old_key = old.hash_to_path();
+        let temp = TempFile::new(base_dir, u64::MAX).await?;
+        self.storage.download(&old_key, temp.path()).await?;
+        let hash = sha256_file(temp.path()).await?;
+        let new = ChatFile {
+            hash,
+            algorithm: HashAlgorithm::Sha256,
+            ..old
+        };
+        let new_key = new.hash_to_path();
+        let new_url = new.url();
+        if !self.storage.exists(&new_key).await? {
+            self.storage.put_file(&new_key, temp.path()).await?;
+        }
+        for &size in THUMBNAIL_SIZES {
+            let (from, to) = (thumbnail_key(&old_key, size), thumbnail_key(&new_key, size));
+            if self.storage.exists(&from).await? && !self.storage.exists(&to).await? {
+                let temp = TempFile::new(base_dir, u64::MAX).await?;
+                self.storage.download(&from, temp.path()).await?;
+                self.storage.put_file(&to, temp.path()).await?;
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE files SET hash = $1, url = $2 WHERE url = $3")
+            .bind(&new.hash)
+            .bind(&new_url)
+            .bind(url)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO chat_files (chat_id, url)
+            SELECT chat_id, $1 FROM chat_files WHERE url = $2
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(&new_url)
+        .bind(url)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM chat_files WHERE url = $1")
+            .bind(url)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE messages SET files = array_replace(files, $1, $2) WHERE $1 = ANY(files)",
+        )
+        .bind(url)
+        .bind(&new_url)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE scheduled_messages SET files = array_replace(files, $1, $2) WHERE $1 = ANY(files)",
+        )
+        .bind(url)
+        .bind(&new_url)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE chat_bookmarks SET file_url = $2 WHERE file_url = $1")
+            .bind(url)
+            .bind(&new_url)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        for &size in THUMBNAIL_SIZES {
+            self.storage.delete(&thumbnail_key(&old_key, size)).await?;
+        }
+        self.storage.delete(&old_key).await?;
+        Ok(new_url)
+    }
+
+    /// Rehash all SHA-1 files in the background, once per start
+    pub fn spawn_file_rehash(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut after = String::new();
+            loop {
+                match state.rehash_files(&after).await {
+                    Ok(Some(last)) => after = last,
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Failed to rehash files: {}", e);
+                        return;
+                    }
+                }
+            }
+            if !after.is_empty() {
+                info!("Rehashed files up to {}", after);
+            }
+        });
+    }
+}
+
+async fn sha256_file(path: &Path) -> Result<String, AppError> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chat_core::Attachment;
+    use chrono::{Duration, Utc};
+    use sha1::Sha1;
+
+    use super::*;
+    use crate::{CreateMessage, CreateScheduledMessage, FileRef, ListMessages};
+
+    /// A file stored the way uploads were before SHA-256
+    async fn create_sha1_file(state: &AppState, data: &[u8]) -> Result<Attachment> {
+        let hash = hex::encode(Sha1::digest(data));
+        let file = ChatFile::with_hash(1, "old.txt", HashAlgorithm::Sha1, hash);
+        let mut temp = TempFile::new(&state.config.server.base_dir, u64::MAX).await?;
+        temp.write(data).await?;
+        temp.flush().await?;
+        state
+            .storage
+            .put_file(&file.hash_to_path(), temp.path())
+            .await?;
+        let attachment = sqlx::query_as(
+            r#"
+            INSERT INTO files (workspace_id, uploader_id, name, size, mime, hash, url)
+            VALUES (1, 1, 'old.txt', $1, 'text/plain', $2, $3)
+            RETURNING id, workspace_id, uploader_id, name, size, mime, hash, url, width, height,
+                blurhash, scan_status, created_at
+            "#,
+        )
+        .bind(data.len() as i64)
+        .bind(&file.hash)
+        .bind(file.url())
+        .fetch_one(&state.pool)
+        .await?;
+        Ok(attachment)
+    }
+
+    #[tokio::test]
+    async fn rehash_files_should_move_sha1_files() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let data = b"legacy content";
+        let old = create_sha1_file(&state, data).await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            format: Default::default(),
+            files: vec![FileRef::Id(old.id)],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        state.create_message(input, 1, 1).await?;
+        let input = CreateScheduledMessage {
+            content: "see attached, again".to_string(),
+            format: Default::default(),
+            files: vec![FileRef::Url(old.url.clone())],
+            send_at: Utc::now() + Duration::hours(1),
+        };
+        state.create_scheduled_message(1, 1, input).await?;
+
+        let mut after = String::new();
+        while let Some(last) = state.rehash_files(&after).await? {
+            after = last;
+        }
+        assert_eq!(after, old.url);
+
+        let new_url = ChatFile::new(1, "old.txt", data).url();
+        let old_key = ChatFile::from_str(&old.url)?.hash_to_path();
+        let new_key = ChatFile::from_str(&new_url)?.hash_to_path();
+        assert!(!state.storage.exists(&old_key).await?);
+        assert!(state.storage.exists(&new_key).await?);
+        assert!(state.can_access_file(2, &new_url).await?);
+        assert!(!state.can_access_file(2, &old.url).await?);
+
+        let input = ListMessages {
+            limit: 10,
+            ..Default::default()
+        };
+        let messages = state.list_message(input, 1).await?.messages;
+        assert_eq!(messages[0].files, vec![new_url.clone()]);
+        assert_eq!(messages[0].attachments[0].url, new_url);
+        let scheduled = state.list_scheduled_messages(1).await?;
+        assert_eq!(scheduled[0].files, vec![FileRef::Url(new_url.clone())]);
+        assert_eq!(state.rehash_files("").await?, None);
+        Ok(())
+    }
+}
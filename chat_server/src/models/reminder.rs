@@ -0,0 +1,492 @@
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::{Message, MessageFormat};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::warn;
+
+use crate::{AppError, AppState, CreateMessage};
+
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const REMINDER_BATCH_SIZE: i64 = 100;
+const MAX_REMINDER_ATTEMPTS: i32 = 5;
+const REMINDER_SENDER_NAME: &str = "Reminder";
+const MAX_REMINDER_NOTE_LEN: usize = 1000;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Reminder {
+    pub id: i64,
+    pub user_id: i64,
+    pub chat_id: i64,
+    /// set for "remind me about this", the message is quoted in the reminder
+    pub message_id: Option<i64>,
+    pub content: String,
+    pub remind_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// why it couldn't be delivered
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateReminder {
+    pub message_id: u64,
+    pub remind_at: DateTime<Utc>,
+    #[serde(default)]
+    pub note: String,
+}
+
+impl AppState {
+    pub async fn create_reminder(
+        &self,
+        user_id: u64,
+        chat_id: u64,
+        content: &str,
+        remind_at: DateTime<Utc>,
+    ) -> Result<Reminder, AppError> {
+        let reminder = sqlx::query_as(
+            r#"
+            INSERT INTO reminders (user_id, chat_id, content, remind_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, chat_id, message_id, content, remind_at, delivered_at, error,
+                attempts, created_at
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(chat_id as i64)
+        .bind(content)
+        .bind(remind_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(reminder)
+    }
+
+    /// Remind the user about a message they can see
+    pub async fn create_message_reminder(
+        &self,
+        user_id: u64,
+        input: &CreateReminder,
+    ) -> Result<Reminder, AppError> {
+        let note = input.note.trim();
+        if note.chars().count() > MAX_REMINDER_NOTE_LEN {
+            return Err(AppError::ReminderError(format!(
+                "note must be at most {} characters",
+                MAX_REMINDER_NOTE_LEN
+            )));
+        }
+        if input.remind_at <= Utc::now() {
+            return Err(AppError::ReminderError(
+                "remind_at must be in the future".to_string(),
+            ));
+        }
+        let message = self.find_visible_message(input.message_id, user_id).await?;
+
+        let reminder = sqlx::query_as(
+            r#"
+            INSERT INTO reminders (user_id, chat_id, message_id, content, remind_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, chat_id, message_id, content, remind_at, delivered_at, error,
+                attempts, created_at
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(message.chat_id)
+        .bind(message.id)
+        .bind(note)
+        .bind(input.remind_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(reminder)
+    }
+
+    /// Reminders of the user that are still to be delivered, soonest first
+    pub async fn list_reminders(&self, user_id: u64) -> Result<Vec<Reminder>, AppError> {
+        let reminders = sqlx::query_as(
+            r#"
+            SELECT id, user_id, chat_id, message_id, content, remind_at, delivered_at, error,
+                attempts, created_at
+            FROM reminders
+            WHERE user_id = $1 AND delivered_at IS NULL
+            ORDER BY remind_at, id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reminders)
+    }
+
+    pub async fn delete_reminder(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM reminders WHERE id = $1 AND user_id = $2")
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("reminder {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Send reminders that are due, each one is delivered once. Reminders are
+    /// claimed without holding a lock while they are sent, their client
+    /// message id keeps a retry, e.g. by another server or after a restart,
+    /// from sending them twice
+    pub async fn deliver_due_reminders(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Message>, AppError> {
+        let reminders: Vec<Reminder> = sqlx::query_as(
+            r#"
+            UPDATE reminders
+            SET attempts = attempts + 1
+            WHERE id IN (
+                SELECT id
+                FROM reminders
+                WHERE delivered_at IS NULL AND error IS NULL AND remind_at <= $1
+                ORDER BY remind_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, user_id, chat_id, message_id, content, remind_at, delivered_at, error,
+                attempts, created_at
+            "#,
+        )
+        .bind(now)
+        .bind(REMINDER_BATCH_SIZE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut messages = Vec::with_capacity(reminders.len());
+        for reminder in reminders {
+            match self.deliver_reminder(&reminder).await {
+                Ok(message) => {
+                    sqlx::query("UPDATE reminders SET delivered_at = $2 WHERE id = $1")
+                        .bind(reminder.id)
+                        .bind(now)
+                        .execute(&self.pool)
+                        .await?;
+                    messages.push(message);
+                }
+                // retried with the next poll
+                Err(
+                    e @ (AppError::SqlxError(_) | AppError::IoError(_) | AppError::StorageError(_)),
+                ) if reminder.attempts < MAX_REMINDER_ATTEMPTS => {
+                    warn!("Failed to deliver reminder {}: {}", reminder.id, e)
+                }
+                Err(e) => {
+                    warn!("Giving up on reminder {}: {}", reminder.id, e);
+                    sqlx::query("UPDATE reminders SET error = $2 WHERE id = $1")
+                        .bind(reminder.id)
+                        .bind(e.to_string())
+                        .execute(&self.pool)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Reminders are sent to the user by the reminder bot of the workspace,
+    /// message reminders quote the message
+    async fn deliver_reminder(&self, reminder: &Reminder) -> Result<Message, AppError> {
+        let mut content = if reminder.content.is_empty() {
+            "Reminder about a message".to_string()
+        } else {
+            format!("Reminder: {}", reminder.content)
+        };
+        // the user may have left the chat since
+        if let Some(message_id) = reminder.message_id {
+            if let Ok(message) = self
+                .find_visible_message(message_id as _, reminder.user_id as _)
+                .await
+            {
+                content.push_str("\n\n");
+                for line in message.content.lines() {
+                    content.push_str("> ");
+                    content.push_str(line);
+                    content.push('\n');
+                }
+            }
+        }
+        let bot_id = self.reminder_bot(reminder.user_id).await?;
+        let chat_id = self.direct_chat(bot_id, reminder.user_id).await?;
+        let input = CreateMessage {
+            content,
+            format: MessageFormat::Markdown,
+            files: vec![],
+            sender_name: Some(REMINDER_SENDER_NAME.to_string()),
+            client_msg_id: Some(format!("reminder-{}", reminder.id)),
+        };
+        self.create_message(input, chat_id as _, bot_id as _).await
+    }
+
+    /// The system bot sending reminders in the workspace of the user, created
+    /// on first use
+    async fn reminder_bot(&self, user_id: i64) -> Result<i64, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // serializes the creation of the bot of the workspace
+        let (workspace_id, bot_id): (i64, Option<i64>) = sqlx::query_as(
+            r#"
+            SELECT w.id, w.reminder_bot_id
+            FROM workspaces w
+            JOIN users u ON u.workspace_id = w.id
+            WHERE u.id = $1
+            FOR UPDATE OF w
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if let Some(bot_id) = bot_id {
+            return Ok(bot_id);
+        }
+
+        // random, so no user can take the name before the bot is created
+        let mut buf = [0u8; 4];
+        OsRng.fill_bytes(&mut buf);
+        let name = format!("reminders-{}-{}", workspace_id, hex::encode(buf));
+        let bot_id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (workspace_id, workspace, fullname, email, is_bot)
+            SELECT workspace_id, workspace, $2, $3, TRUE
+            FROM users
+            WHERE id = $1
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(&name)
+        .bind(format!("{}@system.local", name))
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE workspaces SET reminder_bot_id = $1 WHERE id = $2")
+            .bind(bot_id)
+            .bind(workspace_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(bot_id)
+    }
+
+    /// The direct chat of the two users, created on first use
+    async fn direct_chat(&self, user_id: i64, other_id: i64) -> Result<i64, AppError> {
+        let chat_id: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM chats
+            WHERE type = 'single' AND members @> ARRAY[$1, $2]::BIGINT[]
+            AND cardinality(members) = 2
+            ORDER BY id
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(other_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(chat_id) = chat_id {
+            return Ok(chat_id);
+        }
+
+        let chat_id = sqlx::query_scalar(
+            r#"
+            INSERT INTO chats (workspace_id, type, members)
+            SELECT workspace_id, 'single', ARRAY[$1, $2]::BIGINT[]
+            FROM users
+            WHERE id = $2
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(other_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(chat_id)
+    }
+
+    /// Poll for due reminders in the background
+    pub fn spawn_reminder_worker(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REMINDER_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = state.deliver_due_reminders(Utc::now()).await {
+                    warn!("Failed to deliver reminders: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chrono::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn deliver_due_reminders_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let now = Utc::now();
+        state
+            .create_reminder(1, 1, "stand-up", now + Duration::minutes(10))
+            .await?;
+        state
+            .create_reminder(1, 1, "lunch", now + Duration::hours(2))
+            .await?;
+
+        assert!(state.deliver_due_reminders(now).await?.is_empty());
+
+        let messages = state
+            .deliver_due_reminders(now + Duration::minutes(10))
+            .await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "Reminder: stand-up");
+        assert_eq!(messages[0].sender_name.as_deref(), Some("Reminder"));
+        // sent by the bot in a direct chat, not posted as the user in chat 1
+        assert_ne!(messages[0].sender_id, 1);
+        let chat = state
+            .get_chat_by_id(messages[0].chat_id as _)
+            .await?
+            .expect("chat should exist");
+        assert_eq!(chat.members, vec![messages[0].sender_id, 1]);
+
+        // delivered only once
+        let messages = state
+            .deliver_due_reminders(now + Duration::minutes(30))
+            .await?;
+        assert!(messages.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn message_reminder_should_be_sent_as_direct_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let now = Utc::now();
+        let messages = state.list_message(Default::default(), 1).await?.messages;
+        let input = |message_id: i64, remind_at, note: &str| CreateReminder {
+            message_id: message_id as _,
+            remind_at,
+            note: note.to_string(),
+        };
+
+        let ret = state
+            .create_message_reminder(1, &input(messages[0].id, now, ""))
+            .await;
+        assert!(matches!(ret, Err(AppError::ReminderError(_))));
+        let reminder = state
+            .create_message_reminder(1, &input(messages[0].id, now + Duration::hours(1), "reply"))
+            .await?;
+        assert_eq!(reminder.chat_id, 1);
+        let other = state
+            .create_message_reminder(1, &input(messages[1].id, now + Duration::hours(3), ""))
+            .await?;
+        assert_eq!(
+            state.list_reminders(1).await?,
+            vec![reminder, other.clone()]
+        );
+        assert!(state.list_reminders(2).await?.is_empty());
+
+        let sent = state
+            .deliver_due_reminders(now + Duration::hours(2))
+            .await?;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].format, MessageFormat::Markdown);
+        assert_eq!(
+            sent[0].content,
+            format!("Reminder: reply\n\n> {}\n", messages[0].content)
+        );
+        assert_ne!(sent[0].sender_id, 1);
+        let chat = state
+            .get_chat_by_id(sent[0].chat_id as _)
+            .await?
+            .expect("chat should exist");
+        assert_eq!(chat.members, vec![sent[0].sender_id, 1]);
+        assert_eq!(state.list_reminders(1).await?, vec![other.clone()]);
+
+        // a reminder sent but not marked delivered, e.g. before a restart, isn't sent twice
+        sqlx::query("UPDATE reminders SET delivered_at = NULL")
+            .execute(&state.pool)
+            .await?;
+        let again = state
+            .deliver_due_reminders(now + Duration::hours(4))
+            .await?;
+        assert_eq!(again.len(), 2);
+        assert!(again.iter().any(|m| m.id == sent[0].id));
+        // the same direct chat is reused
+        assert!(again.iter().all(|m| m.chat_id == sent[0].chat_id));
+        let bot_id: Option<i64> =
+            sqlx::query_scalar("SELECT reminder_bot_id FROM workspaces WHERE id = 1")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(bot_id, Some(sent[0].sender_id));
+
+        state.delete_reminder(other.id as _, 1).await?;
+        let ret = state.delete_reminder(other.id as _, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failing_reminders_should_not_be_retried_forever() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let now = Utc::now();
+        let failing = state.create_reminder(1, 1, "fail", now).await?;
+        state.create_reminder(2, 1, "stand-up", now).await?;
+        sqlx::query(
+            r#"
+            CREATE FUNCTION fail_reminder() RETURNS TRIGGER AS $$
+            BEGIN
+                IF NEW.content = 'Reminder: fail' THEN
+                    RAISE EXCEPTION 'broken';
+                END IF;
+                RETURN NEW;
+            END;
+            $$ LANGUAGE plpgsql
+            "#,
+        )
+        .execute(&state.pool)
+        .await?;
+        sqlx::query(
+            r#"
+            CREATE TRIGGER fail_reminder BEFORE INSERT ON messages
+            FOR EACH ROW EXECUTE FUNCTION fail_reminder()
+            "#,
+        )
+        .execute(&state.pool)
+        .await?;
+
+        // the failing reminder doesn't hold up the others
+        let messages = state.deliver_due_reminders(now).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "Reminder: stand-up");
+
+        for _ in 1..MAX_REMINDER_ATTEMPTS {
+            assert!(state.deliver_due_reminders(now).await?.is_empty());
+        }
+        let reminders = state.list_reminders(1).await?;
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].id, failing.id);
+        assert_eq!(reminders[0].attempts, MAX_REMINDER_ATTEMPTS);
+        assert!(reminders[0].error.is_some());
+
+        // given up on, it's no longer due
+        sqlx::query("DROP TRIGGER fail_reminder ON messages")
+            .execute(&state.pool)
+            .await?;
+        assert!(state.deliver_due_reminders(now).await?.is_empty());
+        Ok(())
+    }
+}
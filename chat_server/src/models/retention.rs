@@ -0,0 +1,359 @@
+use std::{str::FromStr, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::{info, warn};
+
+use crate::{
+    models::{
+        file::{lock_in_tx, FileLock},
+        thumbnail_key,
+    },
+    AppError, AppState, ChatFile, THUMBNAIL_SIZES,
+};
+
+const FILE_GC_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct LegalHold {
+    pub id: i64,
+    pub workspace_id: i64,
+    /// only the uploads of this user, all files of the workspace if empty
+    pub uploader_id: Option<i64>,
+    pub reason: String,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateLegalHold {
+    pub uploader_id: Option<i64>,
+    pub reason: String,
+}
+
+/// Storage used by a workspace, the same content uploaded twice is stored once
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct StorageUsage {
+    pub workspace_id: i64,
+    pub files: i64,
+    pub stored_files: i64,
+    pub stored_bytes: i64,
+    /// uploads no message references, collected after the grace period
+    pub unreferenced_files: i64,
+    pub unreferenced_bytes: i64,
+    pub quota_bytes: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileGcReport {
+    pub files: usize,
+    pub objects: usize,
+    pub bytes: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct CollectedFile {
+    url: String,
+    size: i64,
+}
+
+impl AppState {
+    pub async fn create_legal_hold(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+        input: &CreateLegalHold,
+    ) -> Result<LegalHold, AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        if input.reason.trim().is_empty() {
+            return Err(AppError::ChatFileError(
+                "legal hold reason is required".to_string(),
+            ));
+        }
+
+        let hold = sqlx::query_as(
+            r#"
+            INSERT INTO legal_holds (workspace_id, uploader_id, reason, created_by)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, workspace_id, uploader_id, reason, created_by, created_at
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .bind(input.uploader_id)
+        .bind(input.reason.trim())
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(hold)
+    }
+
+    pub async fn list_legal_holds(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<LegalHold>, AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        let holds = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, uploader_id, reason, created_by, created_at
+            FROM legal_holds
+            WHERE workspace_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(holds)
+    }
+
+    /// Release a hold, the files it kept are collected again if unreferenced
+    pub async fn delete_legal_hold(
+        &self,
+        id: u64,
+        workspace_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        let ret = sqlx::query("DELETE FROM legal_holds WHERE id = $1 AND workspace_id = $2")
+            .bind(id as i64)
+            .bind(workspace_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("legal hold {} not found", id)));
+        }
+        Ok(())
+    }
+
+    pub async fn storage_usage(
+        &self,
+        workspace_id: u64,
+        user_id: u64,
+    ) -> Result<StorageUsage, AppError> {
+        self.verify_workspace_owner(workspace_id, user_id).await?;
+        let usage = sqlx::query_as(
+            r#"
+            WITH ws_files AS (
+                SELECT f.id, f.url, f.size,
+                    NOT EXISTS (SELECT 1 FROM message_files mf WHERE mf.file_id = f.id)
+                    AND NOT EXISTS (SELECT 1 FROM chat_files cf WHERE cf.url = f.url)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM scheduled_messages sm
+                        JOIN chats c ON c.id = sm.chat_id
+                        WHERE sm.sent_at IS NULL AND c.workspace_id = f.workspace_id
+                        AND (f.id::TEXT = ANY(sm.files) OR f.url = ANY(sm.files))
+                    )
+                    AS unreferenced
+                FROM files f
+                WHERE f.workspace_id = $1
+            ),
+            objects AS (
+                SELECT DISTINCT ON (url) url, size FROM ws_files
+            )
+            SELECT $1 AS workspace_id,
+                (SELECT COUNT(*) FROM ws_files) AS files,
+                (SELECT COUNT(*) FROM objects) AS stored_files,
+                (SELECT COALESCE(SUM(size), 0)::BIGINT FROM objects) AS stored_bytes,
+                (SELECT COUNT(*) FROM ws_files WHERE unreferenced) AS unreferenced_files,
+                (SELECT COALESCE(SUM(size), 0)::BIGINT FROM ws_files WHERE unreferenced)
+                    AS unreferenced_bytes,
+                (SELECT quota_bytes FROM workspace_file_settings WHERE workspace_id = $1)
+                    AS quota_bytes
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    /// Delete uploads no message references once they are older than the grace
+    /// period and the retention of their workspace, unless they are on hold.
+    /// The content is removed from the storage when no upload shares it anymore.
+    pub async fn collect_unreferenced_files(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<FileGcReport, AppError> {
+        let grace = Duration::seconds(self.config.files.gc_grace_secs as i64);
+        let collected: Vec<CollectedFile> = sqlx::query_as(
+            r#"
+            DELETE FROM files
+            WHERE id IN (
+                SELECT f.id FROM files f
+                LEFT JOIN workspace_file_settings s ON s.workspace_id = f.workspace_id
+                WHERE f.created_at <= $1
+                AND f.created_at <= $2 - MAKE_INTERVAL(days => COALESCE(s.retention_days, 0))
+                AND NOT EXISTS (SELECT 1 FROM message_files mf WHERE mf.file_id = f.id)
+                AND NOT EXISTS (SELECT 1 FROM chat_files cf WHERE cf.url = f.url)
+                -- attached to a message that is still to be sent
+                AND NOT EXISTS (
+                    SELECT 1 FROM scheduled_messages sm
+                    JOIN chats c ON c.id = sm.chat_id
+                    WHERE sm.sent_at IS NULL AND c.workspace_id = f.workspace_id
+                    AND (f.id::TEXT = ANY(sm.files) OR f.url = ANY(sm.files))
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM legal_holds h
+                    WHERE h.workspace_id = f.workspace_id
+                    AND (h.uploader_id IS NULL OR h.uploader_id = f.uploader_id)
+                )
+                ORDER BY f.id
+                LIMIT $3
+                FOR UPDATE OF f SKIP LOCKED
+            )
+            RETURNING url, size
+            "#,
+        )
+        .bind(now - grace)
+        .bind(now)
+        .bind(FILE_GC_BATCH_SIZE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut report = FileGcReport {
+            files: collected.len(),
+            ..Default::default()
+        };
+        let mut urls: Vec<&CollectedFile> = collected.iter().collect();
+        urls.sort_by(|a, b| a.url.cmp(&b.url));
+        urls.dedup_by(|a, b| a.url == b.url);
+        for file in urls {
+            // uploads of the same content wait until the object is gone
+            let mut tx = self.pool.begin().await?;
+            lock_in_tx(&mut tx, FileLock::Object, &file.url).await?;
+            let shared: bool = sqlx::query_scalar(
+                r#"
+                SELECT EXISTS (SELECT 1 FROM files WHERE url = $1)
+                OR EXISTS (SELECT 1 FROM chat_files WHERE url = $1)
+                OR EXISTS (
+                    SELECT 1 FROM scheduled_messages WHERE sent_at IS NULL AND $1 = ANY(files)
+                )
+                "#,
+            )
+            .bind(&file.url)
+            .fetch_one(&mut *tx)
+            .await?;
+            if shared {
+                continue;
+            }
+            let key = ChatFile::from_str(&file.url)?.hash_to_path();
+            for &size in THUMBNAIL_SIZES {
+                self.storage.delete(&thumbnail_key(&key, size)).await?;
+            }
+            self.storage.delete(&key).await?;
+            tx.commit().await?;
+            report.objects += 1;
+            report.bytes += file.size;
+        }
+
+        Ok(report)
+    }
+
+    /// Collect unreferenced files in the background
+    pub fn spawn_file_gc(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let secs = state.config.files.gc_interval_secs;
+            let mut interval = tokio::time::interval(StdDuration::from_secs(secs));
+            loop {
+                interval.tick().await;
+                match state.collect_unreferenced_files(Utc::now()).await {
+                    Ok(report) if report.files > 0 => info!(
+                        "Collected {} unreferenced files, freed {} bytes",
+                        report.files, report.bytes
+                    ),
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to collect unreferenced files: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chat_core::MessageFormat;
+
+    use super::*;
+    use crate::{CreateMessage, FileRef, WorkspaceFileSettings};
+
+    #[tokio::test]
+    async fn collect_unreferenced_files_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let orphan = state.create_file(1, 1, "orphan.txt", b"orphan").await?;
+        let shared = state.create_file(1, 2, "shared.txt", b"shared").await?;
+        let copy = state.create_file(1, 1, "copy.txt", b"shared").await?;
+        let sent = state.create_file(1, 1, "sent.txt", b"sent").await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![FileRef::Id(sent.id)],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        state.create_message(input, 1, 1).await?;
+        let usage = state.storage_usage(1, 1).await?;
+        assert_eq!((usage.files, usage.stored_files), (4, 3));
+        assert_eq!(usage.unreferenced_files, 3);
+
+        // within the grace period
+        let now = Utc::now();
+        let report = state.collect_unreferenced_files(now).await?;
+        assert_eq!(report, FileGcReport::default());
+
+        // held files and the retention are respected
+        let hold = CreateLegalHold {
+            uploader_id: Some(2),
+            reason: "litigation".to_string(),
+        };
+        let hold = state.create_legal_hold(1, 1, &hold).await?;
+        let settings = WorkspaceFileSettings {
+            retention_days: Some(30),
+            ..Default::default()
+        };
+        state.update_file_settings(1, 1, &settings).await?;
+        let later = now + Duration::days(2);
+        let report = state.collect_unreferenced_files(later).await?;
+        assert_eq!(report.files, 0);
+        state
+            .update_file_settings(1, 1, &WorkspaceFileSettings::default())
+            .await?;
+
+        let report = state.collect_unreferenced_files(later).await?;
+        assert_eq!((report.files, report.objects), (2, 1));
+        assert_eq!(report.bytes, orphan.size);
+        let key = |url: &str| ChatFile::from_str(url).map(|f| f.hash_to_path());
+        assert!(!state.storage.exists(&key(&orphan.url)?).await?);
+        assert!(state.storage.exists(&key(&copy.url)?).await?);
+        assert!(state.storage.exists(&key(&sent.url)?).await?);
+
+        state.delete_legal_hold(hold.id as _, 1, 1).await?;
+        let report = state.collect_unreferenced_files(later).await?;
+        assert_eq!((report.files, report.objects), (1, 1));
+        assert!(!state.storage.exists(&key(&shared.url)?).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_settings_should_only_be_changed_by_the_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let settings = WorkspaceFileSettings {
+            retention_days: Some(7),
+            ..Default::default()
+        };
+        let ret = state.update_file_settings(1, 2, &settings).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        assert_eq!(state.update_file_settings(1, 1, &settings).await?, settings);
+        assert_eq!(state.get_file_settings(1).await?, settings);
+        assert!(state.storage_usage(1, 2).await.is_err());
+        Ok(())
+    }
+}
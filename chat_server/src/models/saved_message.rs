@@ -0,0 +1,184 @@
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{AppError, AppState};
+
+const DEFAULT_SAVED_MESSAGES: u64 = 20;
+const MAX_SAVED_MESSAGES: u64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListSavedMessages {
+    pub last_id: Option<u64>,
+    #[serde(default = "default_saved_messages")]
+    pub limit: u64,
+}
+
+/// A message the user saved for later, `id` is the cursor of the list
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SavedMessage {
+    pub id: i64,
+    pub saved_at: DateTime<Utc>,
+    pub message: Message,
+}
+
+#[derive(Debug, FromRow)]
+struct SavedRow {
+    saved_id: i64,
+    saved_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    message: Message,
+}
+
+impl AppState {
+    /// Saving a saved message again keeps when it was first saved
+    pub async fn save_message(
+        &self,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<SavedMessage, AppError> {
+        let message = self.find_visible_message(message_id, user_id).await?;
+        let (id, saved_at): (i64, DateTime<Utc>) = sqlx::query_as(
+            r#"
+            INSERT INTO saved_messages (user_id, message_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, message_id) DO UPDATE SET user_id = EXCLUDED.user_id
+            RETURNING id, created_at
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(message_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(SavedMessage {
+            id,
+            saved_at,
+            message,
+        })
+    }
+
+    pub async fn unsave_message(&self, message_id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query("DELETE FROM saved_messages WHERE user_id = $1 AND message_id = $2")
+            .bind(user_id as i64)
+            .bind(message_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "message {} is not saved",
+                message_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Saved messages across all chats, newest first. Messages of chats the
+    /// user left are hidden
+    pub async fn list_saved_messages(
+        &self,
+        input: ListSavedMessages,
+        user_id: u64,
+    ) -> Result<Vec<SavedMessage>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let rows: Vec<SavedRow> = sqlx::query_as(
+            r#"
+            SELECT s.id AS saved_id, s.created_at AS saved_at, m.id, m.chat_id, m.sender_id,
+                m.sender_name, m.content, m.format, m.html, m.files, m.client_msg_id,
+                m.created_at
+            FROM saved_messages s
+            JOIN messages m ON m.id = s.message_id
+            JOIN chats c ON c.id = m.chat_id
+            WHERE s.user_id = $1 AND s.id < $2 AND $1 = ANY(c.members)
+            ORDER BY s.id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(last_id as i64)
+        .bind(input.limit.clamp(1, MAX_SAVED_MESSAGES) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut messages: Vec<Message> = rows.iter().map(|r| r.message.clone()).collect();
+        self.load_attachments(&mut messages).await?;
+        let saved = rows
+            .into_iter()
+            .zip(messages)
+            .map(|(row, message)| SavedMessage {
+                id: row.saved_id,
+                saved_at: row.saved_at,
+                message,
+            })
+            .collect();
+
+        Ok(saved)
+    }
+}
+
+fn default_saved_messages() -> u64 {
+    DEFAULT_SAVED_MESSAGES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateMessage;
+    use anyhow::Result;
+    use chat_core::MessageFormat;
+
+    #[tokio::test]
+    async fn saved_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let public = state.list_message(Default::default(), 1).await?.messages;
+        let input = CreateMessage {
+            content: "just between us".to_string(),
+            format: MessageFormat::Plain,
+            files: vec![],
+            sender_name: None,
+            client_msg_id: None,
+        };
+        let direct = state.create_message(input, 3, 2).await?;
+        let first = state.save_message(public[2].id as _, 1).await?;
+        state.save_message(direct.id as _, 1).await?;
+        // saving twice keeps the first save
+        let again = state.save_message(public[2].id as _, 1).await?;
+        assert_eq!(again.id, first.id);
+        assert_eq!(again.saved_at, first.saved_at);
+
+        let input = ListSavedMessages {
+            last_id: None,
+            limit: 10,
+        };
+        let saved = state.list_saved_messages(input, 1).await?;
+        let ids: Vec<i64> = saved.iter().map(|s| s.message.id).collect();
+        assert_eq!(ids, vec![direct.id, public[2].id]);
+
+        let input = ListSavedMessages {
+            last_id: Some(saved[0].id as _),
+            limit: 10,
+        };
+        assert_eq!(state.list_saved_messages(input, 1).await?, vec![first]);
+
+        // without a limit a page of the default size is listed, 0 is clamped to 1
+        let input: ListSavedMessages = serde_json::from_str("{}")?;
+        assert_eq!(input.limit, DEFAULT_SAVED_MESSAGES);
+        assert_eq!(state.list_saved_messages(input, 1).await?.len(), 2);
+        let input = ListSavedMessages {
+            last_id: None,
+            limit: 0,
+        };
+        let saved = state.list_saved_messages(input, 1).await?;
+        assert_eq!(saved.len(), 1);
+
+        state.unsave_message(public[2].id as _, 1).await?;
+        let ret = state.unsave_message(public[2].id as _, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // user 3 isn't in the direct chat of user 1 and 2
+        let ret = state.save_message(direct.id as _, 3).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
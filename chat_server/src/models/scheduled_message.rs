@@ -0,0 +1,575 @@
+use std::time::Duration as StdDuration;
+
+use chat_core::{Message, MessageFormat};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::warn;
+
+use crate::{AppError, AppState, CreateMessage, FileRef};
+
+const SCHEDULED_POLL_INTERVAL: StdDuration = StdDuration::from_secs(10);
+const SCHEDULED_BATCH_SIZE: i64 = 100;
+const MAX_SCHEDULED_ATTEMPTS: i32 = 5;
+/// how far in the past a client clock may put `send_at`, it's sent right away
+const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateScheduledMessage {
+    pub content: String,
+    #[serde(default)]
+    pub format: MessageFormat,
+    #[serde(default)]
+    pub files: Vec<FileRef>,
+    pub send_at: DateTime<Utc>,
+}
+
+/// Editing a message that failed to send schedules it again
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateScheduledMessage {
+    pub content: Option<String>,
+    pub format: Option<MessageFormat>,
+    pub files: Option<Vec<FileRef>>,
+    pub send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub format: MessageFormat,
+    pub files: Vec<FileRef>,
+    pub send_at: DateTime<Utc>,
+    pub error: Option<String>,
+    pub message_id: Option<i64>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, FromRow)]
+struct ScheduledRow {
+    id: i64,
+    chat_id: i64,
+    sender_id: i64,
+    content: String,
+    format: MessageFormat,
+    files: Vec<String>,
+    send_at: DateTime<Utc>,
+    error: Option<String>,
+    message_id: Option<i64>,
+    sent_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    // only read by the worker sending them
+    #[sqlx(default)]
+    attempts: i32,
+}
+
+impl AppState {
+    pub async fn create_scheduled_message(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        input: CreateScheduledMessage,
+    ) -> Result<ScheduledMessage, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::ScheduledMessageError(
+                "Content cannot be empty".to_string(),
+            ));
+        }
+        self.verify_send_at(input.send_at).await?;
+        self.verify_scheduled_files(&input.files, chat_id, user_id)
+            .await?;
+
+        let row: ScheduledRow = sqlx::query_as(
+            r#"
+            INSERT INTO scheduled_messages (chat_id, sender_id, content, format, files, send_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, chat_id, sender_id, content, format, files, send_at, error, message_id,
+                sent_at, created_at, updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.content)
+        .bind(input.format)
+        .bind(encode_files(&input.files))
+        .bind(input.send_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    /// Messages of the user waiting to be sent, including the ones that failed
+    pub async fn list_scheduled_messages(
+        &self,
+        user_id: u64,
+    ) -> Result<Vec<ScheduledMessage>, AppError> {
+        let rows: Vec<ScheduledRow> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, format, files, send_at, error, message_id,
+                sent_at, created_at, updated_at
+            FROM scheduled_messages
+            WHERE sender_id = $1 AND sent_at IS NULL
+            ORDER BY send_at, id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    /// Only messages that aren't sent yet can be edited
+    pub async fn update_scheduled_message(
+        &self,
+        id: u64,
+        user_id: u64,
+        input: UpdateScheduledMessage,
+    ) -> Result<ScheduledMessage, AppError> {
+        if input.content.as_deref() == Some("") {
+            return Err(AppError::ScheduledMessageError(
+                "Content cannot be empty".to_string(),
+            ));
+        }
+        if let Some(send_at) = input.send_at {
+            self.verify_send_at(send_at).await?;
+        }
+        if let Some(files) = &input.files {
+            let chat_id: Option<i64> = sqlx::query_scalar(
+                "SELECT chat_id FROM scheduled_messages WHERE id = $1 AND sender_id = $2 AND sent_at IS NULL",
+            )
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+            let Some(chat_id) = chat_id else {
+                return Err(AppError::NotFound(format!(
+                    "scheduled message {} not found or already sent",
+                    id
+                )));
+            };
+            self.verify_scheduled_files(files, chat_id as _, user_id)
+                .await?;
+        }
+
+        let row: Option<ScheduledRow> = sqlx::query_as(
+            r#"
+            UPDATE scheduled_messages
+            SET content = COALESCE($3, content),
+                format = COALESCE($4, format),
+                files = COALESCE($5, files),
+                send_at = COALESCE($6, send_at),
+                error = NULL,
+                attempts = 0,
+                updated_at = now()
+            WHERE id = $1 AND sender_id = $2 AND sent_at IS NULL
+            RETURNING id, chat_id, sender_id, content, format, files, send_at, error, message_id,
+                sent_at, created_at, updated_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .bind(&input.content)
+        .bind(input.format)
+        .bind(input.files.as_deref().map(encode_files))
+        .bind(input.send_at)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Into::into).ok_or_else(|| {
+            AppError::NotFound(format!(
+                "scheduled message {} not found or already sent",
+                id
+            ))
+        })
+    }
+
+    pub async fn cancel_scheduled_message(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            "DELETE FROM scheduled_messages WHERE id = $1 AND sender_id = $2 AND sent_at IS NULL",
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "scheduled message {} not found or already sent",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Send the messages that are due by the database clock, so servers with
+    /// skewed clocks agree. Messages are claimed without holding a lock while
+    /// they are sent, their client message id keeps a retry, e.g. by another
+    /// server or after a restart, from sending them twice
+    pub async fn send_scheduled_messages(&self) -> Result<Vec<Message>, AppError> {
+        let rows: Vec<ScheduledRow> = sqlx::query_as(
+            r#"
+            UPDATE scheduled_messages
+            SET attempts = attempts + 1
+            WHERE id IN (
+                SELECT id
+                FROM scheduled_messages
+                WHERE sent_at IS NULL AND error IS NULL AND send_at <= now()
+                ORDER BY send_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, chat_id, sender_id, content, format, files, send_at, error, message_id,
+                sent_at, created_at, updated_at, attempts
+            "#,
+        )
+        .bind(SCHEDULED_BATCH_SIZE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut messages = Vec::with_capacity(rows.len());
+        for row in rows {
+            let attempts = row.attempts;
+            let scheduled = ScheduledMessage::from(row);
+            match self.send_scheduled_message(&scheduled).await {
+                Ok(message) => {
+                    sqlx::query(
+                        "UPDATE scheduled_messages SET sent_at = now(), message_id = $2 WHERE id = $1",
+                    )
+                    .bind(scheduled.id)
+                    .bind(message.id)
+                    .execute(&self.pool)
+                    .await?;
+                    messages.push(message);
+                }
+                // retried with the next poll
+                Err(
+                    e @ (AppError::SqlxError(_) | AppError::IoError(_) | AppError::StorageError(_)),
+                ) if attempts < MAX_SCHEDULED_ATTEMPTS => {
+                    warn!("Failed to send scheduled message {}: {}", scheduled.id, e)
+                }
+                Err(e) => {
+                    warn!("Giving up on scheduled message {}: {}", scheduled.id, e);
+                    sqlx::query("UPDATE scheduled_messages SET error = $2 WHERE id = $1")
+                        .bind(scheduled.id)
+                        .bind(e.to_string())
+                        .execute(&self.pool)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+
+    async fn send_scheduled_message(
+        &self,
+        scheduled: &ScheduledMessage,
+    ) -> Result<Message, AppError> {
+        // the sender may have left the chat since
+        if !self
+            .is_chat_member(scheduled.chat_id as _, scheduled.sender_id as _)
+            .await?
+        {
+            return Err(AppError::ScheduledMessageError(format!(
+                "User {} is not a member of chat",
+                scheduled.sender_id
+            )));
+        }
+        let input = CreateMessage {
+            content: scheduled.content.clone(),
+            format: scheduled.format,
+            files: scheduled.files.clone(),
+            sender_name: None,
+            client_msg_id: Some(format!("scheduled-{}", scheduled.id)),
+        };
+        self.create_message(input, scheduled.chat_id as _, scheduled.sender_id as _)
+            .await
+    }
+
+    /// Poll for due scheduled messages in the background
+    pub fn spawn_scheduled_message_worker(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULED_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = state.send_scheduled_messages().await {
+                    warn!("Failed to send scheduled messages: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Checked as they would be when sent, they are kept from the file GC
+    /// until then
+    async fn verify_scheduled_files(
+        &self,
+        files: &[FileRef],
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        match self.resolve_files(files, chat_id, user_id).await {
+            Ok(_) => Ok(()),
+            Err(AppError::CreateMessageError(e)) => Err(AppError::ScheduledMessageError(e)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn verify_send_at(&self, send_at: DateTime<Utc>) -> Result<(), AppError> {
+        let now: DateTime<Utc> = sqlx::query_scalar("SELECT now()")
+            .fetch_one(&self.pool)
+            .await?;
+        if send_at < now - Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+            return Err(AppError::ScheduledMessageError(
+                "send_at must be in the future".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl From<ScheduledRow> for ScheduledMessage {
+    fn from(row: ScheduledRow) -> Self {
+        Self {
+            id: row.id,
+            chat_id: row.chat_id,
+            sender_id: row.sender_id,
+            content: row.content,
+            format: row.format,
+            files: row.files.iter().map(|f| decode_file(f)).collect(),
+            send_at: row.send_at,
+            error: row.error,
+            message_id: row.message_id,
+            sent_at: row.sent_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// File ids are stored as numbers, urls always start with a slash
+fn encode_files(files: &[FileRef]) -> Vec<String> {
+    files
+        .iter()
+        .map(|f| match f {
+            FileRef::Id(id) => id.to_string(),
+            FileRef::Url(url) => url.clone(),
+        })
+        .collect()
+}
+
+fn decode_file(s: &str) -> FileRef {
+    match s.parse() {
+        Ok(id) => FileRef::Id(id),
+        Err(_) => FileRef::Url(s.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    async fn make_due(state: &AppState) -> Result<()> {
+        sqlx::query("UPDATE scheduled_messages SET send_at = now() - INTERVAL '1 second'")
+            .execute(&state.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn input(content: &str, files: Vec<FileRef>) -> CreateScheduledMessage {
+        CreateScheduledMessage {
+            content: content.to_string(),
+            format: MessageFormat::Plain,
+            files,
+            send_at: Utc::now() + Duration::hours(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_files_should_not_be_collected() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = state.create_file(1, 1, "notes.txt", b"notes").await?;
+        let other = state.create_file(1, 1, "other.txt", b"other").await?;
+        let files = vec![FileRef::Id(file.id), FileRef::Url(other.url.clone())];
+        state
+            .create_scheduled_message(1, 1, input("later", files))
+            .await?;
+
+        let report = state
+            .collect_unreferenced_files(Utc::now() + Duration::days(2))
+            .await?;
+        assert_eq!(report.files, 0);
+
+        // files of another workspace can't be scheduled, nor kept from the GC
+        let foreign = state.create_file(2, 1, "foreign.txt", b"foreign").await?;
+        let ret = state
+            .create_scheduled_message(1, 1, input("later", vec![FileRef::Id(foreign.id)]))
+            .await;
+        assert!(matches!(ret, Err(AppError::ScheduledMessageError(_))));
+        sqlx::query(
+            r#"
+            INSERT INTO scheduled_messages (chat_id, sender_id, content, files, send_at)
+            VALUES (1, 1, 'shield', $1, now() + INTERVAL '1 day')
+            "#,
+        )
+        .bind(vec![foreign.id.to_string()])
+        .execute(&state.pool)
+        .await?;
+        let report = state
+            .collect_unreferenced_files(Utc::now() + Duration::days(2))
+            .await?;
+        assert_eq!(report.files, 1);
+
+        sqlx::query("DELETE FROM scheduled_messages WHERE content = 'shield'")
+            .execute(&state.pool)
+            .await?;
+        make_due(&state).await?;
+        let sent = state.send_scheduled_messages().await?;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].files, vec![file.url, other.url]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_be_sent_once() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = state.create_file(1, 1, "notes.txt", b"notes").await?;
+        let scheduled = state
+            .create_scheduled_message(1, 1, input("later", vec![FileRef::Id(file.id)]))
+            .await?;
+        assert_eq!(scheduled.files, vec![FileRef::Id(file.id)]);
+        assert!(state.send_scheduled_messages().await?.is_empty());
+
+        let update = UpdateScheduledMessage {
+            content: Some("**later**".to_string()),
+            format: Some(MessageFormat::Markdown),
+            ..Default::default()
+        };
+        let updated = state
+            .update_scheduled_message(scheduled.id as _, 1, update)
+            .await?;
+        assert_eq!(state.list_scheduled_messages(1).await?, vec![updated]);
+        assert!(state.list_scheduled_messages(2).await?.is_empty());
+
+        make_due(&state).await?;
+        let sent = state.send_scheduled_messages().await?;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].content, "**later**");
+        assert_eq!(sent[0].html, "<p><strong>later</strong></p>\n");
+        assert_eq!(sent[0].attachments[0].id, file.id);
+        assert!(state.list_scheduled_messages(1).await?.is_empty());
+        let ret = state.cancel_scheduled_message(scheduled.id as _, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // sent but not marked sent, e.g. before a restart
+        sqlx::query("UPDATE scheduled_messages SET sent_at = NULL")
+            .execute(&state.pool)
+            .await?;
+        let again = state.send_scheduled_messages().await?;
+        assert_eq!(again.len(), 1);
+        assert_eq!(again[0].id, sent[0].id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_record_failures() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut past = input("too late", vec![]);
+        past.send_at = Utc::now() - Duration::hours(1);
+        let ret = state.create_scheduled_message(1, 1, past).await;
+        assert!(matches!(ret, Err(AppError::ScheduledMessageError(_))));
+
+        let cancelled = state
+            .create_scheduled_message(1, 1, input("never", vec![]))
+            .await?;
+        state.cancel_scheduled_message(cancelled.id as _, 1).await?;
+
+        let ret = state
+            .create_scheduled_message(1, 1, input("missing", vec![FileRef::Id(9999)]))
+            .await;
+        assert!(matches!(ret, Err(AppError::ScheduledMessageError(_))));
+
+        // the sender left the chat before it was sent
+        let broken = state
+            .create_scheduled_message(1, 1, input("broken", vec![]))
+            .await?;
+        sqlx::query("UPDATE chats SET members = array_remove(members, 1) WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        make_due(&state).await?;
+        assert!(state.send_scheduled_messages().await?.is_empty());
+        let failed = state.list_scheduled_messages(1).await?;
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].error.is_some());
+        // not retried until edited
+        assert!(state.send_scheduled_messages().await?.is_empty());
+
+        sqlx::query("UPDATE chats SET members = array_append(members, 1) WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let update = UpdateScheduledMessage {
+            content: Some("fixed".to_string()),
+            ..Default::default()
+        };
+        let fixed = state
+            .update_scheduled_message(broken.id as _, 1, update)
+            .await?;
+        assert_eq!(fixed.error, None);
+        assert_eq!(state.send_scheduled_messages().await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_give_up_after_database_errors() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query(
+            r#"
+            CREATE FUNCTION reject_boom() RETURNS TRIGGER AS $$
+            BEGIN
+                RAISE EXCEPTION 'boom';
+            END;
+            $$ LANGUAGE plpgsql
+            "#,
+        )
+        .execute(&state.pool)
+        .await?;
+        sqlx::query(
+            r#"
+            CREATE TRIGGER reject_boom BEFORE INSERT ON messages
+            FOR EACH ROW WHEN (NEW.content = 'boom') EXECUTE FUNCTION reject_boom()
+            "#,
+        )
+        .execute(&state.pool)
+        .await?;
+        state
+            .create_scheduled_message(1, 1, input("boom", vec![]))
+            .await?;
+        make_due(&state).await?;
+
+        for _ in 1..MAX_SCHEDULED_ATTEMPTS {
+            assert!(state.send_scheduled_messages().await?.is_empty());
+            assert_eq!(state.list_scheduled_messages(1).await?[0].error, None);
+        }
+        assert!(state.send_scheduled_messages().await?.is_empty());
+        let failed = state.list_scheduled_messages(1).await?;
+        assert!(failed[0]
+            .error
+            .as_deref()
+            .is_some_and(|e| e.contains("boom")));
+
+        // the failed message doesn't hold up the others
+        state
+            .create_scheduled_message(1, 1, input("later", vec![]))
+            .await?;
+        make_due(&state).await?;
+        assert_eq!(state.send_scheduled_messages().await?.len(), 1);
+        Ok(())
+    }
+}
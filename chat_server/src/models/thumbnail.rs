@@ -0,0 +1,208 @@
+use std::{io::Cursor, path::Path};
+
+use chat_core::Attachment;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use tracing::warn;
+
+use crate::{AppError, AppState, TempFile};
+
+/// Longest side of the generated thumbnails in pixels, served with `?size=`
+pub const THUMBNAIL_SIZES: &[u32] = &[64, 360, 720];
+
+// the blurhash is computed on a tiny version of the image, it is blurred anyway
+const BLURHASH_SOURCE_SIZE: u32 = 32;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Thumbnails are stored next to the original, e.g. `1/c13/504/f6f2e1.360.jpeg`
+pub(crate) fn thumbnail_key(key: &str, size: u32) -> String {
+    let name_start = key.rfind('/').map_or(0, |i| i + 1);
+    match key[name_start..].rfind('.') {
+        Some(i) => {
+            let (stem, ext) = key.split_at(name_start + i);
+            format!("{}.{}{}", stem, size, ext)
+        }
+        None => format!("{}.{}", key, size),
+    }
+}
+
+impl AppState {
+    /// Generate the previews of an uploaded image in the background
+    pub(crate) fn spawn_previews(&self, attachment: &Attachment, key: String) {
+        let state = self.clone();
+        let id = attachment.id;
+        tokio::spawn(async move {
+            if let Err(e) = state.create_previews(id, key).await {
+                warn!("Failed to create previews for file {}: {}", id, e);
+            }
+        });
+    }
+
+    /// Store the thumbnails and the blurhash placeholder of the file
+    pub(crate) async fn create_previews(&self, id: i64, key: String) -> Result<String, AppError> {
+        let base_dir = &self.config.server.base_dir;
+        // remote files are decoded from a local copy
+        let (path, _temp) = match self.storage.local_path(&key).await? {
+            Some(path) => (path, None),
+            None => {
+                let temp = TempFile::new(base_dir, u64::MAX).await?;
+                self.storage.download(&key, temp.path()).await?;
+                (temp.path().to_path_buf(), Some(temp))
+            }
+        };
+        let Previews {
+            blurhash,
+            thumbnails,
+        } = tokio::task::spawn_blocking(move || generate_previews(&path))
+            .await
+            .map_err(|e| AppError::ChatFileError(e.to_string()))??;
+
+        for (size, data) in thumbnails {
+            let mut temp = TempFile::new(base_dir, u64::MAX).await?;
+            temp.write(&data).await?;
+            temp.flush().await?;
+            self.storage
+                .put_file(&thumbnail_key(&key, size), temp.path())
+                .await?;
+        }
+        sqlx::query("UPDATE files SET blurhash = $1 WHERE id = $2")
+            .bind(&blurhash)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(blurhash)
+    }
+}
+
+struct Previews {
+    blurhash: String,
+    // encoded like the original, by size
+    thumbnails: Vec<(u32, Vec<u8>)>,
+}
+
+fn generate_previews(path: &Path) -> Result<Previews, AppError> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let Some(format) = reader.format() else {
+        return Err(AppError::ChatFileError(format!(
+            "unknown image format: {}",
+            path.display()
+        )));
+    };
+    let mut decoder = reader.into_decoder().map_err(image_error)?;
+    let orientation = decoder.orientation().map_err(image_error)?;
+    let mut img = DynamicImage::from_decoder(decoder).map_err(image_error)?;
+    // thumbnails have no metadata, so they are rotated upright
+    img.apply_orientation(orientation);
+
+    let mut thumbnails = vec![];
+    for &size in THUMBNAIL_SIZES {
+        // smaller images are served as they are
+        if size >= img.width().max(img.height()) {
+            break;
+        }
+        let thumbnail = img.thumbnail(size, size);
+        // jpeg has no alpha channel
+        let thumbnail = match format {
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+            _ => thumbnail,
+        };
+        let mut data = Cursor::new(vec![]);
+        thumbnail.write_to(&mut data, format).map_err(image_error)?;
+        thumbnails.push((size, data.into_inner()));
+    }
+
+    let small = img
+        .thumbnail(BLURHASH_SOURCE_SIZE, BLURHASH_SOURCE_SIZE)
+        .to_rgba8();
+    let (x, y) = BLURHASH_COMPONENTS;
+    let blurhash = blurhash::encode(x, y, small.width(), small.height(), small.as_raw())
+        .map_err(|e| AppError::ChatFileError(e.to_string()))?;
+    Ok(Previews {
+        blurhash,
+        thumbnails,
+    })
+}
+
+fn image_error(e: image::ImageError) -> AppError {
+    AppError::ChatFileError(format!("invalid image: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::exif::orientation_segment;
+
+    use anyhow::Result;
+
+    #[test]
+    fn thumbnail_key_should_work() {
+        assert_eq!(
+            thumbnail_key("1/c13/504/f6f2e1.jpeg", 360),
+            "1/c13/504/f6f2e1.360.jpeg"
+        );
+        assert_eq!(
+            thumbnail_key("1/c.13/504/f6f2e1", 64),
+            "1/c.13/504/f6f2e1.64"
+        );
+    }
+
+    #[tokio::test]
+    async fn create_previews_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let data = include_bytes!("../../assets/2.jpeg");
+        let file = state.create_file(1, 1, "2.jpeg", data).await?;
+        // a copy, so the previews created on upload don't race with this test
+        let key = format!("previews-{}/2.jpeg", file.id);
+        let mut temp = state.temp_file().await?;
+        temp.write(data).await?;
+        temp.flush().await?;
+        state.storage.put_file(&key, temp.path()).await?;
+
+        let blurhash = state.create_previews(file.id, key.clone()).await?;
+        assert!(!blurhash.is_empty());
+        let longest = file.width.max(file.height).unwrap_or_default() as u32;
+        for &size in THUMBNAIL_SIZES {
+            let key = thumbnail_key(&key, size);
+            if size < longest {
+                let body = state.storage.get(&key, None).await?;
+                let data = axum::body::to_bytes(body, usize::MAX).await?;
+                let thumbnail = image::load_from_memory(&data)?;
+                assert_eq!(thumbnail.width().max(thumbnail.height()), size);
+            } else {
+                assert!(!state.storage.exists(&key).await?);
+            }
+        }
+
+        let ret = state
+            .create_previews(file.id, format!("previews-{}/2.txt", file.id))
+            .await;
+        assert!(ret.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn previews_should_follow_the_orientation() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut data = Cursor::new(vec![]);
+        DynamicImage::new_rgb8(200, 100).write_to(&mut data, ImageFormat::Jpeg)?;
+        let data = data.into_inner();
+        // rotated by 90 degrees, i.e. a portrait photo
+        let mut rotated = data[..2].to_vec();
+        rotated.extend_from_slice(&orientation_segment(6));
+        rotated.extend_from_slice(&data[2..]);
+
+        let file = state.create_file(1, 1, "portrait.jpeg", &rotated).await?;
+        assert_eq!((file.width, file.height), (Some(100), Some(200)));
+
+        let mut temp = state.temp_file().await?;
+        temp.write(&rotated).await?;
+        temp.flush().await?;
+        let previews = generate_previews(temp.path())?;
+        let (size, data) = &previews.thumbnails[0];
+        assert_eq!(*size, 64);
+        let thumbnail = image::load_from_memory(data)?;
+        assert_eq!((thumbnail.width(), thumbnail.height()), (32, 64));
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{AppError, AppState};
+
+use chat_core::User;
+
+const TOTP_ISSUER: &str = "chat_server";
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+// accept codes one step before / after the current one to tolerate clock drift
+const TOTP_SKEW: u64 = 1;
+const TOTP_SECRET_LEN: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, FromRow)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    // required by the workspace the user belongs to
+    pub required: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct TotpState {
+    totp_secret: Option<String>,
+    totp_enabled: bool,
+}
+
+impl AppState {
+    pub async fn two_factor_status(&self, user_id: u64) -> Result<TwoFactorStatus, AppError> {
+        let status = sqlx::query_as(
+            r#"
+            SELECT u.totp_enabled AS enabled, COALESCE(w.require_2fa, FALSE) AS required
+            FROM users u
+            LEFT JOIN workspaces w ON w.id = u.workspace_id
+            WHERE u.id = $1
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(status.unwrap_or_default())
+    }
+
+    /// Generate a new pending totp secret, it only takes effect after `confirm_totp`
+    pub async fn enroll_totp(&self, user: &User) -> Result<TotpEnrollment, AppError> {
+        let state = self.get_totp_state(user.id as _).await?;
+        if state.totp_enabled {
+            return Err(AppError::TwoFactorError(
+                "two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        let mut secret = vec![0u8; TOTP_SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+        let totp = build_totp(secret, &user.email)?;
+        let encoded = totp.get_secret_base32();
+
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET totp_secret = $2, totp_enabled = FALSE, totp_last_step = NULL
+            WHERE id = $1
+            "#,
+        )
+        .bind(user.id)
+        .bind(&encoded)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(TotpEnrollment {
+            secret: encoded,
+            uri: totp.get_url(),
+        })
+    }
+
+    /// Activate a pending totp secret and issue a fresh set of recovery codes
+    pub async fn confirm_totp(
+        &self,
+        user: &User,
+        code: &str,
+        now: u64,
+    ) -> Result<RecoveryCodes, AppError> {
+        let state = self.get_totp_state(user.id as _).await?;
+        if state.totp_enabled {
+            return Err(AppError::TwoFactorError(
+                "two-factor authentication is already enabled".to_string(),
+            ));
+        }
+        let Some(secret) = state.totp_secret else {
+            return Err(AppError::TwoFactorError(
+                "two-factor authentication is not enrolled".to_string(),
+            ));
+        };
+
+        let totp = build_totp(decode_secret(&secret)?, &user.email)?;
+        let Some(step) = match_totp_step(&totp, code, now) else {
+            return Err(AppError::InvalidTwoFactorCode);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET totp_enabled = TRUE, totp_last_step = $2
+            WHERE id = $1
+            "#,
+        )
+        .bind(user.id)
+        .bind(step as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| gen_recovery_code())
+            .collect();
+        let hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|c| hash_recovery_code(c))
+            .collect();
+        sqlx::query(
+            r#"
+            INSERT INTO user_recovery_codes (user_id, code_hash)
+            SELECT $1, UNNEST($2::CHAR(64)[])
+            "#,
+        )
+        .bind(user.id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(RecoveryCodes { recovery_codes })
+    }
+
+    /// Verify a totp code or an unused recovery code, both can only be used once
+    pub async fn verify_two_factor(
+        &self,
+        user: &User,
+        code: &str,
+        now: u64,
+    ) -> Result<bool, AppError> {
+        let state = self.get_totp_state(user.id as _).await?;
+        let (true, Some(secret)) = (state.totp_enabled, state.totp_secret) else {
+            return Ok(false);
+        };
+
+        let totp = build_totp(decode_secret(&secret)?, &user.email)?;
+        if let Some(step) = match_totp_step(&totp, code, now) {
+            // only move forward, so a code seen before can't be accepted again
+            let ret = sqlx::query(
+                r#"
+                UPDATE users
+                SET totp_last_step = $2
+                WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+                "#,
+            )
+            .bind(user.id)
+            .bind(step as i64)
+            .execute(&self.pool)
+            .await?;
+            return Ok(ret.rows_affected() == 1);
+        }
+
+        let ret = sqlx::query(
+            r#"
+            UPDATE user_recovery_codes
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+        )
+        .bind(user.id)
+        .bind(hash_recovery_code(code))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    pub async fn disable_totp(&self, user: &User, code: &str, now: u64) -> Result<(), AppError> {
+        let status = self.two_factor_status(user.id as _).await?;
+        if status.required {
+            return Err(AppError::TwoFactorError(
+                "two-factor authentication is required by workspace".to_string(),
+            ));
+        }
+        if !status.enabled {
+            return Err(AppError::TwoFactorError(
+                "two-factor authentication is not enabled".to_string(),
+            ));
+        }
+        if !self.verify_two_factor(user, code, now).await? {
+            return Err(AppError::InvalidTwoFactorCode);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET totp_secret = NULL, totp_enabled = FALSE, totp_last_step = NULL
+            WHERE id = $1
+            "#,
+        )
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_totp_state(&self, user_id: u64) -> Result<TotpState, AppError> {
+        let state = sqlx::query_as("SELECT totp_secret, totp_enabled FROM users WHERE id = $1")
+            .bind(user_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        state.ok_or_else(|| AppError::NotFound(format!("user id {} not found", user_id)))
+    }
+}
+
+fn build_totp(secret: Vec<u8>, account: &str) -> Result<TOTP, AppError> {
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as _,
+        TOTP_STEP,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        account.to_string(),
+    )
+    .map_err(|e| AppError::TwoFactorError(e.to_string()))
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>, AppError> {
+    Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::TwoFactorError(format!("invalid totp secret: {:?}", e)))
+}
+
+// return the time step the code belongs to, if it's within the allowed skew
+fn match_totp_step(totp: &TOTP, code: &str, now: u64) -> Option<u64> {
+    let current = now / TOTP_STEP;
+    (current.saturating_sub(TOTP_SKEW)..=current + TOTP_SKEW)
+        .find(|step| totp.generate(step * TOTP_STEP) == code)
+}
+
+// recovery code looks like `a1b2c-3d4e5`
+fn gen_recovery_code() -> String {
+    let mut buf = [0u8; 5];
+    OsRng.fill_bytes(&mut buf);
+    let code = hex::encode(buf);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_lowercase().as_bytes()))
+}
+
+#[cfg(test)]
+pub(crate) fn totp_code(secret: &str, account: &str, now: u64) -> String {
+    let totp = build_totp(decode_secret(secret).unwrap(), account).unwrap();
+    totp.generate(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::UpdateWorkspace;
+
+    // fixed clock, 2024-12-01T00:00:00Z
+    const NOW: u64 = 1_733_011_200;
+
+    #[test]
+    fn match_totp_step_should_respect_skew() -> Result<()> {
+        let totp = build_totp(b"12345678901234567890".to_vec(), "wiki@acme.org")?;
+        let code = totp.generate(NOW);
+
+        assert_eq!(match_totp_step(&totp, &code, NOW), Some(NOW / TOTP_STEP));
+        assert_eq!(
+            match_totp_step(&totp, &code, NOW + TOTP_STEP),
+            Some(NOW / TOTP_STEP)
+        );
+        assert_eq!(match_totp_step(&totp, &code, NOW + TOTP_STEP * 2), None);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_should_match_rfc6238_vector() -> Result<()> {
+        // RFC 6238 appendix B, SHA1 at T = 59, truncated to 6 digits
+        let totp = build_totp(b"12345678901234567890".to_vec(), "wiki@acme.org")?;
+        assert_eq!(totp.generate(59), "287082");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn totp_enroll_confirm_and_verify_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        let enrollment = state.enroll_totp(&user).await?;
+        assert!(enrollment.uri.starts_with("otpauth://totp/chat_server:"));
+        assert!(!state.two_factor_status(1).await?.enabled);
+
+        // wrong code should be rejected
+        let ret = state.confirm_totp(&user, "000000", NOW).await;
+        assert!(matches!(ret, Err(AppError::InvalidTwoFactorCode)));
+
+        let code = totp_code(&enrollment.secret, &user.email, NOW);
+        let codes = state.confirm_totp(&user, &code, NOW).await?;
+        assert_eq!(codes.recovery_codes.len(), RECOVERY_CODE_COUNT);
+        assert!(state.two_factor_status(1).await?.enabled);
+
+        // code used for confirmation can't be replayed
+        assert!(!state.verify_two_factor(&user, &code, NOW).await?);
+
+        let code = totp_code(&enrollment.secret, &user.email, NOW + TOTP_STEP * 2);
+        assert!(
+            state
+                .verify_two_factor(&user, &code, NOW + TOTP_STEP * 2)
+                .await?
+        );
+
+        // recovery code works only once
+        let recovery = &codes.recovery_codes[0];
+        assert!(state.verify_two_factor(&user, recovery, NOW).await?);
+        assert!(!state.verify_two_factor(&user, recovery, NOW).await?);
+
+        // enroll again after enabled should fail
+        assert!(state.enroll_totp(&user).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn totp_disable_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+
+        let enrollment = state.enroll_totp(&user).await?;
+        let code = totp_code(&enrollment.secret, &user.email, NOW);
+        let codes = state.confirm_totp(&user, &code, NOW).await?;
+
+        // workspace requires 2fa, can't disable
+        state.update_workspace_owner(1, 1).await?;
+        let input = UpdateWorkspace::new(true);
+        state.update_workspace(1, 1, &input).await?;
+        let ret = state
+            .disable_totp(&user, &codes.recovery_codes[0], NOW)
+            .await;
+        assert!(matches!(ret, Err(AppError::TwoFactorError(_))));
+
+        let input = UpdateWorkspace::new(false);
+        state.update_workspace(1, 1, &input).await?;
+        state
+            .disable_totp(&user, &codes.recovery_codes[1], NOW)
+            .await?;
+
+        let status = state.two_factor_status(1).await?;
+        assert_eq!(status, TwoFactorStatus::default());
+        assert!(
+            !state
+                .verify_two_factor(&user, &codes.recovery_codes[2], NOW)
+                .await?
+        );
+        Ok(())
+    }
+}
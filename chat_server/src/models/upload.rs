@@ -0,0 +1,435 @@
+use std::{
+    collections::HashSet, fmt, io::ErrorKind, path::PathBuf, sync::Mutex,
+    time::Duration as StdDuration,
+};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::body::Bytes;
+use chat_core::Attachment;
+use chrono::{DateTime, Duration, Utc};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::warn;
+
+use crate::{models::TEMP_DIR, AppError, AppState};
+
+/// The implemented version of the tus resumable upload protocol
+pub const TUS_VERSION: &str = "1.0.0";
+pub(crate) const TUS_RESUMABLE_HEADER: &str = "tus-resumable";
+pub(crate) const TUS_VERSION_HEADER: &str = "tus-version";
+
+// partial uploads are kept apart from the temp files of single requests
+const UPLOADS_DIR: &str = "uploads";
+const UPLOAD_CLEANUP_INTERVAL: StdDuration = StdDuration::from_secs(10 * 60);
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Uploads a request is writing to. Received bytes are kept on the local disk,
+/// so a lock in the process is enough to keep requests from racing
+#[derive(Debug, Default)]
+pub struct UploadLocks(Mutex<HashSet<String>>);
+
+struct UploadGuard<'a> {
+    locks: &'a UploadLocks,
+    id: String,
+}
+
+impl UploadLocks {
+    fn try_lock(&self, id: &str) -> Option<UploadGuard<'_>> {
+        let mut ids = self.0.lock().expect("upload locks should not be poisoned");
+        ids.insert(id.to_string()).then(|| UploadGuard {
+            locks: self,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl Drop for UploadGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut ids) = self.locks.0.lock() {
+            ids.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Upload {
+    pub id: String,
+    pub workspace_id: i64,
+    pub uploader_id: i64,
+    pub name: String,
+    pub length: i64,
+    pub received: i64,
+    pub file_id: Option<i64>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Start a resumable upload of `length` bytes, checked against the upload
+    /// policy of the workspace up front
+    pub async fn create_upload(
+        &self,
+        workspace_id: u64,
+        uploader_id: u64,
+        name: &str,
+        content_type: Option<&str>,
+        length: u64,
+    ) -> Result<Upload, AppError> {
+        if name.is_empty() {
+            return Err(AppError::UploadError("file name is required".to_string()));
+        }
+        let policy = self.upload_policy(workspace_id, uploader_id).await?;
+        policy.check_file(name, content_type)?;
+        policy.check_size(length)?;
+
+        let mut buf = [0u8; 16];
+        OsRng.fill_bytes(&mut buf);
+        let id = hex::encode(buf);
+        let path = self.upload_path(&id);
+        fs::create_dir_all(path.parent().expect("upload path parent should exists")).await?;
+        fs::File::create(&path).await?;
+
+        let upload: Upload = sqlx::query_as(
+            r#"
+            INSERT INTO uploads (id, workspace_id, uploader_id, name, length, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, workspace_id, uploader_id, name, length, received, file_id, expires_at,
+                created_at
+            "#,
+        )
+        .bind(&id)
+        .bind(workspace_id as i64)
+        .bind(uploader_id as i64)
+        .bind(name)
+        .bind(length as i64)
+        .bind(self.upload_expires_at())
+        .fetch_one(&self.pool)
+        .await?;
+
+        // nothing to wait for
+        if length == 0 {
+            return self.complete_upload(upload).await;
+        }
+        Ok(upload)
+    }
+
+    /// An upload of the user that hasn't expired yet
+    pub async fn find_upload(
+        &self,
+        id: &str,
+        uploader_id: u64,
+    ) -> Result<Option<Upload>, AppError> {
+        let upload = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, uploader_id, name, length, received, file_id, expires_at,
+                created_at
+            FROM uploads
+            WHERE id = $1 AND uploader_id = $2 AND expires_at > NOW()
+            "#,
+        )
+        .bind(id)
+        .bind(uploader_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(upload)
+    }
+
+    /// Write the chunk at `offset`, which must be where the upload stopped. The
+    /// bytes received before an interruption are kept, and the file is stored
+    /// once the last byte is received.
+    pub async fn append_upload<S, E>(
+        &self,
+        upload: &Upload,
+        offset: u64,
+        mut chunk: S,
+    ) -> Result<Upload, AppError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: fmt::Display,
+    {
+        // held until the upload is stored, so concurrent requests at the same
+        // offset can't write over each other's bytes. No transaction is open
+        // while the body is streamed
+        let Some(_guard) = self.upload_locks.try_lock(&upload.id) else {
+            return Err(AppError::UploadOffsetMismatch(
+                "upload is being written by another request".to_string(),
+            ));
+        };
+        let current: Option<i64> = sqlx::query_scalar("SELECT received FROM uploads WHERE id = $1")
+            .bind(&upload.id)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(current) = current else {
+            return Err(AppError::NotFound(format!(
+                "upload {} not found",
+                upload.id
+            )));
+        };
+        if offset != current as u64 {
+            return Err(AppError::UploadOffsetMismatch(format!(
+                "upload is at offset {}",
+                current
+            )));
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(self.upload_path(&upload.id))
+            .await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut received = offset;
+        let mut error = None;
+        while let Some(data) = chunk.next().await {
+            let data = match data {
+                Ok(data) => data,
+                Err(e) => {
+                    error = Some(AppError::UploadError(format!("upload interrupted: {}", e)));
+                    break;
+                }
+            };
+            if received + data.len() as u64 > upload.length as u64 {
+                error = Some(AppError::PayloadTooLarge(format!(
+                    "upload is limited to {} bytes",
+                    upload.length
+                )));
+                break;
+            }
+            file.write_all(&data).await?;
+            received += data.len() as u64;
+        }
+        file.flush().await?;
+
+        let updated: Upload = sqlx::query_as(
+            r#"
+            UPDATE uploads
+            SET received = $1, expires_at = $2
+            WHERE id = $3
+            RETURNING id, workspace_id, uploader_id, name, length, received, file_id, expires_at,
+                created_at
+            "#,
+        )
+        .bind(received as i64)
+        .bind(self.upload_expires_at())
+        .bind(&upload.id)
+        .fetch_one(&self.pool)
+        .await?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        if updated.received == updated.length {
+            return self.complete_upload(updated).await;
+        }
+        Ok(updated)
+    }
+
+    /// Remove the uploads that weren't resumed in time, with their received bytes
+    pub async fn delete_expired_uploads(&self, now: DateTime<Utc>) -> Result<usize, AppError> {
+        let ids: Vec<String> =
+            sqlx::query_scalar("DELETE FROM uploads WHERE expires_at <= $1 RETURNING id")
+                .bind(now)
+                .fetch_all(&self.pool)
+                .await?;
+        for id in &ids {
+            match fs::remove_file(self.upload_path(id)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        Ok(ids.len())
+    }
+
+    /// Clean up expired uploads in the background
+    pub fn spawn_upload_cleaner(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(UPLOAD_CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = state.delete_expired_uploads(Utc::now()).await {
+                    warn!("Failed to delete expired uploads: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Hash and store the received bytes like a single request upload
+    async fn complete_upload(&self, mut upload: Upload) -> Result<Upload, AppError> {
+        let path = self.upload_path(&upload.id);
+        let mut temp = self.temp_file().await?;
+        let mut file = fs::File::open(&path).await?.take(upload.length as u64);
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            temp.write(&buf[..n]).await?;
+        }
+        let attachment: Attachment = self
+            .store_file(
+                upload.workspace_id as _,
+                upload.uploader_id as _,
+                &upload.name,
+                temp,
+            )
+            .await?;
+
+        sqlx::query("UPDATE uploads SET file_id = $1 WHERE id = $2")
+            .bind(attachment.id)
+            .bind(&upload.id)
+            .execute(&self.pool)
+            .await?;
+        fs::remove_file(&path).await?;
+        upload.file_id = Some(attachment.id);
+
+        Ok(upload)
+    }
+
+    fn upload_path(&self, id: &str) -> PathBuf {
+        self.config
+            .server
+            .base_dir
+            .join(TEMP_DIR)
+            .join(UPLOADS_DIR)
+            .join(id)
+    }
+
+    fn upload_expires_at(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::seconds(self.config.files.upload_expires_secs as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use anyhow::Result;
+    use futures::stream;
+
+    use super::*;
+    use crate::UploadRejection;
+
+    fn chunks(data: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, Infallible>> + Unpin {
+        stream::iter(
+            data.iter()
+                .map(|d| Ok(Bytes::from_static(d)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn append_upload_should_store_the_file_when_complete() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let upload = state.create_upload(1, 1, "hello.txt", None, 11).await?;
+        assert_eq!(upload.received, 0);
+
+        let upload = state
+            .append_upload(&upload, 0, chunks(&[b"hello", b" "]))
+            .await?;
+        assert_eq!(upload.received, 6);
+        assert_eq!(upload.file_id, None);
+
+        let ret = state.append_upload(&upload, 0, chunks(&[b"world"])).await;
+        assert!(matches!(ret, Err(AppError::UploadOffsetMismatch(_))));
+        let ret = state
+            .append_upload(&upload, 6, chunks(&[b"world and more"]))
+            .await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(_))));
+
+        let upload = state
+            .find_upload(&upload.id, 1)
+            .await?
+            .expect("upload should exist");
+        let upload = state.append_upload(&upload, 6, chunks(&[b"world"])).await?;
+        assert_eq!(upload.received, 11);
+        let file_id = upload.file_id.expect("file should be stored");
+        assert!(!state.upload_path(&upload.id).exists());
+
+        // hashed and deduplicated like any other upload
+        let file = state.create_file(1, 1, "hello.txt", b"hello world").await?;
+        let url: String = sqlx::query_scalar("SELECT url FROM files WHERE id = $1")
+            .bind(file_id)
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(url, file.url);
+        assert!(state.find_upload(&upload.id, 2).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn append_upload_should_keep_bytes_received_before_an_error() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let upload = state.create_upload(1, 1, "hello.txt", None, 11).await?;
+        let chunk = stream::iter(vec![Ok(Bytes::from_static(b"hello")), Err("reset")]);
+        let ret = state.append_upload(&upload, 0, chunk).await;
+        assert!(matches!(ret, Err(AppError::UploadError(_))));
+
+        let upload = state
+            .find_upload(&upload.id, 1)
+            .await?
+            .expect("upload should exist");
+        assert_eq!(upload.received, 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends_should_not_overwrite_each_other() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let upload = state.create_upload(1, 1, "hello.txt", None, 11).await?;
+        // the chunk is only read once the first request holds the upload
+        let started = tokio::sync::Notify::new();
+        let slow = chunks(&[b"hel", b"lo"]).then(|data| async {
+            started.notify_one();
+            tokio::time::sleep(StdDuration::from_millis(100)).await;
+            data
+        });
+        let fast = async {
+            started.notified().await;
+            state.append_upload(&upload, 0, chunks(&[b"HELLO"])).await
+        };
+        let (first, second) = tokio::join!(state.append_upload(&upload, 0, Box::pin(slow)), fast);
+        assert_eq!(first?.received, 5);
+        assert!(matches!(second, Err(AppError::UploadOffsetMismatch(_))));
+        assert_eq!(fs::read(state.upload_path(&upload.id)).await?, b"hello");
+
+        // released once the first request is done
+        let done = state
+            .append_upload(&upload, 5, chunks(&[b" world"]))
+            .await?;
+        assert!(done.file_id.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_expired_uploads_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let upload = state.create_upload(1, 1, "hello.txt", None, 11).await?;
+        assert!(state
+            .create_upload(1, 1, "empty.txt", None, 0)
+            .await?
+            .file_id
+            .is_some());
+        let ret = state.create_upload(1, 1, "big.bin", None, u64::MAX).await;
+        assert!(matches!(
+            ret,
+            Err(AppError::UploadRejected(
+                UploadRejection::FileTooLarge { .. }
+            ))
+        ));
+
+        assert_eq!(state.delete_expired_uploads(Utc::now()).await?, 0);
+        let later = upload.expires_at + Duration::seconds(1);
+        assert_eq!(state.delete_expired_uploads(later).await?, 2);
+        assert!(!state.upload_path(&upload.id).exists());
+        Ok(())
+    }
+}
@@ -106,9 +106,12 @@ impl AppState {
         .await?;
 
         if let Some(mut user) = user {
-            let password_hash = mem::take(&mut user.password_hash);
+            // users provisioned by an identity provider have no password
+            let Some(password_hash) = mem::take(&mut user.password_hash) else {
+                return Ok(None);
+            };
 
-            let is_valid = verify_password(&input.password, &password_hash.unwrap_or_default())?;
+            let is_valid = verify_password(&input.password, &password_hash)?;
             if is_valid {
                 return Ok(Some(user));
             }
@@ -119,7 +122,7 @@ impl AppState {
     pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email, created_at
+            SELECT id, fullname, email, is_bot, created_at
             FROM users
             WHERE id = ANY($1)
             "#,
@@ -131,10 +134,30 @@ impl AppState {
         Ok(users)
     }
 
+    pub async fn find_chat_user_by_fullname(
+        &self,
+        workspace_id: u64,
+        fullname: &str,
+    ) -> Result<Option<ChatUser>, AppError> {
+        let user = sqlx::query_as(
+            r#"
+            SELECT id, fullname, email, is_bot, created_at
+            FROM users
+            WHERE workspace_id = $1 AND fullname = $2
+            "#,
+        )
+        .bind(workspace_id as i64)
+        .bind(fullname)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
     pub async fn fetch_chat_users(&self, workspace_id: i64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email, created_at
+            SELECT id, fullname, email, is_bot, created_at
             FROM users
             WHERE workspace_id = $1
             "#,
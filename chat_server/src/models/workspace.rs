@@ -1,16 +1,22 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
 use crate::{AppError, AppState};
 
 use chat_core::Workspace;
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateWorkspace {
+    pub require_2fa: Option<bool>,
+}
+
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
         let ws = sqlx::query_as(
             r#"
             INSERT INTO workspaces (name, owner_id)
             VALUES ($1, $2)
-            RETURNING id, name, owner_id, created_at
+            RETURNING id, name, owner_id, require_2fa, created_at
             "#,
         )
         .bind(name)
@@ -23,7 +29,7 @@ impl AppState {
     pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-            SELECT id, name, owner_id, created_at
+            SELECT id, name, owner_id, require_2fa, created_at
             FROM workspaces
             WHERE name = $1
             "#,
@@ -38,7 +44,7 @@ impl AppState {
     pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-            SELECT id, name, owner_id, created_at
+            SELECT id, name, owner_id, require_2fa, created_at
             FROM workspaces
             WHERE id = $1
             "#,
@@ -60,7 +66,7 @@ impl AppState {
             UPDATE workspaces
             SET owner_id = $2
             WHERE id = $1
-            RETURNING id, name, owner_id, created_at
+            RETURNING id, name, owner_id, require_2fa, created_at
             "#,
         )
         .bind(id as i64)
@@ -69,6 +75,56 @@ impl AppState {
         .await?;
         Ok(ws)
     }
+
+    /// Update workspace settings, only the owner is allowed to do so
+    pub async fn update_workspace(
+        &self,
+        id: u64,
+        user_id: u64,
+        input: &UpdateWorkspace,
+    ) -> Result<Workspace, AppError> {
+        self.verify_workspace_owner(id, user_id).await?;
+
+        let ws = sqlx::query_as(
+            r#"
+            UPDATE workspaces
+            SET require_2fa = COALESCE($2, require_2fa)
+            WHERE id = $1
+            RETURNING id, name, owner_id, require_2fa, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(input.require_2fa)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ws)
+    }
+
+    pub async fn verify_workspace_owner(
+        &self,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Workspace, AppError> {
+        let Some(ws) = self.find_workspace_by_id(id).await? else {
+            return Err(AppError::NotFound(format!("workspace id {} not found", id)));
+        };
+
+        if ws.owner_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(
+                "only workspace owner can update workspace settings".to_string(),
+            ));
+        }
+        Ok(ws)
+    }
+}
+
+#[cfg(test)]
+impl UpdateWorkspace {
+    pub fn new(require_2fa: bool) -> Self {
+        Self {
+            require_2fa: Some(require_2fa),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -96,6 +152,21 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn workspace_update_should_only_allow_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateWorkspace::new(true);
+
+        let ret = state.update_workspace(1, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state.update_workspace_owner(1, 1).await?;
+        let ws = state.update_workspace(1, 1, &input).await?;
+        assert!(ws.require_2fa);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn workspace_should_find_by_name() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -0,0 +1,197 @@
+use std::{path::Path, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+};
+
+use super::{ScanVerdict, Scanner};
+use crate::AppError;
+
+// clamd rejects chunks larger than its StreamMaxLength, keep them small
+const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_REPLY_LEN: usize = 4096;
+
+/// Streams files to clamd with the INSTREAM command, over tcp (`host:port`) or
+/// a unix socket (`unix:/path/to/clamd.sock`)
+#[derive(Debug, Clone)]
+pub struct ClamdScanner {
+    address: String,
+    timeout: Duration,
+}
+
+impl ClamdScanner {
+    pub fn new(address: impl Into<String>, timeout_secs: u64) -> Self {
+        Self {
+            address: address.into(),
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+
+    async fn scan_file(&self, path: &Path) -> Result<String, AppError> {
+        let file = fs::File::open(path).await?;
+        match self.address.strip_prefix("unix:") {
+            Some(socket) => {
+                let stream = UnixStream::connect(socket).await.map_err(scan_error)?;
+                instream(stream, file).await
+            }
+            None => {
+                let stream = TcpStream::connect(&self.address)
+                    .await
+                    .map_err(scan_error)?;
+                instream(stream, file).await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamdScanner {
+    async fn scan(&self, path: &Path) -> Result<ScanVerdict, AppError> {
+        let reply = tokio::time::timeout(self.timeout, self.scan_file(path))
+            .await
+            .map_err(|_| AppError::ScanError("clamd timed out".to_string()))??;
+        parse_reply(&reply)
+    }
+}
+
+/// Send the file as length prefixed chunks, a zero length chunk ends it
+async fn instream<S, R>(mut stream: S, mut file: R) -> Result<String, AppError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await.map_err(scan_error)?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        stream
+            .write_all(&(n as u32).to_be_bytes())
+            .await
+            .map_err(scan_error)?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&buf[..n]).await.map_err(scan_error)?;
+    }
+    stream.flush().await.map_err(scan_error)?;
+
+    // the reply is terminated by a null byte with the z prefix
+    let mut reply = Vec::new();
+    let mut byte = [0u8; 1];
+    while reply.len() < MAX_REPLY_LEN {
+        if stream.read(&mut byte).await.map_err(scan_error)? == 0 || byte[0] == 0 {
+            break;
+        }
+        reply.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&reply).trim().to_string())
+}
+
+/// `stream: OK`, `stream: Eicar-Signature FOUND` or `... ERROR`
+fn parse_reply(reply: &str) -> Result<ScanVerdict, AppError> {
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        return Ok(ScanVerdict::Clean);
+    }
+    if let Some(signature) = result.strip_suffix(" FOUND") {
+        return Ok(ScanVerdict::Infected(signature.trim().to_string()));
+    }
+    Err(AppError::ScanError(format!(
+        "unexpected clamd reply: {}",
+        reply
+    )))
+}
+
+fn scan_error(e: std::io::Error) -> AppError {
+    AppError::ScanError(e.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use anyhow::Result;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Test signature, flagged by every scanner
+    pub(crate) const EICAR: &str =
+        r"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    /// A clamd that only knows the EICAR test signature, returns its address
+    pub(crate) async fn fake_clamd() -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut command = [0u8; 10];
+                    stream.read_exact(&mut command).await?;
+                    if &command != b"zINSTREAM\0" {
+                        stream.write_all(b"UNKNOWN COMMAND\0").await?;
+                        return Ok::<_, std::io::Error>(());
+                    }
+                    let mut data = vec![];
+                    loop {
+                        let len = stream.read_u32().await? as usize;
+                        if len == 0 {
+                            break;
+                        }
+                        let mut chunk = vec![0u8; len];
+                        stream.read_exact(&mut chunk).await?;
+                        data.extend(chunk);
+                    }
+                    let infected = data.windows(EICAR.len()).any(|w| w == EICAR.as_bytes());
+                    let reply: &[u8] = if infected {
+                        b"stream: Eicar-Signature FOUND\0"
+                    } else {
+                        b"stream: OK\0"
+                    };
+                    stream.write_all(reply).await
+                });
+            }
+        });
+        Ok(address)
+    }
+
+    #[test]
+    fn parse_reply_should_work() -> Result<()> {
+        assert_eq!(parse_reply("stream: OK")?, ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND")?,
+            ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string())
+        );
+        assert!(parse_reply("INSTREAM size limit exceeded. ERROR").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clamd_scanner_should_work() -> Result<()> {
+        let scanner = ClamdScanner::new(fake_clamd().await?, 5);
+        let dir = std::env::temp_dir().join(format!("chat_server_clamd_{}", std::process::id()));
+        fs::create_dir_all(&dir).await?;
+        let clean = dir.join("clean.txt");
+        fs::write(&clean, "hello world").await?;
+        assert_eq!(scanner.scan(&clean).await?, ScanVerdict::Clean);
+
+        // spread over several chunks
+        let infected = dir.join("infected.txt");
+        let mut data = vec![b'x'; CHUNK_SIZE - 10];
+        data.extend(EICAR.as_bytes());
+        fs::write(&infected, data).await?;
+        assert_eq!(
+            scanner.scan(&infected).await?,
+            ScanVerdict::Infected("Eicar-Signature".to_string())
+        );
+
+        let unreachable = ClamdScanner::new("127.0.0.1:1", 5);
+        assert!(matches!(
+            unreachable.scan(&clean).await,
+            Err(AppError::ScanError(_))
+        ));
+        fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+}
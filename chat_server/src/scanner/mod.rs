@@ -0,0 +1,49 @@
+mod clamd;
+
+use std::{path::Path, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{config::ScannerConfig, AppError};
+
+pub use clamd::ClamdScanner;
+
+#[cfg(test)]
+pub(crate) use clamd::tests::{fake_clamd, EICAR};
+
+/// What the scanner found in a file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanVerdict {
+    /// no scanner is configured
+    Unscanned,
+    Clean,
+    /// with the name of the signature that matched
+    Infected(String),
+}
+
+/// Inspects uploads before they are stored and can be attached to messages
+#[async_trait]
+pub trait Scanner: Send + Sync + 'static {
+    async fn scan(&self, path: &Path) -> Result<ScanVerdict, AppError>;
+}
+
+/// Accepts everything, the default without a scanner configured
+#[derive(Debug, Clone, Default)]
+pub struct NoopScanner;
+
+#[async_trait]
+impl Scanner for NoopScanner {
+    async fn scan(&self, _path: &Path) -> Result<ScanVerdict, AppError> {
+        Ok(ScanVerdict::Unscanned)
+    }
+}
+
+pub fn scanner_from_config(config: &ScannerConfig) -> Arc<dyn Scanner> {
+    match config {
+        ScannerConfig::None => Arc::new(NoopScanner),
+        ScannerConfig::Clamd {
+            address,
+            timeout_secs,
+        } => Arc::new(ClamdScanner::new(address, *timeout_secs)),
+    }
+}
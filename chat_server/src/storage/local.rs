@@ -0,0 +1,255 @@
+use std::{
+    io::{ErrorKind, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use axum::body::Body;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+use super::{validate_key, Storage};
+use crate::{
+    models::{QUARANTINE_DIR, TEMP_DIR},
+    AppError,
+};
+
+/// Files on the local disk, under the root dir
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// The real path of the key, which must stay under the directory of its
+    /// first component, the workspace, even through symlinks
+    async fn resolve(&self, key: &str) -> Result<PathBuf, AppError> {
+        validate_key(key)?;
+        let path = self.root.join(key);
+        let Ok(root) = fs::canonicalize(&self.root).await else {
+            // nothing is stored yet, so there is no link to follow
+            return Ok(path);
+        };
+
+        // only the existing part can be canonicalized, the rest are plain names
+        let mut existing = path.clone();
+        let mut missing = vec![];
+        let real = loop {
+            match fs::canonicalize(&existing).await {
+                Ok(real) => break real,
+                Err(e) if e.kind() == ErrorKind::NotFound && existing != self.root => {
+                    missing.extend(existing.file_name().map(|name| name.to_owned()));
+                    existing.pop();
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let real = missing
+            .iter()
+            .rev()
+            .fold(real, |path, name| path.join(name));
+        let workspace = key.split('/').next().unwrap_or_default();
+        if !real.starts_with(root.join(workspace)) {
+            return Err(AppError::ChatFileError(format!(
+                "File path escapes its workspace: {}",
+                key
+            )));
+        }
+        Ok(real)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn size(&self, key: &str) -> Result<Option<u64>, AppError> {
+        match fs::metadata(self.resolve(key).await?).await {
+            Ok(metadata) if metadata.is_file() => Ok(Some(metadata.len())),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), AppError> {
+        let target = self.resolve(key).await?;
+        fs::create_dir_all(target.parent().expect("file path parent should exists")).await?;
+        // rename only works on the same file system
+        if fs::rename(path, &target).await.is_err() {
+            fs::copy(path, &target).await?;
+            fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> Result<Body, AppError> {
+        let mut file = fs::File::open(self.resolve(key).await?).await?;
+        let body = match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Body::from_stream(ReaderStream::new(file.take(range.end - range.start)))
+            }
+            None => Body::from_stream(ReaderStream::new(file)),
+        };
+        Ok(body)
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> Result<(), AppError> {
+        fs::copy(self.resolve(key).await?, path).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.resolve(key).await?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<String>, AppError> {
+        let mut keys = vec![];
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                // uploads in progress aren't stored yet, infected ones never are
+                if path == self.root.join(TEMP_DIR) || path == self.root.join(QUARANTINE_DIR) {
+                    continue;
+                }
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                } else if let Ok(key) = path.strip_prefix(&self.root) {
+                    let parts: Vec<_> = key.iter().map(|p| p.to_string_lossy()).collect();
+                    keys.push(parts.join("/"));
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn local_path(&self, key: &str) -> Result<Option<PathBuf>, AppError> {
+        Ok(Some(self.resolve(key).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::TempFile;
+
+    /// Two workspaces, the first with links to the second and out of the root
+    async fn linked_storage(name: &str) -> Result<(PathBuf, LocalStorage)> {
+        let dir = std::env::temp_dir().join(format!("chat_server_{}_{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("root/1")).await?;
+        fs::create_dir_all(dir.join("root/2")).await?;
+        fs::create_dir_all(dir.join("outside")).await?;
+        for (link, target) in [("root/1/other", "root/2"), ("root/1/out", "outside")] {
+            if fs::symlink_metadata(dir.join(link)).await.is_err() {
+                fs::symlink(dir.join(target), dir.join(link)).await?;
+            }
+        }
+        Ok((dir.clone(), LocalStorage::new(dir.join("root"))))
+    }
+
+    #[tokio::test]
+    async fn resolve_should_keep_keys_in_their_workspace() -> Result<()> {
+        let (dir, storage) = linked_storage("resolve").await?;
+        let root = fs::canonicalize(dir.join("root")).await?;
+        let path = storage.resolve("1/abc/def/0123.txt").await?;
+        assert_eq!(path, root.join("1/abc/def/0123.txt"));
+
+        for key in [
+            "",
+            "../outside/x",
+            "1/../2/x",
+            "/etc/passwd",
+            "1//x",
+            "1/./x",
+            "1\\..\\2\\x",
+            "1/other/x",
+            "1/out/x",
+            "1/out",
+        ] {
+            assert!(
+                storage.resolve(key).await.is_err(),
+                "{} should be rejected",
+                key
+            );
+            assert!(storage.size(key).await.is_err());
+        }
+
+        fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn resolved_paths_should_stay_in_the_workspace(
+            parts in prop::collection::vec(
+                prop_oneof![
+                    Just(".."),
+                    Just("."),
+                    Just(""),
+                    Just("1"),
+                    Just("other"),
+                    Just("out"),
+                    Just("x"),
+                ],
+                1..6,
+            ),
+        ) {
+            let rt = tokio::runtime::Builder::new_current_thread().build()?;
+            rt.block_on(async {
+                let (dir, storage) = linked_storage("resolve_prop").await.expect("storage should be set up");
+                let key = parts.join("/");
+                if let Ok(path) = storage.resolve(&key).await {
+                    let root = fs::canonicalize(dir.join("root")).await?;
+                    prop_assert!(path.starts_with(root.join(parts[0])));
+                    prop_assert!(!path.starts_with(root.join("2")));
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    #[tokio::test]
+    async fn local_storage_should_work() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("chat_server_local_{}", std::process::id()));
+        let storage = LocalStorage::new(dir.clone());
+        let key = "1/abc/def/0123.txt";
+        assert_eq!(storage.size(key).await?, None);
+
+        let temp = TempFile::new(&dir, u64::MAX).await?;
+        fs::write(temp.path(), "hello world").await?;
+        storage.put_file(key, temp.path()).await?;
+        assert!(!temp.path().exists());
+        assert_eq!(storage.size(key).await?, Some(11));
+        assert_eq!(storage.list().await?, vec![key]);
+
+        let body = storage.get(key, Some(6..11)).await?;
+        let data = axum::body::to_bytes(body, usize::MAX).await?;
+        assert_eq!(data.as_ref(), b"world");
+
+        storage.delete(key).await?;
+        assert!(!storage.exists(key).await?);
+        storage.delete(key).await?;
+
+        fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+}
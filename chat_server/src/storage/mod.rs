@@ -0,0 +1,134 @@
+mod local;
+mod s3;
+
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use axum::body::Body;
+use tracing::info;
+
+use crate::{config::StorageConfig, AppError, TempFile};
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// Where the content of files lives, keys are `ChatFile::hash_to_path`
+#[async_trait]
+pub trait Storage: Send + Sync + 'static {
+    /// Size of the object, `None` if it doesn't exist
+    async fn size(&self, key: &str) -> Result<Option<u64>, AppError>;
+
+    /// Move a local file into the storage
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), AppError>;
+
+    /// Stream the object, or a byte range of it
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> Result<Body, AppError>;
+
+    /// Copy the object to a local file
+    async fn download(&self, key: &str, path: &Path) -> Result<(), AppError>;
+
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// Keys of all stored objects
+    async fn list(&self) -> Result<Vec<String>, AppError>;
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        Ok(self.size(key).await?.is_some())
+    }
+
+    /// Path of the object if it is on the local file system
+    async fn local_path(&self, _key: &str) -> Result<Option<PathBuf>, AppError> {
+        Ok(None)
+    }
+
+    /// Url to redirect downloads to instead of streaming them through the server
+    fn presigned_url(&self, _key: &str, _content_disposition: Option<&str>) -> Option<String> {
+        None
+    }
+}
+
+/// Keys are relative paths of plain names, e.g. `1/c13/504/f6f2e1.jpeg`
+pub(crate) fn validate_key(key: &str) -> Result<(), AppError> {
+    let valid = key
+        .split('/')
+        .all(|part| !matches!(part, "" | "." | "..") && !part.contains(['\\', '\0']));
+    if !valid {
+        return Err(AppError::ChatFileError(format!(
+            "Invalid storage key: {}",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Local storage defaults to the base dir of the server
+pub fn storage_from_config(
+    config: &StorageConfig,
+    base_dir: &Path,
+) -> Result<Arc<dyn Storage>, AppError> {
+    let storage: Arc<dyn Storage> = match config {
+        StorageConfig::Local { path } => Arc::new(LocalStorage::new(
+            path.clone().unwrap_or_else(|| base_dir.to_path_buf()),
+        )),
+        StorageConfig::S3(config) => Arc::new(S3Storage::try_new(config.clone())?),
+    };
+    Ok(storage)
+}
+
+/// Copy the objects missing in `to`, returns how many were copied
+pub async fn copy_files(
+    from: &dyn Storage,
+    to: &dyn Storage,
+    base_dir: &Path,
+) -> Result<usize, AppError> {
+    let mut copied = 0;
+    for key in from.list().await? {
+        if to.exists(&key).await? {
+            continue;
+        }
+        let temp = TempFile::new(base_dir, u64::MAX).await?;
+        from.download(&key, temp.path()).await?;
+        to.put_file(&key, temp.path()).await?;
+        info!("Copied {}", key);
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn copy_files_should_work() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("chat_server_copy_{}", std::process::id()));
+        let from = LocalStorage::new(dir.join("from"));
+        let to = LocalStorage::new(dir.join("to"));
+        for (key, data) in [
+            ("1/abc/def/0123.txt", "hello"),
+            ("1/abc/def/4567.txt", "world"),
+        ] {
+            let temp = TempFile::new(&dir, u64::MAX).await?;
+            tokio::fs::write(temp.path(), data).await?;
+            from.put_file(key, temp.path()).await?;
+        }
+        let temp = TempFile::new(&dir, u64::MAX).await?;
+        tokio::fs::write(temp.path(), "hello").await?;
+        to.put_file("1/abc/def/0123.txt", temp.path()).await?;
+
+        assert_eq!(copy_files(&from, &to, &dir).await?, 1);
+        let mut keys = to.list().await?;
+        keys.sort();
+        assert_eq!(keys, vec!["1/abc/def/0123.txt", "1/abc/def/4567.txt"]);
+        assert_eq!(copy_files(&from, &to, &dir).await?, 0);
+
+        tokio::fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chat_core::{Chat, ChatType, Message};
+use chat_core::{Attachment, Chat, ChatType, Message};
 use futures::StreamExt;
 use reqwest::{
     multipart::{Form, Part},
@@ -110,11 +110,35 @@ impl ChatServer {
             .await?;
 
         assert_eq!(res.status(), StatusCode::OK);
-        let ret: Vec<String> = res.json().await?;
+        let ret: Vec<Attachment> = res.json().await?;
+        assert_eq!(ret.len(), 1);
+        assert_eq!(ret[0].name, "Cargo.toml");
+        assert_eq!(ret[0].size, data.len() as i64);
+
+        // download a range, then revalidate with the etag
+        let url = format!("http://{}/api{}", self.addr, ret[0].url);
+        let res = self
+            .client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Range", "bytes=0-9")
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let etag = res.headers()["etag"].clone();
+        assert_eq!(res.bytes().await?.as_ref(), &data[..10]);
+        let res = self
+            .client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("If-None-Match", etag)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
 
         let body = serde_json::to_string(&serde_json::json!({
             "content": "hello",
-            "files": ret
+            "files": [ret[0].id]
         }))?;
 
         let res = self
@@ -128,7 +152,9 @@ impl ChatServer {
         assert_eq!(res.status(), StatusCode::CREATED);
         let message = res.json::<Message>().await?;
         assert_eq!(message.content, "hello");
-        assert_eq!(message.files, ret);
+        assert_eq!(message.files, vec![ret[0].url.clone()]);
+        // the blurhash of images is filled in the background
+        assert_eq!(message.attachments[0].id, ret[0].id);
         assert_eq!(message.sender_id, 1);
         assert_eq!(message.chat_id, chat_id as i64);
 
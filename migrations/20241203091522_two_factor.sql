@@ -0,0 +1,20 @@
+-- Add migration script here
+-- totp secret is stored base32 encoded, only active once totp_enabled is set
+ALTER TABLE users ADD COLUMN totp_secret VARCHAR(64);
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+-- last accepted time step, a code can't be replayed within its window
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- workspace owner can require 2fa for every member
+ALTER TABLE workspaces ADD COLUMN require_2fa BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- one-time recovery codes, stored as sha256 hex
+CREATE TABLE IF NOT EXISTS user_recovery_codes (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON user_recovery_codes (user_id);
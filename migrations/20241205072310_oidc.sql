@@ -0,0 +1,25 @@
+-- Add migration script here
+-- users signed in through an identity provider don't have a password
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+
+-- openid connect provider, at most one per workspace
+CREATE TABLE IF NOT EXISTS oidc_providers (
+    id BIGSERIAL PRIMARY KEY,
+    workspace_id BIGINT NOT NULL UNIQUE,
+    issuer VARCHAR(256) NOT NULL,
+    client_id VARCHAR(256) NOT NULL,
+    client_secret VARCHAR(256) NOT NULL,
+    redirect_uri VARCHAR(256) NOT NULL,
+    -- create users that don't exist yet in the workspace
+    auto_provision BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- pending authorization requests, keyed by the state parameter
+CREATE TABLE IF NOT EXISTS oidc_auth_requests (
+    state VARCHAR(64) PRIMARY KEY,
+    provider_id BIGINT NOT NULL,
+    nonce VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- display name override of a message, e.g. the username of a webhook payload
+ALTER TABLE messages ADD COLUMN sender_name VARCHAR(64);
+
+-- incoming webhooks post into a chat as a bot, only the sha256 hex of the secret is stored
+CREATE TABLE IF NOT EXISTS incoming_webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    bot_id BIGINT NOT NULL REFERENCES users(id),
+    creator_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR(64) NOT NULL,
+    secret_hash CHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_incoming_webhooks_chat_id ON incoming_webhooks (chat_id);
@@ -0,0 +1,42 @@
+-- Add migration script here
+-- bots subscribe to chat events, delivered as signed http posts by notify_server
+CREATE TABLE IF NOT EXISTS event_subscriptions (
+    id BIGSERIAL PRIMARY KEY,
+    bot_id BIGINT NOT NULL REFERENCES users(id),
+    url VARCHAR(1024) NOT NULL,
+    -- used as hmac key, so it has to be stored in plain
+    secret VARCHAR(64) NOT NULL,
+    events TEXT[] NOT NULL,
+    -- empty means every chat the bot is a member of
+    chat_ids BIGINT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_subscriptions_bot_id ON event_subscriptions (bot_id);
+
+CREATE TYPE delivery_status AS ENUM ('pending', 'delivered', 'failed');
+
+CREATE TABLE IF NOT EXISTS event_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    subscription_id BIGINT NOT NULL REFERENCES event_subscriptions(id) ON DELETE CASCADE,
+    event_type VARCHAR(32) NOT NULL,
+    status delivery_status NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    response_status INT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_deliveries_subscription_id ON event_deliveries (subscription_id, id DESC);
+
+-- deliveries that ran out of retries, kept with their payload for inspection and replay
+CREATE TABLE IF NOT EXISTS event_dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    delivery_id BIGINT NOT NULL REFERENCES event_deliveries(id) ON DELETE CASCADE,
+    subscription_id BIGINT NOT NULL REFERENCES event_subscriptions(id) ON DELETE CASCADE,
+    event_type VARCHAR(32) NOT NULL,
+    payload TEXT NOT NULL,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,26 @@
+-- Add migration script here
+ALTER TABLE chats ADD COLUMN topic VARCHAR(256);
+
+-- custom slash commands of a workspace, handled by a bot over http
+CREATE TABLE IF NOT EXISTS slash_commands (
+    id BIGSERIAL PRIMARY KEY,
+    workspace_id BIGINT NOT NULL,
+    bot_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR(32) NOT NULL,
+    description VARCHAR(256) NOT NULL DEFAULT '',
+    usage VARCHAR(128) NOT NULL DEFAULT '',
+    url VARCHAR(1024) NOT NULL,
+    -- used as hmac key, so it has to be stored in plain
+    secret VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (workspace_id, name)
+);
+
+-- muted chats of a user, muted_until NULL means muted until unmuted
+CREATE TABLE IF NOT EXISTS chat_mutes (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    muted_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
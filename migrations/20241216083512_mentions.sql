@@ -0,0 +1,33 @@
+-- Add migration script here
+CREATE TYPE mention_kind AS ENUM ('user', 'here', 'channel', 'group');
+
+CREATE TABLE IF NOT EXISTS mentions (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    -- the mentioned user
+    user_id BIGINT NOT NULL,
+    sender_id BIGINT NOT NULL,
+    kind mention_kind NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (message_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_mentions_user_id ON mentions (user_id, id DESC);
+
+-- notify the mentioned user, muted chats don't apply to mentions
+CREATE OR REPLACE FUNCTION add_to_mention()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE NOTICE 'add_to_mention: %', NEW;
+    PERFORM pg_notify('mention_created', json_build_object(
+        'mention', NEW,
+        'message', (SELECT row_to_json(m) FROM messages m WHERE m.id = NEW.message_id)
+    )::TEXT);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_mention_trigger
+AFTER INSERT ON mentions
+FOR EACH ROW EXECUTE FUNCTION add_to_mention();
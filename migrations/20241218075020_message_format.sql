@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TYPE message_format AS ENUM ('plain', 'markdown');
+
+ALTER TABLE messages ADD COLUMN format message_format NOT NULL DEFAULT 'plain';
+-- rendered content, safe to insert into a page
+ALTER TABLE messages ADD COLUMN html TEXT NOT NULL DEFAULT '';
+
+-- existing messages are plain text, escaped like chat_core::render_message does
+UPDATE messages SET html = replace(replace(replace(replace(replace(
+    content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;');
@@ -0,0 +1,29 @@
+-- Add migration script here
+-- metadata of uploaded files, the content is stored under its hash so
+-- several rows can share one url
+CREATE TABLE IF NOT EXISTS files (
+    id BIGSERIAL PRIMARY KEY,
+    workspace_id BIGINT NOT NULL,
+    uploader_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    mime VARCHAR(255) NOT NULL,
+    -- sha1 of the content
+    hash CHAR(40) NOT NULL,
+    url TEXT NOT NULL,
+    width INT,
+    height INT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_files_workspace_id ON files (workspace_id);
+
+-- files attached to a message, in the order they were sent
+CREATE TABLE IF NOT EXISTS message_files (
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    file_id BIGINT NOT NULL REFERENCES files(id),
+    position INT NOT NULL,
+    PRIMARY KEY (message_id, file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_files_file_id ON message_files (file_id);
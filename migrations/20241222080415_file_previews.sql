@@ -0,0 +1,3 @@
+-- Add migration script here
+-- set once the thumbnails of an image are generated
+ALTER TABLE files ADD COLUMN blurhash VARCHAR(64);
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- resumable uploads (tus) in progress, the received bytes are kept on disk
+-- until the upload is complete and stored like any other file
+CREATE TABLE IF NOT EXISTS uploads (
+    id VARCHAR(64) PRIMARY KEY,
+    workspace_id BIGINT NOT NULL,
+    uploader_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR(255) NOT NULL,
+    length BIGINT NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+    -- set once all bytes are received
+    file_id BIGINT REFERENCES files(id),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_uploads_expires_at ON uploads (expires_at);
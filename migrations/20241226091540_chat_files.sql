@@ -0,0 +1,18 @@
+-- Add migration script here
+-- files referenced by the messages of a chat, its members can download them
+CREATE TABLE IF NOT EXISTS chat_files (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    PRIMARY KEY (chat_id, url)
+);
+
+CREATE INDEX IF NOT EXISTS idx_chat_files_url ON chat_files (url);
+
+CREATE INDEX IF NOT EXISTS idx_files_url ON files (url);
+
+INSERT INTO chat_files (chat_id, url)
+SELECT DISTINCT m.chat_id, f.url
+FROM messages m
+JOIN chats c ON c.id = m.chat_id
+CROSS JOIN UNNEST(m.files) AS f(url)
+ON CONFLICT DO NOTHING;
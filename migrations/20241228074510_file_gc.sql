@@ -0,0 +1,27 @@
+-- Add migration script here
+-- file settings of a workspace, uploads are kept at least retention_days
+-- even when no message references them
+CREATE TABLE IF NOT EXISTS workspace_file_settings (
+    workspace_id BIGINT PRIMARY KEY REFERENCES workspaces(id),
+    retention_days INT
+);
+
+-- files under a legal hold are never collected, either all files of the
+-- workspace or only the uploads of one user
+CREATE TABLE IF NOT EXISTS legal_holds (
+    id BIGSERIAL PRIMARY KEY,
+    workspace_id BIGINT NOT NULL REFERENCES workspaces(id),
+    uploader_id BIGINT REFERENCES users(id),
+    reason TEXT NOT NULL,
+    created_by BIGINT NOT NULL REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_legal_holds_workspace_id ON legal_holds (workspace_id);
+
+CREATE INDEX IF NOT EXISTS idx_files_created_at ON files (created_at);
+
+-- a finished upload doesn't keep its file alive
+ALTER TABLE uploads DROP CONSTRAINT IF EXISTS uploads_file_id_fkey;
+ALTER TABLE uploads
+    ADD CONSTRAINT uploads_file_id_fkey FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE SET NULL;
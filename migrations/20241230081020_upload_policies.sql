@@ -0,0 +1,13 @@
+-- Add migration script here
+-- upload policies of a workspace, empty lists allow everything
+ALTER TABLE workspace_file_settings
+    ADD COLUMN quota_bytes BIGINT,
+    ADD COLUMN max_file_size BIGINT,
+    ADD COLUMN allowed_extensions TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN blocked_extensions TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN allowed_mime_types TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN blocked_mime_types TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN guest_uploads BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- guests can read and write messages but may not be allowed to upload
+ALTER TABLE users ADD COLUMN is_guest BOOLEAN NOT NULL DEFAULT FALSE;
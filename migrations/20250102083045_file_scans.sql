@@ -0,0 +1,8 @@
+-- Add migration script here
+-- files uploaded before scanning was added stay unscanned
+CREATE TYPE file_scan_status AS ENUM ('unscanned', 'clean', 'infected');
+
+ALTER TABLE files
+    ADD COLUMN scan_status file_scan_status NOT NULL DEFAULT 'unscanned',
+    -- the signature reported by the scanner
+    ADD COLUMN scan_result TEXT;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- new uploads are addressed by sha256, sha1 files are rehashed in the background
+ALTER TABLE files ALTER COLUMN hash TYPE VARCHAR(64);
+
+CREATE INDEX IF NOT EXISTS idx_files_sha1_url ON files (url) WHERE length(hash) = 40;
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- set by clients so retried sends don't create duplicates
+ALTER TABLE messages ADD COLUMN client_msg_id VARCHAR(64);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_client_msg_id
+    ON messages (chat_id, sender_id, client_msg_id)
+    WHERE client_msg_id IS NOT NULL;
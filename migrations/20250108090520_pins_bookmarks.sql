@@ -0,0 +1,75 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS pinned_messages (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    pinned_by BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, message_id)
+);
+
+-- links or files shown in the header of a chat
+CREATE TABLE IF NOT EXISTS chat_bookmarks (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    title VARCHAR(128) NOT NULL,
+    link TEXT,
+    file_url TEXT,
+    created_by BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((link IS NULL) <> (file_url IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS idx_chat_bookmarks_chat_id ON chat_bookmarks (chat_id, id);
+
+CREATE INDEX IF NOT EXISTS idx_chat_bookmarks_file_url ON chat_bookmarks (file_url);
+
+-- notify the members of the chat, so pins and bookmarks stay in sync
+CREATE OR REPLACE FUNCTION notify_pinned_message()
+RETURNS TRIGGER AS $$
+DECLARE
+  USERS bigint[];
+  PIN pinned_messages;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PIN := OLD;
+    ELSE
+        PIN := NEW;
+    END IF;
+    SELECT members INTO USERS FROM chats WHERE id = PIN.chat_id;
+    PERFORM pg_notify('chat_pin_updated', json_build_object(
+        'op', TG_OP,
+        'pin', PIN,
+        'members', COALESCE(USERS, '{}')
+    )::TEXT);
+    RETURN PIN;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_pinned_message_trigger
+AFTER INSERT OR DELETE ON pinned_messages
+FOR EACH ROW EXECUTE FUNCTION notify_pinned_message();
+
+CREATE OR REPLACE FUNCTION notify_chat_bookmark()
+RETURNS TRIGGER AS $$
+DECLARE
+  USERS bigint[];
+  BOOKMARK chat_bookmarks;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        BOOKMARK := OLD;
+    ELSE
+        BOOKMARK := NEW;
+    END IF;
+    SELECT members INTO USERS FROM chats WHERE id = BOOKMARK.chat_id;
+    PERFORM pg_notify('chat_bookmark_updated', json_build_object(
+        'op', TG_OP,
+        'bookmark', BOOKMARK,
+        'members', COALESCE(USERS, '{}')
+    )::TEXT);
+    RETURN BOOKMARK;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_chat_bookmark_trigger
+AFTER INSERT OR UPDATE OR DELETE ON chat_bookmarks
+FOR EACH ROW EXECUTE FUNCTION notify_chat_bookmark();
@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL REFERENCES users(id),
+    content TEXT NOT NULL,
+    format message_format NOT NULL DEFAULT 'plain',
+    -- file ids and legacy file urls, in the order they are attached
+    files TEXT[] NOT NULL DEFAULT '{}',
+    send_at TIMESTAMPTZ NOT NULL,
+    -- why it couldn't be sent, it isn't retried until edited
+    error TEXT,
+    message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    sent_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_due ON scheduled_messages (send_at)
+WHERE sent_at IS NULL AND error IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_sender_id ON scheduled_messages (sender_id, send_at)
+WHERE sent_at IS NULL;
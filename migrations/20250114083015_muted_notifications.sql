@@ -0,0 +1,25 @@
+-- Add migration script here
+-- members who muted the chat don't get its messages, the sender still gets
+-- their own message. Mentions are sent even if the chat is muted
+CREATE OR REPLACE FUNCTION add_to_message()
+RETURNS TRIGGER AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        SELECT
+        array_agg(u) INTO USERS
+        FROM chats c, unnest(c.members) AS u
+        WHERE
+        c.id = NEW.chat_id
+        AND (u = NEW.sender_id OR NOT EXISTS (
+            SELECT 1 FROM chat_mutes cm
+            WHERE cm.chat_id = NEW.chat_id AND cm.user_id = u
+            AND (cm.muted_until IS NULL OR cm.muted_until > now())
+        ));
+        PERFORM pg_notify('chat_message_created', json_build_object('message', NEW, 'members', COALESCE(USERS, '{}'))::TEXT);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- the reminder bot is found by id, its name can't be claimed by a signup
+ALTER TABLE workspaces ADD COLUMN reminder_bot_id BIGINT REFERENCES users(id);
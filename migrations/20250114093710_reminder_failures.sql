@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE reminders ADD COLUMN attempts INT NOT NULL DEFAULT 0;
+-- why it couldn't be delivered, it isn't retried
+ALTER TABLE reminders ADD COLUMN error TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_reminders_due ON reminders (remind_at)
+WHERE delivered_at IS NULL AND error IS NULL;
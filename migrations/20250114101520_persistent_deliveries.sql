@@ -0,0 +1,12 @@
+-- Add migration script here
+-- retries are polled by notify_server, so they survive a restart
+ALTER TABLE event_deliveries ADD COLUMN payload TEXT;
+ALTER TABLE event_deliveries ADD COLUMN next_attempt_at TIMESTAMPTZ;
+
+-- retries in memory before this can't be resumed, they have no payload
+UPDATE event_deliveries
+SET status = 'failed', error = COALESCE(error, 'retry lost on restart')
+WHERE status = 'pending';
+
+CREATE INDEX IF NOT EXISTS idx_event_deliveries_due ON event_deliveries (next_attempt_at)
+WHERE status = 'pending';
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- messages failing with a database or storage error are retried a few times
+ALTER TABLE scheduled_messages ADD COLUMN attempts INT NOT NULL DEFAULT 0;
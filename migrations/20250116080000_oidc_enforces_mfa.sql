@@ -0,0 +1,4 @@
+-- Add migration script here
+-- the IdP asks for a second factor itself, so signing in through it skips
+-- the totp challenge
+ALTER TABLE oidc_providers ADD COLUMN enforces_mfa BOOLEAN NOT NULL DEFAULT FALSE;
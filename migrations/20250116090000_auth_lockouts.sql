@@ -0,0 +1,10 @@
+-- Add migration script here
+-- failed signins per account and client ip, shared by all servers
+CREATE TABLE IF NOT EXISTS auth_lockouts (
+    key VARCHAR(512) PRIMARY KEY,
+    failures INT NOT NULL DEFAULT 0,
+    -- lockouts so far, each one doubles the next
+    level INT NOT NULL DEFAULT 0,
+    locked_until TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub delivery: DeliveryConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +22,33 @@ pub struct ServerConfig {
     pub db_url: String,
 }
 
+/// Outgoing webhook deliveries of bot event subscriptions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryConfig {
+    pub max_attempts: u32,
+    // doubled after each failed attempt
+    pub base_delay_ms: u64,
+    pub timeout_secs: u64,
+    // how often retries that are due are looked for
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    5
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay_ms: 1000,
+            timeout_secs: 10,
+            poll_interval_secs: default_poll_interval_secs(),
+        }
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from /etc/config/notify.yml, or ./notify.yml, or from env CHAT_CONFIG
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use axum::http::{header::CONTENT_TYPE, StatusCode};
+use chat_core::{
+    resolve_public_addrs, sign_payload, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER,
+    TIMESTAMP_HEADER,
+};
+use reqwest::Url;
+use sqlx::FromRow;
+use tracing::{info, warn};
+use url::Host;
+
+use crate::{AppEvent, AppState};
+
+const DELIVERY_BATCH_SIZE: i64 = 100;
+// on top of the request timeout, before a claimed delivery can be retried
+const DELIVERY_LEASE_MARGIN_SECS: u64 = 30;
+
+#[derive(Debug, FromRow)]
+struct Subscription {
+    id: i64,
+    bot_id: i64,
+    url: String,
+    secret: String,
+    events: Vec<String>,
+}
+
+/// A delivery being attempted, with what's needed to send it
+#[derive(Debug, FromRow)]
+struct Delivery {
+    id: i64,
+    subscription_id: i64,
+    url: String,
+    secret: String,
+    event_type: String,
+    payload: String,
+    attempts: i32,
+}
+
+/// Deliver the event to the subscriptions of bots among the notified users
+pub(crate) async fn dispatch(state: AppState, user_ids: HashSet<u64>, event: Arc<AppEvent>) {
+    if let Err(e) = try_dispatch(state, user_ids, event).await {
+        warn!("Failed to dispatch event to subscriptions: {}", e);
+    }
+}
+
+async fn try_dispatch(state: AppState, user_ids: HashSet<u64>, event: Arc<AppEvent>) -> Result<()> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+    // only chat members are notified, so bots never see events of other chats
+    let user_ids: Vec<i64> = user_ids.into_iter().map(|id| id as i64).collect();
+    let subscriptions: Vec<Subscription> = sqlx::query_as(
+        r#"
+        SELECT id, bot_id, url, secret, events
+        FROM event_subscriptions
+        WHERE bot_id = ANY($1)
+        AND (cardinality(chat_ids) = 0 OR $2 = ANY(chat_ids))
+        "#,
+    )
+    .bind(&user_ids)
+    .bind(event.chat_id())
+    .fetch_all(&state.pool)
+    .await?;
+    if subscriptions.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::to_string(event.as_ref())?;
+    for subscription in subscriptions {
+        let Some(event_type) = subscription.event_type(&event) else {
+            continue;
+        };
+        // leased to the first attempt, retries are picked up by the retry worker
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO event_deliveries (subscription_id, event_type, payload, next_attempt_at)
+            VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+            RETURNING id
+            "#,
+        )
+        .bind(subscription.id)
+        .bind(event_type)
+        .bind(&payload)
+        .bind(lease_secs(&state))
+        .fetch_one(&state.pool)
+        .await?;
+        let delivery = Delivery {
+            id,
+            subscription_id: subscription.id,
+            url: subscription.url,
+            secret: subscription.secret,
+            event_type: event_type.to_string(),
+            payload: payload.clone(),
+            attempts: 0,
+        };
+        tokio::spawn(deliver(state.clone(), delivery));
+    }
+    Ok(())
+}
+
+/// Retry the deliveries that are due, from any notify_server since the
+/// retries are kept in the database
+pub(crate) fn spawn_retry_worker(state: AppState) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(state.config.delivery.poll_interval_secs);
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match due_deliveries(&state).await {
+                Ok(deliveries) => {
+                    for delivery in deliveries {
+                        tokio::spawn(deliver(state.clone(), delivery));
+                    }
+                }
+                Err(e) => warn!("Failed to load due deliveries: {}", e),
+            }
+        }
+    });
+}
+
+/// Claim the due deliveries, the lease keeps other servers from attempting
+/// them at the same time
+async fn due_deliveries(state: &AppState) -> Result<Vec<Delivery>> {
+    let deliveries = sqlx::query_as(
+        r#"
+        UPDATE event_deliveries d
+        SET next_attempt_at = now() + make_interval(secs => $1)
+        FROM event_subscriptions s
+        WHERE s.id = d.subscription_id AND d.id IN (
+            SELECT id
+            FROM event_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= now() AND payload IS NOT NULL
+            ORDER BY next_attempt_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING d.id, d.subscription_id, s.url, s.secret, d.event_type, d.payload, d.attempts
+        "#,
+    )
+    .bind(lease_secs(state))
+    .bind(DELIVERY_BATCH_SIZE)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(deliveries)
+}
+
+async fn deliver(state: AppState, delivery: Delivery) {
+    if let Err(e) = try_deliver(&state, &delivery).await {
+        warn!("Failed to record delivery {}: {}", delivery.id, e);
+    }
+}
+
+/// Make one attempt, recorded in the delivery log. A failed attempt is
+/// scheduled again with a backoff until it runs out of attempts.
+async fn try_deliver(state: &AppState, delivery: &Delivery) -> Result<()> {
+    let config = &state.config.delivery;
+    let attempts = delivery.attempts as u32 + 1;
+    let (response_status, error) = match send(state, delivery).await {
+        Ok(status) if status.is_success() => (Some(status.as_u16() as i32), None),
+        Ok(status) => (
+            Some(status.as_u16() as i32),
+            Some(format!("unexpected status {}", status)),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    let status = match &error {
+        None => "delivered",
+        Some(_) if attempts >= config.max_attempts => "failed",
+        Some(_) => "pending",
+    };
+    // the payload is only kept while the delivery can be retried
+    sqlx::query(
+        r#"
+        UPDATE event_deliveries
+        SET status = $2::delivery_status, attempts = $3, response_status = $4, error = $5,
+            next_attempt_at = CASE WHEN $2 = 'pending'
+                THEN now() + make_interval(secs => $6) END,
+            payload = CASE WHEN $2 = 'pending' THEN payload END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        "#,
+    )
+    .bind(delivery.id)
+    .bind(status)
+    .bind(attempts as i32)
+    .bind(response_status)
+    .bind(&error)
+    .bind(backoff(config.base_delay_ms, attempts).as_secs_f64())
+    .execute(&state.pool)
+    .await?;
+
+    match status {
+        "delivered" => info!(
+            "Delivered {} to subscription {}",
+            delivery.event_type, delivery.subscription_id
+        ),
+        "failed" => {
+            warn!(
+                "Giving up delivery {} after {} attempts: {:?}",
+                delivery.id, attempts, error
+            );
+            sqlx::query(
+                r#"
+                INSERT INTO event_dead_letters (delivery_id, subscription_id, event_type, payload, error)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+            )
+            .bind(delivery.id)
+            .bind(delivery.subscription_id)
+            .bind(&delivery.event_type)
+            .bind(&delivery.payload)
+            .bind(&error)
+            .execute(&state.pool)
+            .await?;
+        }
+        _ => info!(
+            "Delivery {} failed, attempt {} of {}: {:?}",
+            delivery.id, attempts, config.max_attempts, error
+        ),
+    }
+    Ok(())
+}
+
+async fn send(state: &AppState, delivery: &Delivery) -> Result<StatusCode> {
+    // host names are checked by the resolver of the client, which also
+    // covers a name that resolves differently since the subscription was made
+    let url = Url::parse(&delivery.url)?;
+    if let Some(Host::Ipv4(_) | Host::Ipv6(_)) = url.host() {
+        let host = url.host_str().unwrap_or_default();
+        resolve_public_addrs(host, url.port_or_known_default().unwrap_or_default()).await?;
+    }
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let signature = sign_payload(&delivery.secret, timestamp, &delivery.payload);
+    let res = state
+        .http
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event_type)
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(SIGNATURE_HEADER, signature)
+        .body(delivery.payload.clone())
+        .send()
+        .await?;
+    Ok(res.status())
+}
+
+fn lease_secs(state: &AppState) -> f64 {
+    (state.config.delivery.timeout_secs + DELIVERY_LEASE_MARGIN_SECS) as f64
+}
+
+fn backoff(base_delay_ms: u64, attempts: u32) -> Duration {
+    let factor = 1u64 << attempts.saturating_sub(1).min(16);
+    Duration::from_millis(base_delay_ms.saturating_mul(factor))
+}
+
+impl Subscription {
+    /// The event type this subscription receives the event as, if any
+    fn event_type(&self, event: &AppEvent) -> Option<&'static str> {
+        // never echo the bot's own messages back to it
+        if let AppEvent::NewMessage(message) = event {
+            if message.sender_id == self.bot_id {
+                return None;
+            }
+        }
+        let name = event.name();
+        self.events.iter().any(|e| e == name).then_some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::notif::MentionCreated;
+    use chat_core::{Mention, MentionKind, Message, MessageFormat};
+    use chrono::Utc;
+
+    fn subscription(events: &[&str]) -> Subscription {
+        Subscription {
+            id: 1,
+            bot_id: 10,
+            url: "http://localhost/hook".to_string(),
+            secret: "secret".to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    fn message(sender_id: i64) -> Message {
+        Message {
+            id: 1,
+            chat_id: 1,
+            sender_id,
+            sender_name: None,
+            content: "hi @ci-bot".to_string(),
+            format: MessageFormat::Plain,
+            html: "hi @ci-bot".to_string(),
+            files: vec![],
+            attachments: vec![],
+            client_msg_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn mention(message: Message) -> AppEvent {
+        let mention = Mention {
+            id: 1,
+            message_id: message.id,
+            chat_id: message.chat_id,
+            user_id: 10,
+            sender_id: message.sender_id,
+            kind: MentionKind::User,
+            created_at: Utc::now(),
+        };
+        AppEvent::Mention(MentionCreated { mention, message })
+    }
+
+    #[test]
+    fn subscription_event_type_should_work() {
+        let sub = subscription(&["NewMessage", "Mention"]);
+        assert_eq!(
+            sub.event_type(&AppEvent::NewMessage(message(1))),
+            Some("NewMessage")
+        );
+        assert_eq!(sub.event_type(&mention(message(1))), Some("Mention"));
+        assert_eq!(sub.event_type(&AppEvent::NewMessage(message(10))), None);
+
+        let sub = subscription(&["Mention"]);
+        assert_eq!(sub.event_type(&AppEvent::NewMessage(message(1))), None);
+        assert_eq!(sub.event_type(&mention(message(1))), Some("Mention"));
+    }
+
+    #[test]
+    fn backoff_should_work() {
+        assert_eq!(backoff(1000, 1), Duration::from_secs(1));
+        assert_eq!(backoff(1000, 4), Duration::from_secs(8));
+    }
+}
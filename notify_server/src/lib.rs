@@ -1,9 +1,10 @@
 mod config;
+mod delivery;
 mod error;
 mod notif;
 mod sse;
 
-use std::{ops::Deref, sync::Arc};
+use std::{ops::Deref, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use axum::{
@@ -14,15 +15,16 @@ use axum::{
 };
 use chat_core::{
     middlewares::{verify_token, TokenVerify},
-    DecodingKey,
+    DecodingKey, PublicResolver,
 };
 use dashmap::DashMap;
+use sqlx::PgPool;
 use sse::sse_handler;
 use tokio::sync::broadcast;
 
 pub use config::AppConfig;
 pub use error::AppError;
-pub use notif::AppEvent;
+pub use notif::{AppEvent, MentionCreated};
 
 pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
 
@@ -32,6 +34,8 @@ pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
     dk: DecodingKey,
+    pool: PgPool,
+    http: reqwest::Client,
 }
 
 const INDEX_HTML: &str = include_str!("../index.html");
@@ -39,6 +43,7 @@ const INDEX_HTML: &str = include_str!("../index.html");
 pub async fn get_router(config: AppConfig) -> Result<Router> {
     let state = AppState::new(config);
     notif::setup_pg_listener(state.clone()).await?;
+    delivery::spawn_retry_worker(state.clone());
 
     let app = Router::new()
         .route("/events", get(sse_handler))
@@ -56,7 +61,7 @@ async fn index_handler() -> impl IntoResponse {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> std::result::Result<chat_core::User, Self::Error> {
+    async fn verify(&self, token: &str) -> std::result::Result<chat_core::User, Self::Error> {
         Ok(self.dk.verify(token)?)
     }
 }
@@ -73,6 +78,20 @@ impl AppState {
     pub fn new(config: AppConfig) -> Self {
         let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load public key");
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, dk, users }))
+        let pool = PgPool::connect_lazy(&config.server.db_url).expect("Failed to parse db url");
+        // deliveries only reach public addresses, redirects could point elsewhere
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.delivery.timeout_secs))
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build http client");
+        Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            users,
+            pool,
+            http,
+        }))
     }
 }
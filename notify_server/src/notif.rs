@@ -1,8 +1,8 @@
 use std::{collections::HashSet, sync::Arc};
 
-use crate::AppState;
+use crate::{delivery, AppState, UserMap};
 use anyhow::Result;
-use chat_core::{Chat, Message};
+use chat_core::{Bookmark, Chat, Mention, MentionKind, Message, Pin};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
@@ -15,6 +15,50 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    Mention(MentionCreated),
+    MessagePinned(Pin),
+    MessageUnpinned(Pin),
+    BookmarkAdded(Bookmark),
+    BookmarkUpdated(Bookmark),
+    BookmarkRemoved(Bookmark),
+}
+
+/// Sent to the mentioned user only, even if the chat is muted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MentionCreated {
+    pub mention: Mention,
+    pub message: Message,
+}
+
+impl AppEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::NewChat(_) => "NewChat",
+            AppEvent::AddToChat(_) => "AddToChat",
+            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+            AppEvent::NewMessage(_) => "NewMessage",
+            AppEvent::Mention(_) => "Mention",
+            AppEvent::MessagePinned(_) => "MessagePinned",
+            AppEvent::MessageUnpinned(_) => "MessageUnpinned",
+            AppEvent::BookmarkAdded(_) => "BookmarkAdded",
+            AppEvent::BookmarkUpdated(_) => "BookmarkUpdated",
+            AppEvent::BookmarkRemoved(_) => "BookmarkRemoved",
+        }
+    }
+
+    pub fn chat_id(&self) -> i64 {
+        match self {
+            AppEvent::NewChat(chat)
+            | AppEvent::AddToChat(chat)
+            | AppEvent::RemoveFromChat(chat) => chat.id,
+            AppEvent::NewMessage(message) => message.chat_id,
+            AppEvent::Mention(mention) => mention.mention.chat_id,
+            AppEvent::MessagePinned(pin) | AppEvent::MessageUnpinned(pin) => pin.chat_id,
+            AppEvent::BookmarkAdded(bookmark)
+            | AppEvent::BookmarkUpdated(bookmark)
+            | AppEvent::BookmarkRemoved(bookmark) => bookmark.chat_id,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -36,19 +80,43 @@ struct ChatMessageCreated {
     members: Vec<i64>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatPinUpdated {
+    op: String,
+    pin: Pin,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatBookmarkUpdated {
+    op: String,
+    bookmark: Bookmark,
+    members: Vec<i64>,
+}
+
 pub async fn setup_pg_listener(state: AppState) -> Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url)
         .await
         .expect("Failed to connect to database");
     listener.listen("chat_updated").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("mention_created").await?;
+    listener.listen("chat_pin_updated").await?;
+    listener.listen("chat_bookmark_updated").await?;
 
     let mut stream = listener.into_stream();
 
     tokio::spawn(async move {
         while let Some(Ok(notif)) = stream.next().await {
             info!("Received notification: {:?}", notif);
-            let notification = Notification::load(notif.channel(), notif.payload())?;
+            let mut notification = Notification::load(notif.channel(), notif.payload())?;
+            notification.retain_online(&state.users);
+            // bots subscribed to the event get it over http instead of sse
+            tokio::spawn(delivery::dispatch(
+                state.clone(),
+                notification.user_ids.clone(),
+                notification.event.clone(),
+            ));
             let users = &state.users;
             for user_id in notification.user_ids {
                 if let Some(tx) = users.get(&user_id) {
@@ -66,6 +134,16 @@ pub async fn setup_pg_listener(state: AppState) -> Result<()> {
 }
 
 impl Notification {
+    /// @here only reaches the members connected when it's sent, it's stored
+    /// for every member by chat_server, which doesn't know who is online
+    fn retain_online(&mut self, users: &UserMap) {
+        if let AppEvent::Mention(payload) = &*self.event {
+            if payload.mention.kind == MentionKind::Here {
+                self.user_ids.retain(|id| users.contains_key(id));
+            }
+        }
+    }
+
     fn load(r#type: &str, payload: &str) -> anyhow::Result<Self> {
         match r#type {
             "chat_updated" => {
@@ -92,6 +170,41 @@ impl Notification {
                     event: Arc::new(AppEvent::NewMessage(payload.message)),
                 })
             }
+            "mention_created" => {
+                let payload: MentionCreated = serde_json::from_str(payload)?;
+                let user_ids = HashSet::from([payload.mention.user_id as u64]);
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::Mention(payload)),
+                })
+            }
+            "chat_pin_updated" => {
+                let payload: ChatPinUpdated = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = match payload.op.as_str() {
+                    "INSERT" => AppEvent::MessagePinned(payload.pin),
+                    "DELETE" => AppEvent::MessageUnpinned(payload.pin),
+                    _ => return Err(anyhow::anyhow!("Invalid operation")),
+                };
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
+            "chat_bookmark_updated" => {
+                let payload: ChatBookmarkUpdated = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = match payload.op.as_str() {
+                    "INSERT" => AppEvent::BookmarkAdded(payload.bookmark),
+                    "UPDATE" => AppEvent::BookmarkUpdated(payload.bookmark),
+                    "DELETE" => AppEvent::BookmarkRemoved(payload.bookmark),
+                    _ => return Err(anyhow::anyhow!("Invalid operation")),
+                };
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
             _ => Err(anyhow::anyhow!("Invalid notification type")),
         }
     }
@@ -114,3 +227,58 @@ fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet
         _ => HashSet::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chat_core::MessageFormat;
+    use chrono::Utc;
+    use dashmap::DashMap;
+    use tokio::sync::broadcast;
+
+    use super::*;
+
+    fn mention(user_id: i64, kind: MentionKind) -> Notification {
+        let message = Message {
+            id: 1,
+            chat_id: 1,
+            sender_id: 1,
+            sender_name: None,
+            content: "standup @here".to_string(),
+            format: MessageFormat::Plain,
+            html: "standup @here".to_string(),
+            files: vec![],
+            attachments: vec![],
+            client_msg_id: None,
+            created_at: Utc::now(),
+        };
+        let mention = Mention {
+            id: 1,
+            message_id: 1,
+            chat_id: 1,
+            user_id,
+            sender_id: 1,
+            kind,
+            created_at: Utc::now(),
+        };
+        Notification {
+            user_ids: HashSet::from([user_id as u64]),
+            event: Arc::new(AppEvent::Mention(MentionCreated { mention, message })),
+        }
+    }
+
+    #[test]
+    fn here_mentions_should_only_reach_online_users() {
+        let users: UserMap = Arc::new(DashMap::new());
+        users.insert(2, broadcast::channel(1).0);
+
+        let mut online = mention(2, MentionKind::Here);
+        online.retain_online(&users);
+        assert_eq!(online.user_ids, HashSet::from([2]));
+        let mut offline = mention(3, MentionKind::Here);
+        offline.retain_online(&users);
+        assert!(offline.user_ids.is_empty());
+        let mut channel = mention(3, MentionKind::Channel);
+        channel.retain_online(&users);
+        assert_eq!(channel.user_ids, HashSet::from([3]));
+    }
+}
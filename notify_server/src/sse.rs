@@ -14,7 +14,7 @@ use tokio::sync::broadcast;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tracing::debug;
 
-use crate::{AppEvent, AppState};
+use crate::AppState;
 
 const CHANNEL_CAPACITY: usize = 256;
 
@@ -34,12 +34,7 @@ pub(crate) async fn sse_handler(
     };
 
     let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-        };
+        let name = v.name();
 
         let v = serde_json::to_string(&v).expect("Failed to serialize event");
         debug!("Sending event {}: {:?}", name, v);